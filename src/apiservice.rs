@@ -2,10 +2,13 @@
 
 use std::{collections::HashMap, sync::Arc};
 
-use mediawiki::{api::Api, media_wiki_error::MediaWikiError, title::Title};
+use async_trait::async_trait;
+use mediawiki::{api::{Api, NamespaceID}, media_wiki_error::MediaWikiError, title::Title};
 use serde_json::Value;
 use tokio::{sync::{Mutex, RwLock}, task::JoinHandle};
 use tracing::{event, Level, span, Instrument, instrument};
+use crate::backend::WikiBackend;
+use crate::scheduler::Scheduler;
 use crate::types::{LoginCredential, SiteProfile};
 
 #[derive(Debug)]
@@ -13,6 +16,7 @@ pub enum APIServiceError {
     NoAPI,
     Client(MediaWikiError),
     Server(Value),
+    UnknownSite(String),
 }
 
 // impl std::error::Error for APIServiceError {}
@@ -30,6 +34,7 @@ impl core::fmt::Display for APIServiceError {
             Self::NoAPI => f.write_str("no API object present in the service"),
             Self::Client(e) => e.fmt(f),
             Self::Server(e) => e.fmt(f),
+            Self::UnknownSite(name) => f.write_fmt(format_args!("no site profile named \"{}\"", name)),
         }
     }
 }
@@ -40,7 +45,11 @@ pub struct APIService {
     profile: Mutex<Option<SiteProfile>>,
 
     api: RwLock<Option<Api>>,
-    network_lock: Arc<Mutex<()>>,
+    client: Mutex<Option<reqwest::Client>>,
+    scheduler: Scheduler,
+    // site profiles of the other wikis a query may refer to, and their services once used
+    sites: Mutex<HashMap<String, SiteProfile>>,
+    remotes: Mutex<HashMap<String, Arc<APIService>>>,
     csrf: RwLock<String>,
 
     keepalivehandle: Mutex<Option<JoinHandle<()>>>,
@@ -54,60 +63,52 @@ impl APIService {
             login: Mutex::new(None),
             profile: Mutex::new(None),
             api: RwLock::new(None),
-            network_lock: Arc::new(Mutex::new(())),
+            client: Mutex::new(None),
+            scheduler: Scheduler::new(),
+            sites: Mutex::new(HashMap::new()),
+            remotes: Mutex::new(HashMap::new()),
             csrf: RwLock::new("".to_string()),
             keepalivehandle: Mutex::new(None),
         }
     }
 
-    pub async fn setup(&self, login: LoginCredential, profile: SiteProfile) {
+    /// Sets the credential and the site. Without a credential the service stays anonymous and read-only.
+    pub async fn setup(&self, login: Option<LoginCredential>, profile: SiteProfile) {
         {
             let mut login_lock = self.login.lock().await;
-            *login_lock = Some(login);
+            *login_lock = login;
         }
+        self.scheduler.configure(&profile.throttle);
         {
             let mut profile_lock = self.profile.lock().await;
             *profile_lock = Some(profile);
         }
     }
 
-    /// Send a request via GET
-    pub async fn get(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
-        let api = self.api.read().await;
-        if let Some(api) = &*api {
-            let mut params = params.clone();
-            self.param_decorate(&mut params).await;
-            let resp = api.get_query_api_json(&params).await?;
-            if let Some(errobj) = resp.get("error") {
-                Err(APIServiceError::Server(errobj.clone()))
-            } else {
-                Ok(resp)
-            }
-        } else {
-            Err(APIServiceError::NoAPI)
-        }
+    /// Sends requests through `client` instead of a client of its own, so that services can share connections.
+    /// Takes effect when the API object is next created
+    pub async fn set_client(&self, client: reqwest::Client) {
+        let mut client_lock = self.client.lock().await;
+        *client_lock = Some(client);
     }
 
-    /// Send a request via GET
-    pub async fn get_limit(&self, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
-        let api = self.api.read().await;
-        if let Some(api) = &*api {
-            let mut params = params.clone();
-            self.param_decorate(&mut params).await;
-            let resp = api.get_query_api_json_limit(&params, max).await?;
-            if let Some(errobj) = resp.get("error") {
-                Err(APIServiceError::Server(errobj.clone()))
-            } else {
-                Ok(resp)
-            }
-        } else {
-            Err(APIServiceError::NoAPI)
-        }
+    /// Sets the site profiles that queries may refer to with `site()`
+    pub async fn set_sites(&self, sites: HashMap<String, SiteProfile>) {
+        let mut sites_lock = self.sites.lock().await;
+        *sites_lock = sites;
     }
 
-    /// Send a request via GET
-    pub async fn get_all(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
-        self.get_limit(params, None).await
+    /// Creates an API object, using the shared client if there is one
+    async fn new_api(&self, api_url: &str) -> Result<Api, MediaWikiError> {
+        let mut api_obj = Api::new(api_url).await?;
+        let client = {
+            let lock = self.client.lock().await;
+            lock.clone()
+        };
+        if let Some(client) = client {
+            *api_obj.client_mut() = client;
+        }
+        Ok(api_obj)
     }
 
     /// Send a request via POST
@@ -116,7 +117,10 @@ impl APIService {
         if let Some(api) = &*api {
             let mut params = params.to_owned();
             self.param_decorate(&mut params).await;
-            let resp = api.post_query_api_json(&params).await?;
+            let resp = {
+                let _permit = self.scheduler.edit().await;
+                api.post_query_api_json(&params).await?
+            };
             if let Some(errobj) = resp.get("error") {
                 Err(APIServiceError::Server(errobj.clone()))
             } else {
@@ -127,60 +131,6 @@ impl APIService {
         }
     }
 
-    pub async fn post_edit(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
-        // Add an bot edit flag to params, if it does not exist
-        let mut params = params.to_owned();
-        if !params.contains_key("bot") && self.profile.lock().await.as_ref().unwrap().botflag {
-            params.insert("bot".to_string(), "1".to_string());
-        }
-        self.post(&params).await
-    }
-
-    /// Get csrf token
-    pub async fn csrf(&self) -> String {
-        let self_csrf = self.csrf.read().await;
-        (*self_csrf).clone()
-    }
-
-    pub fn get_lock(&self) -> Arc<Mutex<()>> {
-        self.network_lock.clone()
-    }
-
-    /// Convert Title object to full pretty title
-    pub async fn full_pretty(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
-        let api = self.api.read().await;
-        if let Some(api) = &*api {
-            Ok(title.full_pretty(api))
-        } else {
-            Err(APIServiceError::NoAPI)
-        }
-    }
-
-    /// Convert Title object to namespace name
-    pub async fn namespace_name<'a>(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
-        let api = self.api.read().await;
-        if let Some(api) = &*api {
-            let name = title.namespace_name(api);
-            if let Some(name) = name {
-                Ok(Some(name.to_owned()))
-            } else {
-                Ok(None)
-            }
-        } else {
-            Err(APIServiceError::NoAPI)
-        }
-    }
-
-    /// Create a title from full name
-    pub async fn title_new_from_full(&self, title: &str) -> Result<Title, APIServiceError> {
-        let api = self.api.read().await;
-        if let Some(api) = &*api {
-            Ok(Title::new_from_full(title, api))
-        } else {
-            Err(APIServiceError::NoAPI)
-        }
-    }
-
     async fn param_decorate(&self, params: &mut HashMap<String, String>) {
         // Add a format to params, if it does not exist
         if !params.contains_key("format") {
@@ -199,43 +149,52 @@ impl APIService {
             let lock = self.profile.lock().await;
             lock.as_ref().unwrap().assert
         };
-        if !params.contains_key("assert") && user_assert.is_some() {
-            params.insert("assert".to_string(), user_assert.unwrap().to_string());
+        if let Some(user_assert) = user_assert {
+            if !params.contains_key("assert") {
+                params.insert("assert".to_string(), user_assert.to_string());
+            }
         }
-        // Add an assertuser to params, if it does not exist
+        // Add an assertuser to params, if it does not exist and we are logged in
         if !params.contains_key("assertuser") {
             // extract the part before @
             // notice that @ is in reserved username character list, so that there is no ordinary username that contains @
             let user_username = {
                 let lock = self.login.lock().await;
-                lock.as_ref().unwrap().username.clone()
+                lock.as_ref().map(|login| login.username.clone())
             };
-            params.insert("assertuser".to_string(), user_username.split('@').next().unwrap().to_string());
+            if let Some(user_username) = user_username {
+                params.insert("assertuser".to_string(), user_username.split('@').next().unwrap().to_string());
+            }
         }
     }
 
     #[instrument(target = "API Service", level = "info", name = "API initiator")]
-    pub async fn try_init(&'static self) {
-        _ = tokio::task::spawn_blocking(|| self.stop()).await;
+    pub async fn try_init(self: &Arc<Self>) {
+        let this = self.clone();
+        _ = tokio::task::spawn_blocking(move || this.stop()).await;
         event!(Level::INFO, "initiating API");
         // Try to initialize the API object...
         let api_url = {
             let lock = self.profile.lock().await;
             lock.as_ref().unwrap().api.clone()
         };
-        let (username, password) = {
+        let login = {
             let lock = self.login.lock().await;
-            (lock.as_ref().unwrap().username.clone(), lock.as_ref().unwrap().password.clone())
+            lock.clone()
         };
-        let api_obj = Api::new(&api_url).await;
+        let api_obj = self.new_api(&api_url).await;
         if let Ok(mut api_obj) = api_obj {
             api_obj.set_maxlag(Some(5));
             api_obj.set_max_retry_attempts(3);
-            api_obj.set_user_agent(format!("Page List Bot / via User:{}", username.split('@').next().unwrap()));
-            let _ = api_obj.login(&username, &password).await;
-            if let Ok(csrf) = api_obj.get_edit_token().await {
-                let mut self_csrf = self.csrf.write().await;
-                *self_csrf = csrf;
+            if let Some(LoginCredential { username, password }) = login {
+                api_obj.set_user_agent(format!("Page List Bot / via User:{}", username.split('@').next().unwrap()));
+                let _ = api_obj.login(&username, &password).await;
+                if let Ok(csrf) = api_obj.get_edit_token().await {
+                    let mut self_csrf = self.csrf.write().await;
+                    *self_csrf = csrf;
+                }
+            } else {
+                api_obj.set_user_agent("Page List Bot".to_string());
             }
             let mut api = self.api.write().await;
             *api = Some(api_obj);
@@ -245,17 +204,17 @@ impl APIService {
     }
 
     /// Starts the daemon process. This should only be called once
-    pub async fn start(&'static self) {
-        _ = tokio::task::spawn_blocking(|| self.stop()).await;
-        let handle = tokio::spawn(async {
+    pub async fn start(self: &Arc<Self>) {
+        let this = self.clone();
+        _ = tokio::task::spawn_blocking(move || this.stop()).await;
+        let this = self.clone();
+        let handle = tokio::spawn(async move {
             // API status checker runs every hour
             let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60 * 60));
             loop {
                 interval.tick().await;
                 event!(Level::INFO, "API checking start");
-                // Require a lock
-                let _ = self.network_lock.lock().await;
-                let mut api = self.api.write().await;
+                let mut api = this.api.write().await;
                 if let Some(api) = &mut *api {
                     // Tries to send a request to check for login status
                     let params = api.params_into(&[
@@ -263,27 +222,30 @@ impl APIService {
                         ("format", "json"),
                         ("formatversion", "2"),
                         ("assert", &{
-                            let lock = self.profile.lock().await;
+                            let lock = this.profile.lock().await;
                             lock.as_ref().unwrap().assert.unwrap().to_string()
                         }),
                         ("assertuser", &{
-                            let lock = self.login.lock().await;
+                            let lock = this.login.lock().await;
                             lock.as_ref().unwrap().username.split('@').next().unwrap().to_string()
                         }),
                     ]);
-                    let response = api.get_query_api_json(&params).await;
+                    let response = {
+                        let _permit = this.scheduler.read().await;
+                        api.get_query_api_json(&params).await
+                    };
                     // Do nothing if a general client-side problem occurs
                     if let Ok(response) = response {
                         if response["error"].as_object().is_some() {
                             event!(Level::INFO, "API expired, re-login");
                             // re-login
                             let (username, password) = {
-                                let lock = self.login.lock().await;
+                                let lock = this.login.lock().await;
                                 (lock.as_ref().unwrap().username.clone(), lock.as_ref().unwrap().password.clone())
                             };
                             let _ = api.login(&username, &password).await;
                             if let Ok(csrf) = api.get_edit_token().await {
-                                let mut self_csrf = self.csrf.write().await;
+                                let mut self_csrf = this.csrf.write().await;
                                 *self_csrf = csrf;
                             }
                         } else {
@@ -296,21 +258,21 @@ impl APIService {
                     event!(Level::INFO, "API not initiated, initiating");
                     // Try to initialize the API object...
                     let api_url = {
-                        let lock = self.profile.lock().await;
+                        let lock = this.profile.lock().await;
                         lock.as_ref().unwrap().api.clone()
                     };
                     let (username, password) = {
-                        let lock = self.login.lock().await;
+                        let lock = this.login.lock().await;
                         (lock.as_ref().unwrap().username.clone(), lock.as_ref().unwrap().password.clone())
                     };
-                    let api_obj = Api::new(&api_url).await;
+                    let api_obj = this.new_api(&api_url).await;
                     if let Ok(mut api_obj) = api_obj {
                         api_obj.set_maxlag(Some(5));
                         api_obj.set_max_retry_attempts(3);
                         api_obj.set_user_agent(format!("Page List Bot / via User:{}", username.split('@').next().unwrap()));
                         let _ = api_obj.login(&username, &password).await;
                         if let Ok(csrf) = api_obj.get_edit_token().await {
-                            let mut self_csrf = self.csrf.write().await;
+                            let mut self_csrf = this.csrf.write().await;
                             *self_csrf = csrf;
                         }
                         *api = Some(api_obj);
@@ -338,6 +300,259 @@ impl APIService {
 
 impl Drop for APIService {
     fn drop(&mut self) {
-        self.stop();
+        // `stop` cannot block here, the last reference may be dropped inside the runtime
+        if let Some(handle) = self.keepalivehandle.get_mut().take() {
+            handle.abort();
+        }
+    }
+}
+
+#[async_trait]
+impl WikiBackend for APIService {
+
+    /// Send a request via GET
+    async fn get(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        let api = self.api.read().await;
+        if let Some(api) = &*api {
+            let mut params = params.clone();
+            self.param_decorate(&mut params).await;
+            let resp = {
+                let _permit = self.scheduler.read().await;
+                api.get_query_api_json(&params).await?
+            };
+            if let Some(errobj) = resp.get("error") {
+                Err(APIServiceError::Server(errobj.clone()))
+            } else {
+                Ok(resp)
+            }
+        } else {
+            Err(APIServiceError::NoAPI)
+        }
+    }
+
+    /// Send a request via GET, following continuations.
+    /// Each continuation is scheduled on its own, so that other tasks can send requests in between
+    async fn get_limit(&self, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let api = self.api.read().await;
+        if let Some(api) = &*api {
+            let mut params = params.clone();
+            self.param_decorate(&mut params).await;
+            let mut result = Value::Null;
+            let mut count: usize = 0;
+            loop {
+                let mut resp = {
+                    let _permit = self.scheduler.read().await;
+                    api.get_query_api_json(&params).await?
+                };
+                if let Some(errobj) = resp.get("error") {
+                    return Err(APIServiceError::Server(errobj.clone()));
+                }
+                count += query_result_count(&resp);
+                let cont = resp.as_object_mut().and_then(|obj| obj.remove("continue"));
+                merge_json(&mut result, resp);
+                match cont {
+                    Some(Value::Object(cont)) if max.is_none_or(|max| count < max) => {
+                        for (k, v) in cont {
+                            let v = match v {
+                                Value::String(s) => s,
+                                v => v.to_string(),
+                            };
+                            params.insert(k, v);
+                        }
+                    },
+                    _ => break,
+                }
+            }
+            Ok(result)
+        } else {
+            Err(APIServiceError::NoAPI)
+        }
+    }
+
+    async fn post_edit(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        // Add an bot edit flag to params, if it does not exist
+        let mut params = params.to_owned();
+        if !params.contains_key("bot") && self.profile.lock().await.as_ref().unwrap().botflag {
+            params.insert("bot".to_string(), "1".to_string());
+        }
+        self.post(&params).await
+    }
+
+    /// Get csrf token
+    async fn csrf(&self) -> String {
+        let self_csrf = self.csrf.read().await;
+        (*self_csrf).clone()
+    }
+
+    /// Convert Title object to full pretty title
+    async fn full_pretty(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
+        let api = self.api.read().await;
+        if let Some(api) = &*api {
+            Ok(title.full_pretty(api))
+        } else {
+            Err(APIServiceError::NoAPI)
+        }
+    }
+
+    /// Convert Title object to namespace name
+    async fn namespace_name(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
+        let api = self.api.read().await;
+        if let Some(api) = &*api {
+            let name = title.namespace_name(api);
+            if let Some(name) = name {
+                Ok(Some(name.to_owned()))
+            } else {
+                Ok(None)
+            }
+        } else {
+            Err(APIServiceError::NoAPI)
+        }
+    }
+
+    /// Create a title from full name
+    async fn title_new_from_full(&self, title: &str) -> Result<Title, APIServiceError> {
+        let api = self.api.read().await;
+        if let Some(api) = &*api {
+            Ok(Title::new_from_full(title, api))
+        } else {
+            Err(APIServiceError::NoAPI)
+        }
+    }
+
+    /// Get the namespace names and aliases from the site information loaded with the API object
+    async fn namespace_names(&self) -> Result<Vec<(String, NamespaceID)>, APIServiceError> {
+        let api = self.api.read().await;
+        if let Some(api) = &*api {
+            let query = &api.get_site_info()["query"];
+            let mut names: Vec<(String, NamespaceID)> = Vec::new();
+            if let Some(namespaces) = query["namespaces"].as_object() {
+                for ns in namespaces.values() {
+                    if let Some(id) = ns["id"].as_i64() {
+                        // `*` holds the name in format version 1
+                        for key in ["name", "*", "canonical"] {
+                            if let Some(name) = ns[key].as_str() {
+                                names.push((name.to_owned(), id));
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(aliases) = query["namespacealiases"].as_array() {
+                for alias in aliases {
+                    if let (Some(id), Some(name)) = (alias["id"].as_i64(), alias["alias"].as_str().or_else(|| alias["*"].as_str())) {
+                        names.push((name.to_owned(), id));
+                    }
+                }
+            }
+            Ok(names)
+        } else {
+            Err(APIServiceError::NoAPI)
+        }
+    }
+
+    /// Get an anonymous, read-only service for another wiki. It is created on first use and kept afterwards
+    async fn site(&self, name: &str) -> Result<Arc<dyn WikiBackend>, APIServiceError> {
+        let mut remotes = self.remotes.lock().await;
+        if let Some(remote) = remotes.get(name).cloned() {
+            // retry if the wiki could not be reached last time
+            if remote.api.read().await.is_none() {
+                remote.try_init().await;
+            }
+            return Ok(remote.clone());
+        }
+        let sites = {
+            let lock = self.sites.lock().await;
+            lock.clone()
+        };
+        let mut profile = sites.get(name).cloned().ok_or_else(|| APIServiceError::UnknownSite(name.to_owned()))?;
+        profile.assert = None;
+        let client = {
+            let lock = self.client.lock().await;
+            lock.clone()
+        };
+        let remote = Arc::new(APIService::new());
+        if let Some(client) = client {
+            remote.set_client(client).await;
+        }
+        remote.set_sites(sites).await;
+        remote.setup(None, profile).await;
+        remote.try_init().await;
+        remotes.insert(name.to_owned(), remote.clone());
+        Ok(remote)
+    }
+
+}
+
+/// The number of results in a response, counted over the lists of the `query` object and the `results` lists of its modules
+fn query_result_count(resp: &Value) -> usize {
+    resp["query"].as_object()
+        .map(|query| query.values().map(|v| match v {
+            Value::Array(items) => items.len(),
+            Value::Object(module) => module.get("results").and_then(|r| r.as_array()).map_or(0, |r| r.len()),
+            _ => 0,
+        }).sum())
+        .unwrap_or(0)
+}
+
+/// Merges a continued response into the previous ones. Lists are concatenated, and objects are merged by key
+fn merge_json(into: &mut Value, from: Value) {
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (k, v) in from {
+                merge_json(into.entry(k).or_insert(Value::Null), v);
+            }
+        },
+        (Value::Array(into), Value::Array(from)) => into.extend(from),
+        (into, from) => *into = from,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn continued_responses_are_merged() {
+        let mut result = Value::Null;
+        merge_json(&mut result, json!({
+            "batchcomplete": false,
+            "query": {
+                "pages": [{ "title": "A", "links": [{ "title": "B" }] }],
+                "searchinfo": { "totalhits": 3 },
+            },
+        }));
+        merge_json(&mut result, json!({
+            "batchcomplete": true,
+            "query": {
+                "pages": [{ "title": "C" }],
+                "searchinfo": { "suggestion": "D" },
+                "normalized": [{ "from": "a", "to": "A" }],
+            },
+        }));
+        assert_eq!(result, json!({
+            "batchcomplete": true,
+            "query": {
+                "pages": [{ "title": "A", "links": [{ "title": "B" }] }, { "title": "C" }],
+                "searchinfo": { "totalhits": 3, "suggestion": "D" },
+                "normalized": [{ "from": "a", "to": "A" }],
+            },
+        }));
+    }
+
+    #[test]
+    fn results_are_counted() {
+        assert_eq!(query_result_count(&json!({ "batchcomplete": true })), 0);
+        assert_eq!(query_result_count(&json!({
+            "query": {
+                "pages": [{ "title": "A" }, { "title": "B" }],
+                "search": [{ "title": "C" }],
+                "searchinfo": { "totalhits": 3 },
+            },
+        })), 3);
+        // special pages keep their rows under `results`
+        assert_eq!(query_result_count(&json!({
+            "query": { "querypage": { "name": "Lonelypages", "results": [{ "title": "A" }, { "title": "B" }] } },
+        })), 2);
     }
 }
@@ -4,6 +4,7 @@ pub fn build_argparse() -> Command<'static> {
     Command::new("Page List Bot")
         .about("Generate a list of wiki pages based on numerous criteria and set operations")
         .version(crate_version!())
+        .subcommand_negates_reqs(true)
         .args(&[
             Arg::new("login")
                 .long("login")
@@ -17,8 +18,102 @@ pub fn build_argparse() -> Command<'static> {
                 .help("Path to the JSON file with the website's information"),
             Arg::new("profile")
                 .long("profile")
+                .required_unless_present("all-profiles")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .help("The site profiles in site information file to use, separated by commas or given several times"),
+            Arg::new("all-profiles")
+                .long("all-profiles")
+                .conflicts_with("profile")
+                .help("Use every site profile in site information file")
+        ])
+        .subcommand(build_query_subcommand())
+        .subcommand(build_check_subcommand())
+}
+
+fn build_query_subcommand() -> Command<'static> {
+    Command::new("query")
+        .about("Evaluate an expression once and print the resulting pages")
+        .args(&[
+            Arg::new("login")
+                .long("login")
+                .takes_value(true)
+                .help("Path to the JSON file with username and password. Read-only anonymous access is used if omitted"),
+            Arg::new("site")
+                .long("site")
+                .required_unless_present("dump")
+                .takes_value(true)
+                .help("Path to the JSON file with the website's information"),
+            Arg::new("profile")
+                .long("profile")
+                .required_unless_present("dump")
+                .takes_value(true)
+                .help("The specific site profile in site information file to use"),
+            Arg::new("dump")
+                .long("dump")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .use_value_delimiter(true)
+                .conflicts_with_all(&["login", "site", "profile"])
+                .help("Evaluate against database dumps instead of the wiki: a pages-articles XML dump, and SQL dumps of linktarget, pagelinks, templatelinks, categorylinks or redirect. Separated by commas or given several times"),
+            Arg::new("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(["plain", "json", "tsv"])
+                .default_value("plain")
+                .help("Output format"),
+            Arg::new("limit")
+                .long("limit")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("-1")
+                .help("Query limit of instructions without a `.limit()` constraint. Negative for no limit"),
+            Arg::new("fanout")
+                .long("fanout")
+                .takes_value(true)
+                .allow_hyphen_values(true)
+                .default_value("100")
+                .help("The maximum number of pages a unary instruction may run on. Negative for no limit"),
+            Arg::new("parallelism")
+                .long("parallelism")
+                .takes_value(true)
+                .default_value("4")
+                .help("The maximum number of instructions evaluated at the same time"),
+            Arg::new("expr")
+                .required(true)
+                .takes_value(true)
+                .help("The expression to evaluate"),
+        ])
+}
+
+fn build_check_subcommand() -> Command<'static> {
+    Command::new("check")
+        .about("Validate a task page or an on-site configuration page without running it")
+        .args(&[
+            Arg::new("kind")
+                .long("kind")
+                .takes_value(true)
+                .possible_values(["auto", "task", "config"])
+                .default_value("auto")
+                .help("What the page is. `auto` treats a page with `taskdir` as a configuration, and anything else as a task"),
+            Arg::new("login")
+                .long("login")
+                .takes_value(true)
+                .help("Path to the JSON file with username and password. Only used to fetch a page from the wiki"),
+            Arg::new("site")
+                .long("site")
+                .takes_value(true)
+                .requires("profile")
+                .help("Path to the JSON file with the website's information. Only needed to fetch a page from the wiki"),
+            Arg::new("profile")
+                .long("profile")
+                .takes_value(true)
+                .requires("site")
+                .help("The specific site profile in site information file to use. Only needed to fetch a page from the wiki"),
+            Arg::new("target")
                 .required(true)
                 .takes_value(true)
-                .help("The specific site profile in site information file to use")
+                .help("A local file, or the title of a page on the wiki"),
         ])
-}
\ No newline at end of file
+}
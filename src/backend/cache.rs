@@ -0,0 +1,427 @@
+//! A response cache in front of another backend.
+//!
+//! `CachedBackend` keeps the responses to read-only `action=query` requests for a time that depends on the modules
+//! the request uses (`generator`, `list`, `prop`, `meta`). Requests are keyed on their normalized parameters,
+//! so that the same query sent by two tasks hits the same entry. Entries can also be written to a directory,
+//! so that they survive restarts. Expired files are removed from the directory from time to time, and the
+//! directory can be capped to a number of files.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, atomic::{AtomicU64, Ordering}};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use md5::{Md5, Digest};
+use mediawiki::{api::NamespaceID, title::Title};
+use serde_json::{json, Value};
+use tokio::sync::RwLock;
+use tracing::{event, Level};
+
+use crate::apiservice::APIServiceError;
+use crate::types::CacheProfile;
+use super::WikiBackend;
+
+/// Parameters that do not change the content of a response
+const IGNORED_PARAMS: &[&str] = &["format", "formatversion", "utf8", "assert", "assertuser", "maxlag"];
+/// Parameters naming the modules of a request
+const MODULE_PARAMS: &[&str] = &["generator", "list", "prop", "meta"];
+/// Key of the TTL used by modules without their own
+const DEFAULT_TTL_KEY: &str = "default";
+/// Expired entries are purged from memory once the cache holds this many entries
+const PURGE_THRESHOLD: usize = 4096;
+/// Statistics are logged every this many lookups
+const STATS_INTERVAL: u64 = 100;
+/// The cache directory is purged every this many writes, starting with the first one
+const DISK_PURGE_INTERVAL: u64 = 256;
+
+#[derive(Debug, Clone)]
+struct Entry {
+    // seconds since unix epoch
+    expires: u64,
+    value: Value,
+}
+
+/// Hit and miss counts since the cache was created
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CacheStats {
+    hits: u64,
+    misses: u64,
+}
+
+pub struct CachedBackend {
+    inner: Arc<dyn WikiBackend>,
+    // module name -> TTL in seconds
+    ttl: HashMap<String, u64>,
+    path: Option<PathBuf>,
+    // most files kept in `path`
+    maxfiles: Option<usize>,
+    entries: RwLock<HashMap<String, Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    writes: AtomicU64,
+}
+
+impl CachedBackend {
+
+    /// The cache directory, if any, is created on the first write
+    pub fn new(inner: Arc<dyn WikiBackend>, profile: &CacheProfile) -> Self {
+        CachedBackend {
+            inner,
+            ttl: profile.ttl.clone(),
+            path: profile.path.as_ref().map(PathBuf::from),
+            maxfiles: profile.maxfiles,
+            entries: RwLock::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            writes: AtomicU64::new(0),
+        }
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats { hits: self.hits.load(Ordering::Relaxed), misses: self.misses.load(Ordering::Relaxed) }
+    }
+
+    /// How long the response to `params` may be kept. A request using several modules is kept as long as its shortest-lived module.
+    fn ttl_of(&self, params: &HashMap<String, String>) -> u64 {
+        if params.get("action").map(|a| a.as_str()) != Some("query") {
+            return 0;
+        }
+        let default_ttl = self.ttl.get(DEFAULT_TTL_KEY).copied().unwrap_or(0);
+        MODULE_PARAMS.iter()
+            .filter_map(|p| params.get(*p))
+            .flat_map(|modules| modules.split('|'))
+            .map(|module| self.ttl.get(module).copied().unwrap_or(default_ttl))
+            .min()
+            .unwrap_or(default_ttl)
+    }
+
+    async fn lookup(&self, key: &str) -> Option<Value> {
+        let now = now();
+        let cached = {
+            let entries = self.entries.read().await;
+            entries.get(key).filter(|e| e.expires > now).map(|e| e.value.clone())
+        };
+        let cached = match cached {
+            Some(value) => Some(value),
+            None => {
+                let entry = self.read_disk(key).await.filter(|e| e.expires > now);
+                if let Some(entry) = &entry {
+                    self.entries.write().await.insert(key.to_owned(), entry.clone());
+                }
+                entry.map(|e| e.value)
+            },
+        };
+        if cached.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        let CacheStats { hits, misses } = self.stats();
+        if (hits + misses) % STATS_INTERVAL == 0 {
+            event!(Level::INFO, hits, misses, "cache statistics");
+        }
+        cached
+    }
+
+    async fn store(&self, key: String, value: &Value, ttl: u64) {
+        let entry = Entry { expires: now() + ttl, value: value.clone() };
+        if self.writes.fetch_add(1, Ordering::Relaxed).is_multiple_of(DISK_PURGE_INTERVAL) {
+            self.purge_disk().await;
+        }
+        self.write_disk(&key, &entry).await;
+        let mut entries = self.entries.write().await;
+        if entries.len() >= PURGE_THRESHOLD {
+            let now = now();
+            entries.retain(|_, e| e.expires > now);
+        }
+        entries.insert(key, entry);
+    }
+
+    fn disk_path(&self, key: &str) -> Option<PathBuf> {
+        self.path.as_ref().map(|path| {
+            let mut hasher = Md5::new();
+            hasher.update(key);
+            path.join(format!("{}.json", hex::encode(hasher.finalize())))
+        })
+    }
+
+    async fn read_disk(&self, key: &str) -> Option<Entry> {
+        let stored = read_file(&self.disk_path(key)?).await?;
+        // a different key with the same hash is a miss
+        if stored["key"].as_str() != Some(key) {
+            return None;
+        }
+        Some(Entry { expires: stored["expires"].as_u64()?, value: stored["value"].clone() })
+    }
+
+    async fn write_disk(&self, key: &str, entry: &Entry) {
+        if let Some(path) = self.disk_path(key) {
+            let stored = json!({ "key": key, "expires": entry.expires, "value": entry.value });
+            if let Err(e) = tokio::fs::write(path, stored.to_string()).await {
+                event!(Level::WARN, error = ?e, "cannot write cache entry");
+            }
+        }
+    }
+
+    /// Creates the cache directory if needed, removes expired and unreadable files from it,
+    /// then the files closest to expiry until at most `maxfiles` are left
+    async fn purge_disk(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(e) = tokio::fs::create_dir_all(path).await {
+            event!(Level::WARN, error = ?e, "cannot create cache directory");
+            return;
+        }
+        let mut dir = match tokio::fs::read_dir(path).await {
+            Ok(dir) => dir,
+            Err(e) => {
+                event!(Level::WARN, error = ?e, "cannot read cache directory");
+                return;
+            },
+        };
+        let now = now();
+        let mut kept = Vec::new();
+        let mut removed = Vec::new();
+        while let Ok(Some(file)) = dir.next_entry().await {
+            let file = file.path();
+            if file.extension().and_then(|ext| ext.to_str()) == Some("json") {
+                match read_file(&file).await.and_then(|stored| stored["expires"].as_u64()) {
+                    Some(expires) if expires > now => kept.push((expires, file)),
+                    _ => removed.push(file),
+                }
+            }
+        }
+        if let Some(maxfiles) = self.maxfiles {
+            if kept.len() > maxfiles {
+                kept.sort_unstable();
+                removed.extend(kept.drain(..kept.len() - maxfiles).map(|(_, file)| file));
+            }
+        }
+        for file in &removed {
+            if let Err(e) = tokio::fs::remove_file(file).await {
+                event!(Level::WARN, error = ?e, "cannot remove cache entry");
+            }
+        }
+        event!(Level::DEBUG, kept = kept.len(), removed = removed.len(), "cache directory purged");
+    }
+
+    /// Sends a request through the cache. `method` tells apart requests that follow continuations differently.
+    async fn cached(&self, method: &str, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let ttl = self.ttl_of(params);
+        if ttl == 0 {
+            return self.fetch(method, params, max).await;
+        }
+        let key = cache_key(method, params);
+        if let Some(value) = self.lookup(&key).await {
+            return Ok(value);
+        }
+        let value = self.fetch(method, params, max).await?;
+        self.store(key, &value, ttl).await;
+        Ok(value)
+    }
+
+    async fn fetch(&self, method: &str, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        if method == "get" {
+            self.inner.get(params).await
+        } else {
+            self.inner.get_limit(params, max).await
+        }
+    }
+
+}
+
+#[async_trait]
+impl WikiBackend for CachedBackend {
+
+    async fn get(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        self.cached("get", params, None).await
+    }
+
+    async fn get_limit(&self, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let method = match max {
+            Some(max) => format!("limit={}", max),
+            None => String::from("all"),
+        };
+        self.cached(&method, params, max).await
+    }
+
+    async fn post_edit(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        self.inner.post_edit(params).await
+    }
+
+    async fn csrf(&self) -> String {
+        self.inner.csrf().await
+    }
+
+    async fn full_pretty(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
+        self.inner.full_pretty(title).await
+    }
+
+    async fn namespace_name(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
+        self.inner.namespace_name(title).await
+    }
+
+    async fn title_new_from_full(&self, title: &str) -> Result<Title, APIServiceError> {
+        self.inner.title_new_from_full(title).await
+    }
+
+    async fn namespace_names(&self) -> Result<Vec<(String, NamespaceID)>, APIServiceError> {
+        self.inner.namespace_names().await
+    }
+
+    async fn site(&self, name: &str) -> Result<Arc<dyn WikiBackend>, APIServiceError> {
+        self.inner.site(name).await
+    }
+
+    fn uncached(&self) -> Option<Arc<dyn WikiBackend>> {
+        Some(self.inner.clone())
+    }
+
+}
+
+/// Sorts parameters by name and multi-values by value, and drops those that do not affect the response
+fn cache_key(method: &str, params: &HashMap<String, String>) -> String {
+    let normalized: BTreeMap<&str, String> = params.iter()
+        .filter(|(k, _)| !IGNORED_PARAMS.contains(&k.as_str()))
+        .map(|(k, v)| {
+            let mut values: Vec<&str> = v.split('|').collect();
+            values.sort_unstable();
+            (k.as_str(), values.join("|"))
+        })
+        .collect();
+    let query: Vec<String> = normalized.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    format!("{}?{}", method, query.join("&"))
+}
+
+async fn read_file(path: &Path) -> Option<Value> {
+    let content = tokio::fs::read_to_string(path).await.ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use mediawiki::hashmap;
+
+    fn profile(path: Option<&Path>, maxfiles: Option<usize>) -> CacheProfile {
+        CacheProfile {
+            ttl: hashmap!["links".to_string() => 60],
+            path: path.map(|p| p.display().to_string()),
+            maxfiles,
+        }
+    }
+
+    fn wiki() -> Arc<dyn WikiBackend> {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_link("A", "B").add_link("A", "C");
+        Arc::new(wiki)
+    }
+
+    fn links_of(titles: &str) -> HashMap<String, String> {
+        hashmap![
+            "action".to_string() => "query".to_string(),
+            "generator".to_string() => "links".to_string(),
+            "titles".to_string() => titles.to_string()
+        ]
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("plbot-cache-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    fn files_in(path: &Path) -> usize {
+        std::fs::read_dir(path).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn second_request_hits() {
+        let cache = CachedBackend::new(wiki(), &profile(None, None));
+        let first = cache.get(&links_of("A")).await.unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 1 });
+        let second = cache.get(&links_of("A")).await.unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+        assert_eq!(first, second);
+    }
+
+    #[tokio::test]
+    async fn keys_are_normalized() {
+        let cache = CachedBackend::new(wiki(), &profile(None, None));
+        cache.get(&links_of("A|B")).await.unwrap();
+        let mut params = links_of("B|A");
+        params.insert("format".to_string(), "json".to_string());
+        cache.get(&params).await.unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+        // a different method is a different entry
+        cache.get_limit(&links_of("A|B"), Some(1)).await.unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+    }
+
+    #[tokio::test]
+    async fn modules_without_ttl_are_not_cached() {
+        let cache = CachedBackend::new(wiki(), &profile(None, None));
+        let mut params = links_of("B");
+        params.insert("generator".to_string(), "templates".to_string());
+        cache.get(&params).await.unwrap();
+        cache.get(&params).await.unwrap();
+        assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 0 });
+        // the shortest-lived module wins
+        params.insert("generator".to_string(), "links".to_string());
+        params.insert("prop".to_string(), "info".to_string());
+        assert_eq!(cache.ttl_of(&params), 0);
+    }
+
+    #[tokio::test]
+    async fn expired_entries_miss() {
+        let cache = CachedBackend::new(wiki(), &profile(None, None));
+        let key = cache_key("get", &links_of("A"));
+        cache.store(key.clone(), &json!({}), 0).await;
+        assert_eq!(cache.lookup(&key).await, None);
+        cache.store(key.clone(), &json!({}), 60).await;
+        assert_eq!(cache.lookup(&key).await, Some(json!({})));
+        assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 1 });
+    }
+
+    #[tokio::test]
+    async fn entries_survive_restarts() {
+        let path = temp_dir("restart");
+        let first = CachedBackend::new(wiki(), &profile(Some(&path), None));
+        let value = first.get(&links_of("A")).await.unwrap();
+        assert_eq!(files_in(&path), 1);
+        let second = CachedBackend::new(wiki(), &profile(Some(&path), None));
+        assert_eq!(second.get(&links_of("A")).await.unwrap(), value);
+        assert_eq!(second.stats(), CacheStats { hits: 1, misses: 0 });
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn directory_is_purged() {
+        let path = temp_dir("purge");
+        let cache = CachedBackend::new(wiki(), &profile(Some(&path), Some(2)));
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("garbage.json"), "{").unwrap();
+        std::fs::write(path.join("other.txt"), "").unwrap();
+        for (i, title) in ["A", "B", "C"].iter().enumerate() {
+            let key = cache_key("get", &links_of(title));
+            cache.write_disk(&key, &Entry { expires: now() + 60 * (i as u64 + 1), value: json!({}) }).await;
+        }
+        cache.write_disk("expired", &Entry { expires: now(), value: json!({}) }).await;
+        assert_eq!(files_in(&path), 6);
+        cache.purge_disk().await;
+        // the unreadable and expired files are removed, then "A", which expires first
+        assert_eq!(files_in(&path), 3);
+        assert!(path.join("other.txt").exists());
+        assert!(cache.read_disk(&cache_key("get", &links_of("A"))).await.is_none());
+        assert!(cache.read_disk(&cache_key("get", &links_of("C"))).await.is_some());
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,553 @@
+//! Loads a wiki from MediaWiki database dumps.
+//!
+//! `DumpLoader` reads a `pages-articles` XML dump and the SQL dumps of the link tables into a `MemoryBackend`,
+//! so that a query gives the same result offline as it does online.
+//! Load the XML dump first, then `linktarget` if the link tables refer to it, then the link tables,
+//! or let `load_files` pick the order.
+//! Rows pointing from pages missing in the XML dump are skipped.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use mediawiki::{api::NamespaceID, title::Title};
+use quick_xml::{events::{BytesStart, Event}, Reader};
+
+use super::MemoryBackend;
+
+const NS_CATEGORY: NamespaceID = 14;
+/// The tables `load_files` reads, in the order they must be loaded
+const SQL_TABLES: &[&str] = &["linktarget", "pagelinks", "templatelinks", "categorylinks", "redirect"];
+
+#[derive(Debug)]
+pub enum DumpError {
+    IO(std::io::Error),
+    Xml(quick_xml::Error),
+    Format(String),
+}
+
+impl From<std::io::Error> for DumpError {
+    fn from(e: std::io::Error) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<quick_xml::Error> for DumpError {
+    fn from(e: quick_xml::Error) -> Self {
+        Self::Xml(e)
+    }
+}
+
+impl std::fmt::Display for DumpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IO(e) => e.fmt(f),
+            Self::Xml(e) => e.fmt(f),
+            Self::Format(e) => f.write_fmt(format_args!("malformed dump: {}", e)),
+        }
+    }
+}
+
+/// A value in a row of an SQL dump
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum DumpValue {
+    Int(i64),
+    Text(String),
+    Null,
+}
+
+impl DumpValue {
+    fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(i) => Some(*i),
+            Self::Text(s) => s.parse().ok(),
+            Self::Null => None,
+        }
+    }
+
+    fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct DumpPage {
+    title: String,
+    ns: NamespaceID,
+    id: i64,
+    redirect: Option<String>,
+    content: String,
+}
+
+#[derive(Debug)]
+pub struct DumpLoader {
+    backend: MemoryBackend,
+    // lt_id -> target, from the `linktarget` dump
+    link_targets: HashMap<i64, Title>,
+}
+
+impl DumpLoader {
+
+    pub fn new() -> Self {
+        DumpLoader { backend: MemoryBackend::new(), link_targets: HashMap::new() }
+    }
+
+    /// Reads every dump of `paths`, in the order they must be loaded.
+    /// Files ending with `.xml` are page dumps, the others are SQL dumps, told apart by the table they create.
+    pub fn load_files(&mut self, paths: &[&Path]) -> Result<&mut Self, DumpError> {
+        let mut xml: Vec<&Path> = Vec::new();
+        let mut tables: Vec<(usize, &Path)> = Vec::new();
+        for path in paths {
+            if path.extension().map(|ext| ext == "xml").unwrap_or(false) {
+                xml.push(path);
+                continue;
+            }
+            let table = sql_table(path)?.ok_or_else(|| DumpError::Format(format!("no table in \"{}\"", path.display())))?;
+            let order = SQL_TABLES.iter().position(|t| *t == table).ok_or_else(|| DumpError::Format(format!("unsupported table \"{}\"", table)))?;
+            tables.push((order, path));
+        }
+        for path in xml {
+            self.load_pages(path)?;
+        }
+        tables.sort_by_key(|(order, _)| *order);
+        for (order, path) in tables {
+            match SQL_TABLES[order] {
+                "linktarget" => self.load_link_targets(path)?,
+                "pagelinks" => self.load_page_links(path)?,
+                "templatelinks" => self.load_template_links(path)?,
+                "categorylinks" => self.load_category_links(path)?,
+                _ => self.load_redirects(path)?,
+            };
+        }
+        Ok(self)
+    }
+
+    /// Reads namespaces and pages from a `pages-articles` (or `pages-meta-current`) XML dump.
+    /// Only the last revision of each page is kept.
+    pub fn load_pages(&mut self, path: &Path) -> Result<&mut Self, DumpError> {
+        let mut reader = Reader::from_reader(BufReader::new(File::open(path)?));
+        let mut buf: Vec<u8> = Vec::new();
+        // names of the open elements
+        let mut stack: Vec<Vec<u8>> = Vec::new();
+        let mut text = String::new();
+        let mut page: Option<DumpPage> = None;
+        let mut ns_key: Option<NamespaceID> = None;
+        loop {
+            match reader.read_event_into(&mut buf)? {
+                Event::Start(e) => {
+                    match e.name().as_ref() {
+                        b"page" => page = Some(DumpPage::default()),
+                        b"namespace" => ns_key = namespace_key(&e)?,
+                        _ => {},
+                    }
+                    stack.push(e.name().as_ref().to_vec());
+                    text.clear();
+                },
+                Event::Empty(e) => {
+                    match e.name().as_ref() {
+                        b"redirect" => {
+                            if let (Some(page), Some(target)) = (page.as_mut(), e.try_get_attribute("title")?) {
+                                page.redirect = Some(target.unescape_value()?.into_owned());
+                            }
+                        },
+                        b"namespace" => {
+                            if let Some(key) = namespace_key(&e)? {
+                                self.backend.add_namespace(key, "");
+                            }
+                        },
+                        _ => {},
+                    }
+                },
+                Event::Text(t) => text.push_str(&t.unescape()?),
+                Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t.into_inner())),
+                Event::End(_) => {
+                    let name = stack.pop().unwrap_or_default();
+                    let in_page = stack.last().map(|parent| parent.as_slice() == b"page").unwrap_or(false);
+                    match (name.as_slice(), page.as_mut()) {
+                        (b"namespace", _) => {
+                            if let Some(key) = ns_key.take() {
+                                self.backend.add_namespace(key, &text);
+                            }
+                        },
+                        (b"title", Some(page)) if in_page => page.title = std::mem::take(&mut text),
+                        (b"ns", Some(page)) if in_page => page.ns = parse_int(&text)?,
+                        (b"id", Some(page)) if in_page => page.id = parse_int(&text)?,
+                        (b"text", Some(page)) => page.content = std::mem::take(&mut text),
+                        (b"page", _) => {
+                            if let Some(page) = page.take() {
+                                self.insert_page(page);
+                            }
+                        },
+                        _ => {},
+                    }
+                    text.clear();
+                },
+                Event::Eof => break,
+                _ => {},
+            }
+            buf.clear();
+        }
+        Ok(self)
+    }
+
+    /// Reads the `linktarget` table, which newer `pagelinks` and `templatelinks` dumps refer to
+    pub fn load_link_targets(&mut self, path: &Path) -> Result<&mut Self, DumpError> {
+        let mut link_targets: HashMap<i64, Title> = HashMap::new();
+        read_sql_dump(path, &["lt_id", "lt_namespace", "lt_title"], |row| {
+            if let (Some(id), Some(ns), Some(title)) = (int_at(row, 0), int_at(row, 1), text_at(row, 2)) {
+                link_targets.insert(id, db_title(ns, title));
+            }
+        })?;
+        self.link_targets.extend(link_targets);
+        Ok(self)
+    }
+
+    /// Reads the `pagelinks` table
+    pub fn load_page_links(&mut self, path: &Path) -> Result<&mut Self, DumpError> {
+        self.load_links(path, "pl", MemoryBackend::insert_link)
+    }
+
+    /// Reads the `templatelinks` table
+    pub fn load_template_links(&mut self, path: &Path) -> Result<&mut Self, DumpError> {
+        self.load_links(path, "tl", MemoryBackend::insert_template)
+    }
+
+    /// Reads the `categorylinks` table
+    pub fn load_category_links(&mut self, path: &Path) -> Result<&mut Self, DumpError> {
+        let mut rows: Vec<(Title, Title)> = Vec::new();
+        read_sql_dump(path, &["cl_from", "cl_to", "cl_target_id"], |row| {
+            let from = int_at(row, 0).and_then(|id| self.backend.title_of(id));
+            let to = text_at(row, 1).map(|t| db_title(NS_CATEGORY, t))
+                .or_else(|| int_at(row, 2).and_then(|id| self.link_targets.get(&id).cloned()));
+            if let (Some(from), Some(to)) = (from, to) {
+                rows.push((from, to));
+            }
+        })?;
+        for (from, to) in rows {
+            self.backend.insert_category(from, to);
+        }
+        Ok(self)
+    }
+
+    /// Reads the `redirect` table. Redirects to other wikis are skipped.
+    /// The XML dump already marks redirects, this table only refines their targets.
+    pub fn load_redirects(&mut self, path: &Path) -> Result<&mut Self, DumpError> {
+        let mut rows: Vec<(Title, Title)> = Vec::new();
+        read_sql_dump(path, &["rd_from", "rd_namespace", "rd_title", "rd_interwiki"], |row| {
+            if row[3].as_ref().and_then(|v| v.as_text()).map(|iw| !iw.is_empty()).unwrap_or(false) {
+                return;
+            }
+            let from = int_at(row, 0).and_then(|id| self.backend.title_of(id));
+            if let (Some(from), Some(ns), Some(title)) = (from, int_at(row, 1), text_at(row, 2)) {
+                rows.push((from, db_title(ns, title)));
+            }
+        })?;
+        for (from, to) in rows {
+            self.backend.set_redirect(&from, to);
+        }
+        Ok(self)
+    }
+
+    pub fn finish(self) -> MemoryBackend {
+        self.backend
+    }
+
+    /// Reads a link table whose columns start with `prefix`.
+    /// The target is either `<prefix>_target_id` into `linktarget`, or `<prefix>_namespace` and `<prefix>_title`.
+    fn load_links(&mut self, path: &Path, prefix: &str, insert: fn(&mut MemoryBackend, Title, Title)) -> Result<&mut Self, DumpError> {
+        let columns: Vec<String> = ["from", "target_id", "namespace", "title"].iter().map(|c| format!("{}_{}", prefix, c)).collect();
+        let columns: Vec<&str> = columns.iter().map(|c| c.as_str()).collect();
+        let mut rows: Vec<(Title, Title)> = Vec::new();
+        read_sql_dump(path, &columns, |row| {
+            let from = int_at(row, 0).and_then(|id| self.backend.title_of(id));
+            let to = int_at(row, 1).and_then(|id| self.link_targets.get(&id).cloned())
+                .or_else(|| match (int_at(row, 2), text_at(row, 3)) {
+                    (Some(ns), Some(title)) => Some(db_title(ns, title)),
+                    _ => None,
+                });
+            if let (Some(from), Some(to)) = (from, to) {
+                rows.push((from, to));
+            }
+        })?;
+        for (from, to) in rows {
+            insert(&mut self.backend, from, to);
+        }
+        Ok(self)
+    }
+
+    fn insert_page(&mut self, page: DumpPage) {
+        // the title in the dump carries the namespace prefix
+        let name = if page.ns == 0 {
+            page.title.as_str()
+        } else {
+            page.title.split_once(':').map(|(_, rest)| rest).unwrap_or(&page.title)
+        };
+        let title = Title::new(name, page.ns);
+        let redirect = page.redirect.map(|target| self.backend.parse_title(&target));
+        self.backend.insert_page(page.id, title, page.content, redirect);
+    }
+
+}
+
+impl Default for DumpLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn namespace_key(e: &BytesStart) -> Result<Option<NamespaceID>, DumpError> {
+    match e.try_get_attribute("key")? {
+        Some(key) => Ok(Some(parse_int(&key.unescape_value()?)?)),
+        None => Ok(None),
+    }
+}
+
+fn parse_int(s: &str) -> Result<i64, DumpError> {
+    s.trim().parse().map_err(|_| DumpError::Format(format!("expect an integer, found \"{}\"", s)))
+}
+
+/// Titles in the database are stored with underscores and without namespace prefix
+fn db_title(ns: NamespaceID, title: &str) -> Title {
+    Title::new(&Title::underscores_to_spaces(title), ns)
+}
+
+fn int_at(row: &[Option<DumpValue>], idx: usize) -> Option<i64> {
+    row[idx].as_ref().and_then(|v| v.as_int())
+}
+
+fn text_at(row: &[Option<DumpValue>], idx: usize) -> Option<&str> {
+    row[idx].as_ref().and_then(|v| v.as_text())
+}
+
+/// Reads the rows of the table in a `mysqldump` file.
+///
+/// Column names are taken from the `CREATE TABLE` statement.
+/// For every row, `f` receives the values of `wanted`, in that order, with `None` for a column the table does not have.
+fn read_sql_dump(path: &Path, wanted: &[&str], mut f: impl FnMut(&[Option<DumpValue>])) -> Result<(), DumpError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line: Vec<u8> = Vec::new();
+    let mut in_create = false;
+    let mut columns: Vec<String> = Vec::new();
+    // position of every wanted column in a row
+    let mut positions: Vec<Option<usize>> = vec![None; wanted.len()];
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        let trimmed = line.trim_ascii();
+        if trimmed.starts_with(b"CREATE TABLE") {
+            in_create = true;
+            columns.clear();
+        } else if in_create {
+            if trimmed.starts_with(b")") {
+                in_create = false;
+                positions = wanted.iter().map(|w| columns.iter().position(|c| c == w)).collect();
+            } else if let Some(rest) = trimmed.strip_prefix(b"`") {
+                if let Some(end) = rest.iter().position(|b| *b == b'`') {
+                    columns.push(String::from_utf8_lossy(&rest[..end]).into_owned());
+                }
+            }
+        } else if trimmed.starts_with(b"INSERT INTO") {
+            let start = find(trimmed, b" VALUES ").ok_or_else(|| DumpError::Format("INSERT without VALUES".to_string()))?;
+            parse_rows(&trimmed[start + b" VALUES ".len()..], |row| {
+                let picked: Vec<Option<DumpValue>> = positions.iter().map(|p| p.and_then(|p| row.get(p).cloned())).collect();
+                f(&picked);
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// The name of the table created by a `mysqldump` file, if any
+fn sql_table(path: &Path) -> Result<Option<String>, DumpError> {
+    let reader = BufReader::new(File::open(path)?);
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if let Some(rest) = line.trim_ascii().strip_prefix(b"CREATE TABLE") {
+            let name: Vec<u8> = rest.iter().copied().skip_while(|c| *c == b' ' || *c == b'`').take_while(|c| *c != b'`' && *c != b' ').collect();
+            return Ok(Some(String::from_utf8_lossy(&name).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Parses `(v, ...),(v, ...);`
+fn parse_rows(s: &[u8], mut f: impl FnMut(Vec<DumpValue>)) -> Result<(), DumpError> {
+    let mut i = 0;
+    while i < s.len() {
+        match s[i] {
+            b'(' => {
+                i += 1;
+                let mut row: Vec<DumpValue> = Vec::new();
+                loop {
+                    let (value, next) = parse_value(s, i)?;
+                    row.push(value);
+                    match s.get(next) {
+                        Some(b',') => i = next + 1,
+                        Some(b')') => {
+                            i = next + 1;
+                            break;
+                        },
+                        _ => return Err(DumpError::Format("unterminated row".to_string())),
+                    }
+                }
+                f(row);
+            },
+            b',' | b';' | b' ' | b'\r' | b'\n' => i += 1,
+            c => return Err(DumpError::Format(format!("unexpected character '{}' between rows", c as char))),
+        }
+    }
+    Ok(())
+}
+
+/// Parses one value starting at `i`. Returns the value and the position right after it.
+fn parse_value(s: &[u8], mut i: usize) -> Result<(DumpValue, usize), DumpError> {
+    if s.get(i) == Some(&b'\'') {
+        i += 1;
+        let mut bytes: Vec<u8> = Vec::new();
+        while let Some(&c) = s.get(i) {
+            match c {
+                b'\\' => {
+                    let escaped = s.get(i + 1).ok_or_else(|| DumpError::Format("unterminated string".to_string()))?;
+                    bytes.push(match escaped {
+                        b'0' => 0,
+                        b'n' => b'\n',
+                        b'r' => b'\r',
+                        b't' => b'\t',
+                        b'Z' => 0x1a,
+                        other => *other,
+                    });
+                    i += 2;
+                },
+                b'\'' => return Ok((DumpValue::Text(String::from_utf8_lossy(&bytes).into_owned()), i + 1)),
+                _ => {
+                    bytes.push(c);
+                    i += 1;
+                },
+            }
+        }
+        Err(DumpError::Format("unterminated string".to_string()))
+    } else {
+        let end = s[i..].iter().position(|c| *c == b',' || *c == b')').map(|p| i + p).unwrap_or(s.len());
+        let token = String::from_utf8_lossy(&s[i..end]).trim().to_owned();
+        let value = if token == "NULL" {
+            DumpValue::Null
+        } else if let Ok(int) = token.parse::<i64>() {
+            DumpValue::Int(int)
+        } else {
+            DumpValue::Text(token)
+        };
+        Ok((value, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use mediawiki::hashmap;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::backend::WikiBackend;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/backend/testdata").join(name)
+    }
+
+    fn rows(s: &str) -> Result<Vec<Vec<DumpValue>>, DumpError> {
+        let mut rows = Vec::new();
+        parse_rows(s.as_bytes(), |row| rows.push(row))?;
+        Ok(rows)
+    }
+
+    fn text(s: &str) -> DumpValue {
+        DumpValue::Text(s.to_string())
+    }
+
+    /// The full titles of the pages a request returns
+    async fn titles_of(wiki: &MemoryBackend, params: &[(&str, &str)]) -> Vec<String> {
+        let mut params: HashMap<String, String> = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        params.insert("action".to_string(), "query".to_string());
+        let res = wiki.get(&params).await.unwrap();
+        res["query"]["pages"].as_array().unwrap().iter().map(|page| page["title"].as_str().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn rows_are_parsed() {
+        let parsed = rows("(1,'a\\'b',NULL),(-2,'x,y)','line\\nbreak'),(3,1.5,'');").unwrap();
+        assert_eq!(parsed, vec![
+            vec![DumpValue::Int(1), text("a'b"), DumpValue::Null],
+            vec![DumpValue::Int(-2), text("x,y)"), text("line\nbreak")],
+            vec![DumpValue::Int(3), text("1.5"), text("")],
+        ]);
+    }
+
+    #[test]
+    fn malformed_rows_are_rejected() {
+        assert!(matches!(rows("(1,2"), Err(DumpError::Format(_))));
+        assert!(matches!(rows("(1,'open)"), Err(DumpError::Format(_))));
+        assert!(matches!(rows("(1),x(2)"), Err(DumpError::Format(_))));
+    }
+
+    #[test]
+    fn table_name_is_read() {
+        assert_eq!(sql_table(&testdata("pagelinks.sql")).unwrap().as_deref(), Some("pagelinks"));
+        assert_eq!(sql_table(&testdata("pages.xml")).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn pages_are_loaded() {
+        let mut loader = DumpLoader::new();
+        loader.load_pages(&testdata("pages.xml")).unwrap();
+        let wiki = loader.finish();
+        // a namespace only this wiki has
+        assert_eq!(wiki.title_new_from_full("Portal:P").await.unwrap(), Title::new("P", 100));
+        assert_eq!(wiki.title_of(6), Some(Title::new("P", 100)));
+        assert_eq!(wiki.title_of(7), Some(Title::new("O'Brien", 0)));
+        assert_eq!(wiki.content("A").as_deref(), Some("[[B]] & [[O'Brien]] [[Category:X]]"));
+        assert_eq!(wiki.content("Portal:P").as_deref(), Some("[[A]] <"));
+        assert_eq!(wiki.content("B").as_deref(), Some(""));
+        // the redirect of the XML dump is followed
+        assert_eq!(titles_of(&wiki, &[("titles", "D"), ("redirects", "1")]).await, vec!["B"]);
+    }
+
+    #[tokio::test]
+    async fn dumps_are_loaded_in_any_order() {
+        let paths = ["redirect.sql", "categorylinks.sql", "pagelinks.sql", "templatelinks.sql", "linktarget.sql", "pages.xml"].map(testdata);
+        let paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+        let mut loader = DumpLoader::new();
+        loader.load_files(&paths).unwrap();
+        let wiki = loader.finish();
+        assert_eq!(titles_of(&wiki, &[("generator", "links"), ("titles", "A")]).await, vec!["B", "O'Brien"]);
+        // the link from page 99, which is not in the XML dump, is skipped
+        assert_eq!(titles_of(&wiki, &[("generator", "backlinks"), ("gbltitle", "B")]).await, vec!["A", "D"]);
+        assert_eq!(titles_of(&wiki, &[("generator", "links"), ("titles", "Portal:P")]).await, vec!["Not created"]);
+        assert_eq!(titles_of(&wiki, &[("generator", "embeddedin"), ("geititle", "Template:T")]).await, vec!["F"]);
+        assert_eq!(titles_of(&wiki, &[("generator", "categorymembers"), ("gcmtitle", "Category:X")]).await, vec!["A", "F"]);
+        assert_eq!(titles_of(&wiki, &[("generator", "categorymembers"), ("gcmtitle", "Category:Portals (all)")]).await, vec!["Portal:P"]);
+        // redirects to other wikis are skipped
+        assert_eq!(titles_of(&wiki, &[("titles", "O'Brien"), ("redirects", "1")]).await, vec!["O'Brien"]);
+        let params = hashmap!["action".to_string() => "query".to_string(), "titles".to_string() => "D".to_string()];
+        let res: Value = wiki.get(&params).await.unwrap();
+        assert_eq!(res["query"]["pages"][0]["redirect"], Value::Bool(true));
+    }
+
+    #[test]
+    fn unknown_table_is_rejected() {
+        let path = std::env::temp_dir().join(format!("plbot-dump-{}-user.sql", std::process::id()));
+        std::fs::write(&path, "CREATE TABLE `user` (\n  `user_id` int\n);\n").unwrap();
+        let res = DumpLoader::new().load_files(&[path.as_path()]).map(|_| ());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(DumpError::Format(_))));
+    }
+}
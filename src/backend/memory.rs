@@ -0,0 +1,818 @@
+//! An in-memory wiki.
+//!
+//! `MemoryBackend` answers the requests the bot sends (page content, page info, the generators used by the solver, edits)
+//! from pages, links, categories, transclusions, file usages and page properties registered up front.
+//! Edits replace page content but do not update links, categories or transclusions.
+//!
+//! The wiki keeps the edits, log entries and special page reports registered up front. The edits are listed as user contributions
+//! and recent changes, the first edit of a page being its creation. Requests for other reports fail with an error instead of an empty result.
+//! Search finds the pages whose title or content contains the search text.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::sync::RwLock;
+
+use async_trait::async_trait;
+use mediawiki::{api::NamespaceID, title::Title};
+use serde_json::{json, Value};
+
+use crate::apiservice::APIServiceError;
+use super::WikiBackend;
+
+/// Namespaces bundled with every MediaWiki installation
+const STANDARD_NAMESPACES: &[(NamespaceID, &str)] = &[
+    (-2, "Media"),
+    (-1, "Special"),
+    (0, ""),
+    (1, "Talk"),
+    (2, "User"),
+    (3, "User talk"),
+    (4, "Project"),
+    (5, "Project talk"),
+    (6, "File"),
+    (7, "File talk"),
+    (8, "MediaWiki"),
+    (9, "MediaWiki talk"),
+    (10, "Template"),
+    (11, "Template talk"),
+    (12, "Help"),
+    (13, "Help talk"),
+    (14, "Category"),
+    (15, "Category talk"),
+];
+
+const CSRF_TOKEN: &str = "+\\";
+
+/// Items in one response to a request for `max` of them, as the API gives to users without the `apihighlimits` right
+const MAX_LIMIT: usize = 500;
+
+#[derive(Debug, Clone)]
+struct Page {
+    id: i64,
+    content: String,
+    redirect: Option<Title>,
+    props: HashMap<String, String>,
+}
+
+/// An edit, listed in the contributions of its user
+#[derive(Debug, Clone)]
+struct Edit {
+    title: Title,
+    user: String,
+    // in the format of the API, so that the order of the strings is the order of the times
+    timestamp: String,
+}
+
+/// An entry of a log, such as a page move
+#[derive(Debug, Clone)]
+struct LogEntry {
+    // type and action, such as `move/move` or `delete/delete`
+    action: String,
+    title: Title,
+    // in the format of the API, like the time of an `Edit`
+    timestamp: String,
+}
+
+/// A relation between pages, indexed in both directions
+#[derive(Debug, Default)]
+struct Relation {
+    forward: HashMap<Title, HashSet<Title>>,
+    backward: HashMap<Title, HashSet<Title>>,
+}
+
+impl Relation {
+    fn insert(&mut self, source: Title, target: Title) {
+        self.backward.entry(target.clone()).or_default().insert(source.clone());
+        self.forward.entry(source).or_default().insert(target);
+    }
+
+    /// Pages `source` points to
+    fn targets_of(&self, source: &Title) -> Vec<Title> {
+        self.forward.get(source).map(|t| t.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Pages pointing to `target`
+    fn sources_of(&self, target: &Title) -> Vec<Title> {
+        self.backward.get(target).map(|s| s.iter().cloned().collect()).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Store {
+    last_id: i64,
+    pages: HashMap<Title, Page>,
+    // page id -> page
+    ids: HashMap<i64, Title>,
+    // namespace and name of every page, sorted for prefix lookups
+    sorted: BTreeSet<(NamespaceID, String)>,
+    // page -> pages it links to
+    links: Relation,
+    // page -> categories it is in
+    categories: Relation,
+    // page -> pages it transcludes
+    templates: Relation,
+    // page -> files it uses
+    images: Relation,
+    // in the order they were registered
+    edits: Vec<Edit>,
+    // special page name -> pages of its report, in order
+    reports: HashMap<String, Vec<Title>>,
+    // in the order they were registered
+    log: Vec<LogEntry>,
+}
+
+impl Store {
+    fn ensure_page(&mut self, title: &Title) -> &mut Page {
+        if !self.pages.contains_key(title) {
+            self.insert_page(self.last_id + 1, title.clone());
+        }
+        self.pages.get_mut(title).unwrap()
+    }
+
+    fn insert_page(&mut self, id: i64, title: Title) -> &mut Page {
+        self.last_id = i64::max(self.last_id, id);
+        self.ids.insert(id, title.clone());
+        self.sorted.insert((title.namespace_id(), title.pretty().to_owned()));
+        self.pages.entry(title).or_insert(Page { id, content: String::new(), redirect: None, props: HashMap::new() })
+    }
+
+    fn is_redirect(&self, title: &Title) -> bool {
+        self.pages.get(title).map(|p| p.redirect.is_some()).unwrap_or(false)
+    }
+
+    /// The indices of the edits `keep` accepts, from the newest. `keep` is also told whether an edit is the first and the last of its page.
+    fn edits_from_newest(&self, keep: impl Fn(&Edit, bool, bool) -> bool) -> Vec<usize> {
+        // the first and the last edit of every page, counted in the order of the times then of registration
+        let mut first: HashMap<&Title, usize> = HashMap::new();
+        let mut last: HashMap<&Title, usize> = HashMap::new();
+        for (i, edit) in self.edits.iter().enumerate() {
+            let newer = |j: &usize| (edit.timestamp.as_str(), i) > (self.edits[*j].timestamp.as_str(), *j);
+            if first.get(&edit.title).is_none_or(|j| !newer(j)) {
+                first.insert(&edit.title, i);
+            }
+            if last.get(&edit.title).is_none_or(newer) {
+                last.insert(&edit.title, i);
+            }
+        }
+        let mut edits: Vec<usize> = (0..self.edits.len())
+            .filter(|i| {
+                let edit = &self.edits[*i];
+                keep(edit, first[&edit.title] == *i, last[&edit.title] == *i)
+            })
+            .collect();
+        edits.sort_by(|i, j| (self.edits[*j].timestamp.as_str(), *j).cmp(&(self.edits[*i].timestamp.as_str(), *i)));
+        edits
+    }
+
+    /// Pages in namespace `ns` whose name starts with `prefix`
+    fn with_prefix(&self, ns: NamespaceID, prefix: &str) -> Vec<Title> {
+        self.sorted.range((ns, prefix.to_owned())..)
+            .take_while(|(n, name)| *n == ns && name.starts_with(prefix))
+            .map(|(n, name)| Title::new(name, *n))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryBackend {
+    namespaces: HashMap<NamespaceID, String>,
+    // lowercased namespace name or alias -> namespace
+    namespace_lookup: HashMap<String, NamespaceID>,
+    store: RwLock<Store>,
+}
+
+impl MemoryBackend {
+
+    /// Creates an empty wiki with the standard namespaces
+    pub fn new() -> Self {
+        let mut backend = MemoryBackend {
+            namespaces: HashMap::new(),
+            namespace_lookup: HashMap::new(),
+            store: RwLock::new(Store::default()),
+        };
+        for (id, name) in STANDARD_NAMESPACES {
+            backend.add_namespace(*id, name);
+        }
+        backend
+    }
+
+    /// Adds a namespace, or renames an existing one. The old name is kept as an alias.
+    pub fn add_namespace(&mut self, id: NamespaceID, name: &str) -> &mut Self {
+        let name = Title::underscores_to_spaces(name);
+        if !name.is_empty() {
+            self.namespace_lookup.insert(name.to_lowercase(), id);
+        }
+        self.namespaces.insert(id, name);
+        self
+    }
+
+    /// Creates a page with the given id. Used when loading a wiki whose page ids are known.
+    pub(super) fn insert_page(&mut self, id: i64, title: Title, content: String, redirect: Option<Title>) {
+        let store = self.store.get_mut().unwrap();
+        let page = store.insert_page(id, title.clone());
+        page.content = content;
+        if let Some(target) = redirect {
+            page.redirect = Some(target.clone());
+            store.links.insert(title, target);
+        }
+    }
+
+    pub(super) fn insert_link(&mut self, from: Title, to: Title) {
+        self.store.get_mut().unwrap().links.insert(from, to);
+    }
+
+    pub(super) fn insert_category(&mut self, page: Title, category: Title) {
+        self.store.get_mut().unwrap().categories.insert(page, category);
+    }
+
+    pub(super) fn insert_template(&mut self, page: Title, template: Title) {
+        self.store.get_mut().unwrap().templates.insert(page, template);
+    }
+
+    /// Points an existing page to a redirect target
+    pub(super) fn set_redirect(&mut self, from: &Title, to: Title) {
+        let store = self.store.get_mut().unwrap();
+        if let Some(page) = store.pages.get_mut(from) {
+            page.redirect = Some(to.clone());
+            store.links.insert(from.clone(), to);
+        }
+    }
+
+    /// Looks up the title of a page by its id
+    pub(super) fn title_of(&self, id: i64) -> Option<Title> {
+        self.store.read().unwrap().ids.get(&id).cloned()
+    }
+
+    pub(super) fn parse_title(&self, full: &str) -> Title {
+        let full = Title::underscores_to_spaces(full);
+        if let Some((prefix, rest)) = full.split_once(':') {
+            if let Some(id) = self.namespace_lookup.get(&prefix.trim().to_lowercase()) {
+                return Title::new(&Title::first_letter_uppercase(rest), *id);
+            }
+        }
+        Title::new(&Title::first_letter_uppercase(&full), 0)
+    }
+
+    fn full_text(&self, title: &Title) -> Option<String> {
+        self.namespaces.get(&title.namespace_id()).map(|ns| {
+            if ns.is_empty() {
+                title.pretty().to_owned()
+            } else {
+                format!("{}:{}", ns, title.pretty())
+            }
+        })
+    }
+
+    fn page_object(&self, store: &Store, title: &Title, with_content: bool, props: Option<&HashSet<&str>>) -> Value {
+        let mut obj = json!({
+            "ns": title.namespace_id(),
+            "title": self.full_text(title).unwrap_or_else(|| title.pretty().to_owned()),
+        });
+        if let Some(page) = store.pages.get(title) {
+            let contentmodel = content_model(title);
+            obj["pageid"] = json!(page.id);
+            obj["contentmodel"] = json!(contentmodel);
+            if page.redirect.is_some() {
+                obj["redirect"] = json!(true);
+            }
+            if let Some(props) = props {
+                let found: serde_json::Map<String, Value> = page.props.iter()
+                    .filter(|(name, _)| props.is_empty() || props.contains(name.as_str()))
+                    .map(|(name, value)| (name.to_owned(), json!(value)))
+                    .collect();
+                if !found.is_empty() {
+                    obj["pageprops"] = Value::Object(found);
+                }
+            }
+            if with_content {
+                obj["revisions"] = json!([{ "slots": { "main": { "contentmodel": contentmodel, "content": page.content } } }]);
+            }
+        } else {
+            obj["missing"] = json!(true);
+        }
+        obj
+    }
+
+    fn query(&self, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let store = self.store.read().unwrap();
+        if let Some(list) = params.get("list") {
+            match list.as_str() {
+                "usercontribs" => return self.user_contribs(&store, params, max),
+                "querypage" => return self.query_page(&store, params, max),
+                "logevents" => return self.log_events(&store, params, max),
+                _ => {},
+            }
+            return self.list(&store, list, params, max);
+        }
+        if params.get("generator").is_some_and(|g| g == "recentchanges") {
+            return self.recent_changes(&store, params, max);
+        }
+        let mut redirects: Vec<Value> = Vec::new();
+        let mut titles: Vec<Title> = if let Some(generator) = params.get("generator") {
+            // like MediaWiki, `redirects` also resolves the titles the generator starts from
+            match params.get("titles").filter(|_| params.contains_key("redirects")) {
+                Some(sources) => {
+                    let sources: Vec<String> = sources.split('|')
+                        .map(|t| self.follow_redirect(&store, self.parse_title(t), &mut redirects))
+                        .filter_map(|t| self.full_text(&t))
+                        .collect();
+                    let mut params = params.clone();
+                    params.insert(String::from("titles"), sources.join("|"));
+                    self.generate(&store, generator, &params)?
+                },
+                None => self.generate(&store, generator, params)?,
+            }
+        } else if let Some(titles) = params.get("titles") {
+            titles.split('|').map(|t| self.parse_title(t)).collect()
+        } else if let Some(pageids) = params.get("pageids") {
+            pageids.split('|')
+                .filter_map(|id| id.parse::<i64>().ok())
+                .filter_map(|id| store.ids.get(&id).cloned())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if params.contains_key("redirects") {
+            titles = titles.into_iter().map(|t| self.follow_redirect(&store, t, &mut redirects)).collect();
+        }
+        sort_titles(&mut titles);
+        if let Some(max) = max {
+            titles.truncate(max);
+        }
+        let props: Vec<&str> = params.get("prop").map(|p| p.split('|').collect()).unwrap_or_default();
+        let with_content = props.contains(&"revisions");
+        let pageprops: Option<HashSet<&str>> = if props.contains(&"pageprops") {
+            Some(params.get("ppprop").map(|p| p.split('|').collect()).unwrap_or_default())
+        } else {
+            None
+        };
+        let pages: Vec<Value> = titles.iter().map(|t| self.page_object(&store, t, with_content, pageprops.as_ref())).collect();
+        let mut query = json!({ "pages": pages });
+        if !redirects.is_empty() {
+            query["redirects"] = Value::Array(redirects);
+        }
+        Ok(json!({ "batchcomplete": true, "query": query }))
+    }
+
+    /// The target of `title` if it is a redirect, recorded in `redirects` as the API reports it
+    fn follow_redirect(&self, store: &Store, title: Title, redirects: &mut Vec<Value>) -> Title {
+        match store.pages.get(&title).and_then(|p| p.redirect.clone()) {
+            Some(target) => {
+                redirects.push(json!({ "from": self.full_text(&title), "to": self.full_text(&target) }));
+                target
+            },
+            None => title,
+        }
+    }
+
+    fn generate(&self, store: &Store, generator: &str, params: &HashMap<String, String>) -> Result<Vec<Title>, APIServiceError> {
+        match generator {
+            "links" => {
+                let ns = namespace_param(params, "gplnamespace");
+                let mut result: Vec<Title> = Vec::new();
+                for source in params.get("titles").into_iter().flat_map(|t| t.split('|')) {
+                    result.extend(store.links.targets_of(&self.parse_title(source)).into_iter().filter(|t| in_namespaces(&ns, t)));
+                }
+                Ok(result)
+            },
+            "backlinks" => {
+                let target = self.parse_title(required_param(params, "gbltitle")?);
+                let ns = namespace_param(params, "gblnamespace");
+                let mut result = store.links.sources_of(&target);
+                if params.contains_key("gblredirect") {
+                    // also list pages linking to the redirects of the target
+                    let redirects: Vec<Title> = result.iter().filter(|t| store.pages.get(t).and_then(|p| p.redirect.as_ref()) == Some(&target)).cloned().collect();
+                    for redirect in redirects {
+                        result.extend(store.links.sources_of(&redirect));
+                    }
+                }
+                let filter = params.get("gblfilterredir").map(|s| s.as_str());
+                Ok(result.into_iter().filter(|t| in_namespaces(&ns, t) && redirect_filter(store, filter, t)).collect())
+            },
+            "categorymembers" => {
+                let category = self.parse_title(required_param(params, "gcmtitle")?);
+                let ns = namespace_param(params, "gcmnamespace");
+                let types: Vec<&str> = params.get("gcmtype").map(|t| t.split('|').collect()).unwrap_or_else(|| vec!["page", "subcat", "file"]);
+                Ok(store.categories.sources_of(&category).into_iter().filter(|t| {
+                    let member_type = match t.namespace_id() {
+                        14 => "subcat",
+                        6 => "file",
+                        _ => "page",
+                    };
+                    types.contains(&member_type) && in_namespaces(&ns, t)
+                }).collect())
+            },
+            "allpages" => {
+                let ns: NamespaceID = params.get("gapnamespace").and_then(|ns| ns.parse().ok()).unwrap_or(0);
+                let prefix = params.get("gapprefix").map(|p| Title::first_letter_uppercase(p)).unwrap_or_default();
+                let filter = params.get("gapfilterredir").map(|s| s.as_str());
+                Ok(store.with_prefix(ns, &prefix).into_iter().filter(|t| redirect_filter(store, filter, t)).collect())
+            },
+            "embeddedin" => {
+                let template = self.parse_title(required_param(params, "geititle")?);
+                let ns = namespace_param(params, "geinamespace");
+                let filter = params.get("geifilterredir").map(|s| s.as_str());
+                Ok(store.templates.sources_of(&template).into_iter().filter(|t| in_namespaces(&ns, t) && redirect_filter(store, filter, t)).collect())
+            },
+            "categories" => {
+                Ok(self.collect_from_titles(params, |source| store.categories.targets_of(source)))
+            },
+            "templates" => {
+                let ns = namespace_param(params, "gtlnamespace");
+                Ok(self.collect_from_titles(params, |source| store.templates.targets_of(source)).into_iter().filter(|t| in_namespaces(&ns, t)).collect())
+            },
+            "images" => {
+                Ok(self.collect_from_titles(params, |source| store.images.targets_of(source)))
+            },
+            "fileusage" => {
+                let ns = namespace_param(params, "gfunamespace");
+                let filter = match params.get("gfushow").map(|s| s.as_str()) {
+                    Some("redirect") => Some("redirects"),
+                    Some("!redirect") => Some("nonredirects"),
+                    _ => None,
+                };
+                Ok(self.collect_from_titles(params, |source| store.images.sources_of(source)).into_iter().filter(|t| in_namespaces(&ns, t) && redirect_filter(store, filter, t)).collect())
+            },
+            "redirects" => {
+                let ns = namespace_param(params, "grdnamespace");
+                Ok(self.collect_from_titles(params, |source| {
+                    store.links.sources_of(source).into_iter().filter(|t| store.pages.get(t).and_then(|p| p.redirect.as_ref()) == Some(source)).collect()
+                }).into_iter().filter(|t| in_namespaces(&ns, t)).collect())
+            },
+            _ => Err(api_error("badvalue", &format!("Unrecognized value for parameter \"generator\": {}.", generator))),
+        }
+    }
+
+    /// Runs `f` on every page of the `titles` parameter and collects the results
+    fn collect_from_titles(&self, params: &HashMap<String, String>, f: impl Fn(&Title) -> Vec<Title>) -> Vec<Title> {
+        params.get("titles").into_iter().flat_map(|t| t.split('|')).flat_map(|source| f(&self.parse_title(source))).collect()
+    }
+
+    fn list(&self, store: &Store, list: &str, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let (mut titles, key) = match list {
+            "search" => {
+                let text = required_param(params, "srsearch")?.to_lowercase();
+                // like the search engine, only the main namespace is searched by default
+                let ns = namespace_param(params, "srnamespace").unwrap_or_else(|| HashSet::from([0]));
+                let titles: Vec<Title> = store.pages.iter()
+                    .filter(|(t, page)| ns.contains(&t.namespace_id()) && (t.pretty().to_lowercase().contains(&text) || page.content.to_lowercase().contains(&text)))
+                    .map(|(t, _)| t.clone())
+                    .collect();
+                (titles, "search")
+            },
+            "pageswithprop" => {
+                let name = required_param(params, "pwppropname")?;
+                let titles: Vec<Title> = store.pages.iter().filter(|(_, page)| page.props.contains_key(name)).map(|(t, _)| t.clone()).collect();
+                (titles, "pageswithprop")
+            },
+            _ => return Err(api_error("badvalue", &format!("Unrecognized value for parameter \"list\": {}.", list))),
+        };
+        sort_titles(&mut titles);
+        if let Some(max) = max {
+            titles.truncate(max);
+        }
+        let items: Vec<Value> = titles.iter().map(|t| json!({
+            "ns": t.namespace_id(),
+            "title": self.full_text(t).unwrap_or_else(|| t.pretty().to_owned()),
+        })).collect();
+        Ok(json!({ "batchcomplete": true, "query": { key: items } }))
+    }
+
+    /// Lists the edits of a user from the newest, a batch of `uclimit` edits at a time
+    fn user_contribs(&self, store: &Store, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let user = Title::underscores_to_spaces(required_param(params, "ucuser")?);
+        let ns = namespace_param(params, "ucnamespace");
+        let show = params.get("ucshow").map(|s| s.as_str());
+        let edits = store.edits_from_newest(|edit, is_first, is_last| {
+            edit.user == user
+                && in_namespaces(&ns, &edit.title)
+                && in_span(&edit.timestamp, params, "ucstart", "ucend")
+                && match show {
+                    Some("new") => is_first,
+                    Some("top") => is_last,
+                    _ => true,
+                }
+        });
+        let (range, cont) = batch(edits.len(), params, "uclimit", "uccontinue", max)?;
+        let items: Vec<Value> = edits[range].iter().map(|i| {
+            let edit = &store.edits[*i];
+            json!({
+                "user": edit.user,
+                "ns": edit.title.namespace_id(),
+                "title": self.full_text(&edit.title).unwrap_or_else(|| edit.title.pretty().to_owned()),
+                "timestamp": edit.timestamp,
+            })
+        }).collect();
+        let mut res = json!({ "batchcomplete": true, "query": { "usercontribs": items } });
+        if let Some(cont) = cont {
+            res["continue"] = cont;
+        }
+        Ok(res)
+    }
+
+    /// Lists the pages edited or created, from the newest edit, a batch of `grclimit` edits at a time.
+    /// Every edit registered with `add_edit` is a recent change, the first one of a page being its creation.
+    fn recent_changes(&self, store: &Store, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let types: Vec<&str> = params.get("grctype").map(|t| t.split('|').collect()).unwrap_or_else(|| vec!["edit", "new"]);
+        let ns = namespace_param(params, "grcnamespace");
+        let show = params.get("grcshow").map(|s| s.as_str());
+        let edits = store.edits_from_newest(|edit, is_first, _| {
+            types.contains(&if is_first { "new" } else { "edit" })
+                && in_namespaces(&ns, &edit.title)
+                && in_span(&edit.timestamp, params, "grcstart", "grcend")
+                && match show {
+                    Some("redirect") => store.is_redirect(&edit.title),
+                    Some("!redirect") => !store.is_redirect(&edit.title),
+                    _ => true,
+                }
+        });
+        let (range, cont) = batch(edits.len(), params, "grclimit", "grccontinue", max)?;
+        // like a generator, a batch lists each of its pages once
+        let mut titles: Vec<Title> = edits[range].iter().map(|i| store.edits[*i].title.clone()).collect();
+        sort_titles(&mut titles);
+        let pages: Vec<Value> = titles.iter().map(|t| self.page_object(store, t, false, None)).collect();
+        let mut res = json!({ "batchcomplete": true, "query": { "pages": pages } });
+        if let Some(cont) = cont {
+            res["continue"] = cont;
+        }
+        Ok(res)
+    }
+
+    /// Lists the log entries registered with `add_log_entry` from the newest, a batch of `lelimit` entries at a time
+    fn log_events(&self, store: &Store, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let ns = namespace_param(params, "lenamespace");
+        let mut entries: Vec<&LogEntry> = store.log.iter()
+            .filter(|entry| {
+                params.get("letype").is_none_or(|t| entry.action.split('/').next() == Some(t.as_str()))
+                    && params.get("leaction").is_none_or(|a| entry.action == *a)
+                    && in_namespaces(&ns, &entry.title)
+                    && in_span(&entry.timestamp, params, "lestart", "leend")
+            })
+            .collect();
+        // stable, so that entries at the same time stay in the order they were registered
+        entries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        let (range, cont) = batch(entries.len(), params, "lelimit", "lecontinue", max)?;
+        let items: Vec<Value> = entries[range].iter().map(|entry| {
+            let (kind, action) = entry.action.split_once('/').unwrap_or((&entry.action, &entry.action));
+            json!({
+                "type": kind,
+                "action": action,
+                "ns": entry.title.namespace_id(),
+                "title": self.full_text(&entry.title).unwrap_or_else(|| entry.title.pretty().to_owned()),
+                "timestamp": entry.timestamp,
+            })
+        }).collect();
+        let mut res = json!({ "batchcomplete": true, "query": { "logevents": items } });
+        if let Some(cont) = cont {
+            res["continue"] = cont;
+        }
+        Ok(res)
+    }
+
+    /// Lists the pages of a special page report registered with `add_report`, a batch of `qplimit` pages at a time
+    fn query_page(&self, store: &Store, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        let name = required_param(params, "qppage")?;
+        let pages = store.reports.get(name).ok_or_else(|| api_error("badvalue", &format!("Unrecognized value for parameter \"qppage\": {}.", name)))?;
+        let (range, cont) = batch(pages.len(), params, "qplimit", "qpoffset", max)?;
+        let results: Vec<Value> = pages[range].iter().map(|t| json!({
+            "value": "0",
+            "ns": t.namespace_id(),
+            "title": self.full_text(t).unwrap_or_else(|| t.pretty().to_owned()),
+        })).collect();
+        let mut res = json!({ "batchcomplete": true, "query": { "querypage": { "name": name, "results": results } } });
+        if let Some(cont) = cont {
+            res["continue"] = cont;
+        }
+        Ok(res)
+    }
+
+    fn edit(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        let title = self.parse_title(required_param(params, "title")?);
+        let text = required_param(params, "text")?;
+        if params.get("token").map(|t| t.as_str()) != Some(CSRF_TOKEN) {
+            return Err(api_error("badtoken", "Invalid CSRF token."));
+        }
+        let mut store = self.store.write().unwrap();
+        if params.contains_key("nocreate") && !store.pages.contains_key(&title) {
+            return Err(api_error("missingtitle", "The page you specified doesn't exist."));
+        }
+        let page = store.ensure_page(&title);
+        page.content = text.to_owned();
+        Ok(json!({ "edit": { "result": "Success", "pageid": page.id, "title": self.full_text(&title) } }))
+    }
+
+}
+
+// Builders for wikis set up by hand in tests. A loaded wiki is built by `DumpLoader` instead.
+#[cfg(test)]
+impl MemoryBackend {
+
+    pub fn add_namespace_alias(&mut self, alias: &str, id: NamespaceID) -> &mut Self {
+        self.namespace_lookup.insert(Title::underscores_to_spaces(alias).to_lowercase(), id);
+        self
+    }
+
+    /// Creates a page, or replaces the content of an existing one
+    pub fn add_page(&mut self, title: &str, content: &str) -> &mut Self {
+        let title = self.parse_title(title);
+        self.store.get_mut().unwrap().ensure_page(&title).content = content.to_owned();
+        self
+    }
+
+    /// Creates a redirect page. A redirect also counts as a link to its target, as it does on a real wiki.
+    pub fn add_redirect(&mut self, from: &str, to: &str) -> &mut Self {
+        let from = self.parse_title(from);
+        let to = self.parse_title(to);
+        let content = format!("#REDIRECT [[{}]]", self.full_text(&to).unwrap_or_default());
+        let store = self.store.get_mut().unwrap();
+        let page = store.ensure_page(&from);
+        page.content = content;
+        page.redirect = Some(to.clone());
+        store.links.insert(from, to);
+        self
+    }
+
+    pub fn add_link(&mut self, from: &str, to: &str) -> &mut Self {
+        let from = self.parse_title(from);
+        let to = self.parse_title(to);
+        let store = self.store.get_mut().unwrap();
+        store.ensure_page(&from);
+        store.links.insert(from, to);
+        self
+    }
+
+    pub fn add_category(&mut self, page: &str, category: &str) -> &mut Self {
+        let page = self.parse_title(page);
+        let category = self.parse_title(category);
+        let store = self.store.get_mut().unwrap();
+        store.ensure_page(&page);
+        store.categories.insert(page, category);
+        self
+    }
+
+    pub fn add_template(&mut self, page: &str, template: &str) -> &mut Self {
+        let page = self.parse_title(page);
+        let template = self.parse_title(template);
+        let store = self.store.get_mut().unwrap();
+        store.ensure_page(&page);
+        store.templates.insert(page, template);
+        self
+    }
+
+    pub fn add_image(&mut self, page: &str, file: &str) -> &mut Self {
+        let page = self.parse_title(page);
+        let file = self.parse_title(file);
+        let store = self.store.get_mut().unwrap();
+        store.ensure_page(&page);
+        store.images.insert(page, file);
+        self
+    }
+
+    /// Sets a page property, such as `wikibase_item` or `disambiguation`, on a page
+    pub fn add_page_prop(&mut self, page: &str, name: &str, value: &str) -> &mut Self {
+        let page = self.parse_title(page);
+        self.store.get_mut().unwrap().ensure_page(&page).props.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Records an edit of `user` on a page, creating the page. `timestamp` is in the format of the API, such as `2020-01-01T00:00:00Z`.
+    pub fn add_edit(&mut self, page: &str, user: &str, timestamp: &str) -> &mut Self {
+        let title = self.parse_title(page);
+        let store = self.store.get_mut().unwrap();
+        store.ensure_page(&title);
+        store.edits.push(Edit { title, user: Title::underscores_to_spaces(user), timestamp: timestamp.to_owned() });
+        self
+    }
+
+    /// Records a log entry on a page. `action` is the type and the action, such as `move/move`.
+    pub fn add_log_entry(&mut self, action: &str, page: &str, timestamp: &str) -> &mut Self {
+        let title = self.parse_title(page);
+        self.store.get_mut().unwrap().log.push(LogEntry { action: action.to_owned(), title, timestamp: timestamp.to_owned() });
+        self
+    }
+
+    /// Sets the pages listed by a special page report, such as `Lonelypages`. The pages need not exist, as in `Wantedpages`.
+    pub fn add_report(&mut self, name: &str, pages: &[&str]) -> &mut Self {
+        let titles: Vec<Title> = pages.iter().map(|p| self.parse_title(p)).collect();
+        self.store.get_mut().unwrap().reports.insert(name.to_owned(), titles);
+        self
+    }
+
+    /// Returns the current content of a page, including edits made through the backend
+    pub fn content(&self, title: &str) -> Option<String> {
+        let title = self.parse_title(title);
+        self.store.read().unwrap().pages.get(&title).map(|p| p.content.clone())
+    }
+
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl WikiBackend for MemoryBackend {
+
+    async fn get(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        self.get_limit(params, None).await
+    }
+
+    async fn get_limit(&self, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError> {
+        match params.get("action").map(|a| a.as_str()) {
+            Some("query") => self.query(params, max),
+            Some(action) => Err(api_error("badvalue", &format!("Unrecognized value for parameter \"action\": {}.", action))),
+            None => Err(api_error("missingparam", "The \"action\" parameter must be set.")),
+        }
+    }
+
+    async fn post_edit(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        match params.get("action").map(|a| a.as_str()) {
+            Some("edit") => self.edit(params),
+            _ => self.get(params).await,
+        }
+    }
+
+    async fn csrf(&self) -> String {
+        CSRF_TOKEN.to_owned()
+    }
+
+    async fn full_pretty(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
+        Ok(self.full_text(title))
+    }
+
+    async fn namespace_name(&self, title: &Title) -> Result<Option<String>, APIServiceError> {
+        Ok(self.namespaces.get(&title.namespace_id()).cloned())
+    }
+
+    async fn title_new_from_full(&self, title: &str) -> Result<Title, APIServiceError> {
+        Ok(self.parse_title(title))
+    }
+
+    async fn namespace_names(&self) -> Result<Vec<(String, NamespaceID)>, APIServiceError> {
+        Ok(self.namespace_lookup.iter().map(|(name, id)| (name.to_owned(), *id)).collect())
+    }
+
+}
+
+fn api_error(code: &str, info: &str) -> APIServiceError {
+    APIServiceError::Server(json!({ "code": code, "info": info }))
+}
+
+fn required_param<'a>(params: &'a HashMap<String, String>, key: &str) -> Result<&'a str, APIServiceError> {
+    params.get(key).map(|v| v.as_str()).ok_or_else(|| api_error("missingparam", &format!("The \"{}\" parameter must be set.", key)))
+}
+
+/// The part of a list of `len` items in the batch a request asks for with `limit_key` and `offset_key`,
+/// and the continuation to the next batch if there is one
+fn batch(len: usize, params: &HashMap<String, String>, limit_key: &str, offset_key: &str, max: Option<usize>) -> Result<(Range<usize>, Option<Value>), APIServiceError> {
+    let offset: usize = params.get(offset_key).and_then(|c| c.parse().ok()).unwrap_or(0);
+    let size = match params.get(limit_key).map(|l| l.as_str()) {
+        None | Some("max") => MAX_LIMIT,
+        Some(limit) => limit.parse().map_err(|_| api_error("badinteger", &format!("Invalid value \"{}\" for integer parameter \"{}\".", limit, limit_key)))?,
+    };
+    let end = usize::min(len, offset + max.map_or(size, |max| usize::min(max, size)));
+    let cont = (end < len).then(|| json!({ offset_key: end.to_string(), "continue": "-||" }));
+    Ok((usize::min(offset, end)..end, cont))
+}
+
+/// Whether `timestamp` is within the span of a request listing from the newest, which starts at `start_key` and ends at `end_key`
+fn in_span(timestamp: &str, params: &HashMap<String, String>, start_key: &str, end_key: &str) -> bool {
+    params.get(start_key).is_none_or(|start| timestamp <= start.as_str()) && params.get(end_key).is_none_or(|end| timestamp >= end.as_str())
+}
+
+fn namespace_param(params: &HashMap<String, String>, key: &str) -> Option<HashSet<NamespaceID>> {
+    params.get(key).map(|ns| ns.split('|').filter_map(|n| n.parse().ok()).collect())
+}
+
+fn in_namespaces(ns: &Option<HashSet<NamespaceID>>, title: &Title) -> bool {
+    ns.as_ref().map(|ns| ns.contains(&title.namespace_id())).unwrap_or(true)
+}
+
+fn redirect_filter(store: &Store, filter: Option<&str>, title: &Title) -> bool {
+    match filter {
+        Some("redirects") => store.is_redirect(title),
+        Some("nonredirects") => !store.is_redirect(title),
+        _ => true,
+    }
+}
+
+fn content_model(title: &Title) -> &'static str {
+    let name = title.pretty();
+    if name.ends_with(".json") {
+        "json"
+    } else if name.ends_with(".css") {
+        "css"
+    } else if name.ends_with(".js") {
+        "javascript"
+    } else {
+        "wikitext"
+    }
+}
+
+/// Sorts by namespace then name, and removes duplicates, so that responses do not depend on hash order
+fn sort_titles(titles: &mut Vec<Title>) {
+    titles.sort_by(|a, b| (a.namespace_id(), a.pretty()).cmp(&(b.namespace_id(), b.pretty())));
+    titles.dedup();
+}
@@ -0,0 +1,73 @@
+//! Wiki backends.
+//!
+//! The solver and the routines never talk to a wiki directly, they go through a `WikiBackend`.
+//! `APIService` is the backend for a live wiki, `MemoryBackend` holds a wiki in memory,
+//! either built by hand or loaded from database dumps by `DumpLoader`.
+//! `CachedBackend` keeps the responses of another backend for a while.
+
+use std::{collections::HashMap, sync::Arc};
+
+use async_trait::async_trait;
+use mediawiki::{api::NamespaceID, title::Title};
+use serde_json::Value;
+
+use crate::apiservice::APIServiceError;
+
+mod cache;
+mod dump;
+mod memory;
+
+pub use cache::CachedBackend;
+pub use dump::DumpLoader;
+pub use memory::MemoryBackend;
+
+/// Everything the bot needs from a wiki.
+///
+/// Requests and responses follow MediaWiki Action API conventions (`format=json`, `formatversion=2`),
+/// so that a backend can be swapped without touching the code that builds the requests.
+#[async_trait]
+pub trait WikiBackend: Send + Sync {
+    /// Send a read-only request
+    async fn get(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError>;
+
+    /// Send a read-only request, following continuations until `max` results are collected
+    async fn get_limit(&self, params: &HashMap<String, String>, max: Option<usize>) -> Result<Value, APIServiceError>;
+
+    /// Send a read-only request, following all continuations
+    async fn get_all(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError> {
+        self.get_limit(params, None).await
+    }
+
+    /// Send an edit request
+    async fn post_edit(&self, params: &HashMap<String, String>) -> Result<Value, APIServiceError>;
+
+    /// Get csrf token
+    async fn csrf(&self) -> String;
+
+    /// Convert Title object to full pretty title
+    async fn full_pretty(&self, title: &Title) -> Result<Option<String>, APIServiceError>;
+
+    /// Convert Title object to namespace name
+    async fn namespace_name(&self, title: &Title) -> Result<Option<String>, APIServiceError>;
+
+    /// Create a title from full name
+    async fn title_new_from_full(&self, title: &str) -> Result<Title, APIServiceError>;
+
+    /// Every name and alias of every namespace
+    async fn namespace_names(&self) -> Result<Vec<(String, NamespaceID)>, APIServiceError>;
+
+    /// The backend of another wiki, named by its site profile
+    async fn site(&self, name: &str) -> Result<Arc<dyn WikiBackend>, APIServiceError> {
+        Err(APIServiceError::UnknownSite(name.to_owned()))
+    }
+
+    /// The backend beneath a cache, if this backend is one
+    fn uncached(&self) -> Option<Arc<dyn WikiBackend>> {
+        None
+    }
+}
+
+/// The backend to use for reads that must see the current state of the wiki
+pub fn fresh(backend: &Arc<dyn WikiBackend>) -> Arc<dyn WikiBackend> {
+    backend.uncached().unwrap_or_else(|| backend.clone())
+}
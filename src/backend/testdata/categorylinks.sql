@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS `categorylinks`;
+CREATE TABLE `categorylinks` (
+  `cl_from` int(8) unsigned NOT NULL DEFAULT 0,
+  `cl_to` varbinary(255) NOT NULL DEFAULT '',
+  `cl_sortkey` varbinary(230) NOT NULL DEFAULT '',
+  `cl_timestamp` timestamp NOT NULL DEFAULT current_timestamp(),
+  `cl_type` enum('page','subcat','file') NOT NULL DEFAULT 'page',
+  PRIMARY KEY (`cl_from`,`cl_to`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary;
+
+INSERT INTO `categorylinks` VALUES (1,'X','A','2024-01-01 00:00:00','page'),(5,'X','F\nsortkey','2024-01-01 00:00:00','page'),(6,'Portals_(all)','P','2024-01-01 00:00:00','page');
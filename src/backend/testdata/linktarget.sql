@@ -0,0 +1,15 @@
+-- MySQL dump 10.19  Distrib 10.3.38-MariaDB, for debian-linux-gnu (x86_64)
+--
+-- Table structure for table `linktarget`
+--
+
+DROP TABLE IF EXISTS `linktarget`;
+CREATE TABLE `linktarget` (
+  `lt_id` bigint(20) unsigned NOT NULL AUTO_INCREMENT,
+  `lt_namespace` int(11) NOT NULL,
+  `lt_title` varbinary(255) NOT NULL,
+  PRIMARY KEY (`lt_id`),
+  UNIQUE KEY `lt_namespace_title` (`lt_namespace`,`lt_title`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary;
+
+INSERT INTO `linktarget` VALUES (1,0,'B'),(2,0,'O\'Brien'),(3,0,'Not_created'),(4,10,'T');
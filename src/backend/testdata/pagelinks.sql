@@ -0,0 +1,11 @@
+DROP TABLE IF EXISTS `pagelinks`;
+CREATE TABLE `pagelinks` (
+  `pl_from` int(8) unsigned NOT NULL DEFAULT 0,
+  `pl_from_namespace` int(11) NOT NULL DEFAULT 0,
+  `pl_target_id` bigint(20) unsigned NOT NULL,
+  PRIMARY KEY (`pl_from`,`pl_target_id`),
+  KEY `pl_target_id` (`pl_target_id`,`pl_from`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary;
+
+INSERT INTO `pagelinks` VALUES (1,0,1),(1,0,2),(3,0,1),(6,100,3);
+INSERT INTO `pagelinks` VALUES (99,0,1);
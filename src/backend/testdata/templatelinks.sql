@@ -0,0 +1,10 @@
+DROP TABLE IF EXISTS `templatelinks`;
+CREATE TABLE `templatelinks` (
+  `tl_from` int(8) unsigned NOT NULL DEFAULT 0,
+  `tl_namespace` int(11) NOT NULL DEFAULT 0,
+  `tl_title` varbinary(255) NOT NULL DEFAULT '',
+  `tl_from_namespace` int(11) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`tl_from`,`tl_namespace`,`tl_title`)
+) ENGINE=InnoDB DEFAULT CHARSET=binary;
+
+INSERT INTO `templatelinks` VALUES (5,10,'T',0);
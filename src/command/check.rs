@@ -0,0 +1,386 @@
+//! `check` subcommand: validates a task page or an on-site configuration page without running it.
+//!
+//! The page is read from a local file if one exists, so that a check needs no network access.
+//! Otherwise the target is taken as a title and fetched from the wiki.
+//! Every problem is printed on its own line, located by its path in the JSON document.
+//!
+//! The page is deserialized into the same types the bot uses, so a check accepts exactly what the bot accepts.
+
+use std::{fs, path::Path, str::FromStr, sync::Arc};
+
+use clap::ArgMatches;
+use mediawiki::hashmap;
+use serde_json::Value;
+
+use crate::apiservice::APIService;
+use crate::backend::WikiBackend;
+use crate::parser::{NamespaceTable, PLBotParserError};
+use crate::routine::{placeholders, types::{OutputFormat, SiteConfig, TaskInfo}};
+use super::{EXIT_CONFIG_FAILURE, EXIT_FAILURE};
+
+/// Placeholders substituted in `success.item`
+const ITEM_PLACEHOLDERS: &[&str] = &["$", "0", "1", "2", "@", "+", "wd"];
+/// Placeholders substituted in `success.before`, `success.between` and `success.after`
+const LIST_PLACEHOLDERS: &[&str] = &["$", "+"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageKind {
+    Task,
+    Config,
+}
+
+/// `location`: the path to the offending value, or a position in the source if it is not valid JSON
+struct Problem {
+    location: String,
+    message: String,
+}
+
+impl Problem {
+    fn new(location: &str, message: impl Into<String>) -> Self {
+        Problem { location: location.to_owned(), message: message.into() }
+    }
+}
+
+pub async fn run(args: &ArgMatches, api: &Arc<APIService>) -> i32 {
+    let target = args.value_of("target").unwrap();
+    // a local file is checked against the canonical namespace names only
+    let mut namespaces = NamespaceTable::canonical();
+    let content = if Path::new(target).is_file() {
+        match fs::read_to_string(target) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("error: cannot read \"{}\": {}", target, e);
+                return EXIT_CONFIG_FAILURE;
+            },
+        }
+    } else if let (Some(site), Some(profile)) = (args.value_of("site"), args.value_of("profile")) {
+        match fetch_page(api, args.value_of("login"), site, profile, target).await {
+            Ok(content) => {
+                if let Ok(names) = api.namespace_names().await {
+                    namespaces = NamespaceTable::new(names);
+                }
+                content
+            },
+            Err(e) => {
+                eprintln!("error: {}", e);
+                return EXIT_CONFIG_FAILURE;
+            },
+        }
+    } else {
+        eprintln!("error: \"{}\" is not a file, pass --site and --profile to fetch it from the wiki", target);
+        return EXIT_CONFIG_FAILURE;
+    };
+    let kind = match args.value_of("kind") {
+        Some("task") => Some(PageKind::Task),
+        Some("config") => Some(PageKind::Config),
+        _ => None,
+    };
+
+    let problems = check(&content, kind, &namespaces);
+    for problem in &problems {
+        println!("{}: {}: {}", target, problem.location, problem.message);
+    }
+    if problems.is_empty() {
+        println!("{}: ok", target);
+        0
+    } else {
+        EXIT_FAILURE
+    }
+}
+
+async fn fetch_page(api: &Arc<APIService>, login: Option<&str>, site: &str, profile: &str, title: &str) -> Result<String, String> {
+    let mut profile = super::read_profile(site, profile)?;
+    let login = if let Some(login) = login {
+        Some(super::read_login(login, &profile.login)?)
+    } else {
+        profile.assert = None;
+        None
+    };
+    api.setup(login, profile).await;
+    api.try_init().await;
+    let params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "prop".to_string() => "revisions".to_string(),
+        "titles".to_string() => title.to_string(),
+        "rvslots".to_string() => "*".to_string(),
+        "rvprop".to_string() => "content".to_string(),
+        "rvlimit".to_string() => "1".to_string()
+    ];
+    let page_content = api.get(&params).await.map_err(|e| format!("cannot fetch \"{}\": {}", title, e))?;
+    page_content["query"]["pages"][0]["revisions"][0]["slots"]["main"]["content"].as_str()
+        .map(|s| s.to_owned())
+        .ok_or_else(|| format!("cannot find the content of \"{}\"", title))
+}
+
+fn check(content: &str, kind: Option<PageKind>, namespaces: &NamespaceTable) -> Vec<Problem> {
+    let mut problems: Vec<Problem> = Vec::new();
+    let value: Value = match serde_json::from_str(content) {
+        Ok(value) => value,
+        Err(e) => {
+            problems.push(Problem::new(&format!("line {} column {}", e.line(), e.column()), e.to_string()));
+            return problems;
+        },
+    };
+    let kind = kind.unwrap_or_else(|| if value.get("taskdir").is_some() { PageKind::Config } else { PageKind::Task });
+    match kind {
+        PageKind::Task => {
+            match serde_json::from_str::<TaskInfo>(content) {
+                Ok(task) => {
+                    check_fields(&value, &task, &mut problems);
+                    check_task(&task, namespaces, &mut problems);
+                },
+                Err(e) => problems.push(Problem::new(&format!("line {} column {}", e.line(), e.column()), e.to_string())),
+            }
+        },
+        PageKind::Config => {
+            match serde_json::from_str::<SiteConfig>(content) {
+                Ok(config) => {
+                    check_fields(&value, &config, &mut problems);
+                    check_config(&config, &mut problems);
+                },
+                Err(e) => problems.push(Problem::new(&format!("line {} column {}", e.line(), e.column()), e.to_string())),
+            }
+        },
+    }
+    problems
+}
+
+/// Reports the fields of `value` that `parsed`, deserialized from it, has no place for. They are silently ignored when the bot runs.
+fn check_fields(value: &Value, parsed: &impl serde::Serialize, problems: &mut Vec<Problem>) {
+    // serializing back gives every field the type has, the optional ones included
+    match serde_json::to_value(parsed) {
+        Ok(known) => report_unknown_fields(value, &known, "", problems),
+        Err(e) => problems.push(Problem::new("", format!("cannot list known fields: {}", e))),
+    }
+}
+
+fn report_unknown_fields(value: &Value, known: &Value, path: &str, problems: &mut Vec<Problem>) {
+    match (value, known) {
+        (Value::Object(obj), Value::Object(known_obj)) => {
+            for (key, v) in obj {
+                let field_path = if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) };
+                if let Some(known_v) = known_obj.get(key) {
+                    report_unknown_fields(v, known_v, &field_path, problems);
+                } else {
+                    problems.push(Problem::new(&field_path, format!("unknown field `{}`, it is ignored", key)));
+                }
+            }
+        },
+        (Value::Array(items), Value::Array(known_items)) => {
+            for (idx, (item, known_item)) in items.iter().zip(known_items).enumerate() {
+                report_unknown_fields(item, known_item, &format!("{}[{}]", path, idx), problems);
+            }
+        },
+        _ => {},
+    }
+}
+
+fn check_task(task: &TaskInfo, namespaces: &NamespaceTable, problems: &mut Vec<Problem>) {
+    if let Err(e) = cron::Schedule::from_str(&task.cron) {
+        problems.push(Problem::new("cron", format!("invalid cron specification: {}", e)));
+    }
+    match crate::parser::parse_with_namespaces(&task.expr, namespaces) {
+        Ok(_) => {},
+        Err(PLBotParserError::Parse(errs)) => {
+            for e in errs {
+                problems.push(Problem::new("expr", e.to_string()));
+            }
+        },
+        Err(e) => problems.push(Problem::new("expr", e.to_string())),
+    }
+    if task.output.is_empty() {
+        problems.push(Problem::new("output", "no output page, the result goes nowhere"));
+    }
+    for (idx, output) in task.output.iter().enumerate() {
+        check_output(output, &format!("output[{}]", idx), problems);
+    }
+}
+
+fn check_output(output: &OutputFormat, path: &str, problems: &mut Vec<Problem>) {
+    if output.target.trim().is_empty() {
+        problems.push(Problem::new(&format!("{}.target", path), "target page is empty"));
+    }
+    check_placeholders(&output.success.item, ITEM_PLACEHOLDERS, &format!("{}.success.item", path), problems);
+    check_placeholders(&output.success.before, LIST_PLACEHOLDERS, &format!("{}.success.before", path), problems);
+    check_placeholders(&output.success.between, LIST_PLACEHOLDERS, &format!("{}.success.between", path), problems);
+    check_placeholders(&output.success.after, LIST_PLACEHOLDERS, &format!("{}.success.after", path), problems);
+    check_placeholders(&output.failure, &[], &format!("{}.failure", path), problems);
+    check_placeholders(&output.empty, &[], &format!("{}.empty", path), problems);
+}
+
+/// Reports `$x` sequences that are not substituted in this template, and a trailing `$`, which is dropped.
+fn check_placeholders(template: &str, allowed: &[&str], path: &str, problems: &mut Vec<Problem>) {
+    for (idx, name) in placeholders(template) {
+        match name {
+            Some(name) if allowed.contains(&name.as_str()) => {},
+            Some(name) if name == "w" && allowed.contains(&"wd") => {
+                problems.push(Problem::new(path, format!("unknown placeholder `$w` at character {}, did you mean `$wd`?", idx + 1)));
+            },
+            Some(name) => {
+                if allowed.is_empty() {
+                    problems.push(Problem::new(path, format!("`${}` at character {} is printed as is, no placeholder is substituted here", name, idx + 1)));
+                } else {
+                    let accepted: Vec<String> = allowed.iter().map(|a| format!("`${}`", a)).collect();
+                    problems.push(Problem::new(path, format!("unknown placeholder `${}` at character {}, expected one of {}", name, idx + 1, accepted.join(", "))));
+                }
+            },
+            None => {
+                if !allowed.is_empty() {
+                    problems.push(Problem::new(path, format!("trailing `$` at character {} is dropped, write `$$` for a dollar sign", idx + 1)));
+                }
+            },
+        }
+    }
+}
+
+fn check_config(config: &SiteConfig, problems: &mut Vec<Problem>) {
+    if config.taskdir.trim().is_empty() {
+        problems.push(Problem::new("taskdir", "task directory is empty, every page would be a task"));
+    }
+    if config.resultheader.trim().is_empty() {
+        problems.push(Problem::new("resultheader", "result header template is empty"));
+    }
+    if config.default.timeout == 0 {
+        problems.push(Problem::new("default.timeout", "a timeout of 0 seconds fails every query"));
+    }
+    if config.default.querylimit == 0 {
+        problems.push(Problem::new("default.querylimit", "a query limit of 0 makes every generator return nothing"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK: &str = r#"{
+        "activate": true,
+        "description": "test",
+        "expr": "incat(\"Category:X\").ns(0)",
+        "cron": "0 0 * * * * *",
+        "timeout": 60,
+        "output": [{
+            "target": "User:Bot/Result",
+            "failure": "failed",
+            "empty": "none",
+            "success": { "before": "$+ pages\n", "item": "* [[$0]] $wd", "between": "\n", "after": "" }
+        }]
+    }"#;
+
+    const CONFIG: &str = r#"{
+        "activate": true,
+        "taskdir": "User:Bot/Tasks",
+        "resultheader": "<!-- result -->",
+        "denyns": [2, 3],
+        "default": { "timeout": 60, "querylimit": 500 }
+    }"#;
+
+    fn check_task_json(content: &str) -> Vec<(String, String)> {
+        check(content, Some(PageKind::Task), &NamespaceTable::canonical()).into_iter().map(|p| (p.location, p.message)).collect()
+    }
+
+    /// `TASK` with `from` replaced by `to`
+    fn task_with(from: &str, to: &str) -> String {
+        assert!(TASK.contains(from));
+        TASK.replacen(from, to, 1)
+    }
+
+    #[test]
+    fn valid_pages_pass() {
+        assert!(check_task_json(TASK).is_empty());
+        assert!(check(CONFIG, None, &NamespaceTable::canonical()).is_empty());
+    }
+
+    #[test]
+    fn kind_is_guessed() {
+        // a config checked as a task lacks the task fields
+        assert!(!check(CONFIG, Some(PageKind::Task), &NamespaceTable::canonical()).is_empty());
+        assert!(check(CONFIG, None, &NamespaceTable::canonical()).is_empty());
+    }
+
+    #[test]
+    fn unknown_fields_are_located() {
+        let problems = check_task_json(&task_with("\"timeout\": 60,", "\"timeout\": 60, \"querylimt\": 10,"));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "querylimt");
+        let problems = check_task_json(&task_with("\"after\": \"\"", "\"after\": \"\", \"itme\": \"\""));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "output[0].success.itme");
+        let config = CONFIG.replacen("\"querylimit\": 500", "\"querylimit\": 500, \"fanuot\": 5", 1);
+        let problems = check(&config, None, &NamespaceTable::canonical());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].location, "default.fanuot");
+    }
+
+    #[test]
+    fn optional_fields_are_known() {
+        let task = task_with("\"timeout\": 60,", "\"timeout\": 60, \"eager\": null, \"querylimit\": 10, \"fanout\": 5, \"nocache\": true,");
+        assert!(check_task_json(&task).is_empty());
+    }
+
+    #[test]
+    fn type_errors_are_reported() {
+        let problems = check_task_json(&task_with("\"timeout\": 60", "\"timeout\": \"60\""));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].0.starts_with("line "));
+        let problems = check_task_json("{ \"activate\": ");
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].0.starts_with("line 1"));
+    }
+
+    #[test]
+    fn cron_and_expr_are_checked() {
+        let problems = check_task_json(&task_with("0 0 * * * * *", "every day"));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "cron");
+        let problems = check_task_json(&task_with(".ns(0)", ".ns(0) + )"));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "expr");
+    }
+
+    #[test]
+    fn wikidata_placeholder_is_checked() {
+        assert!(check_task_json(&task_with("$wd", "$$wd")).is_empty());
+        let problems = check_task_json(&task_with("$wd", "$w"));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "output[0].success.item");
+        assert!(problems[0].1.contains("did you mean `$wd`"));
+        // `$wd` is only substituted in items
+        let problems = check_task_json(&task_with("$+ pages", "$wd pages"));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "output[0].success.before");
+    }
+
+    #[test]
+    fn placeholders_are_checked() {
+        let problems = check_task_json(&task_with("[[$0]]", "[[$0]] $x"));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].1.contains("unknown placeholder `$x`"));
+        let problems = check_task_json(&task_with("\"after\": \"\"", "\"after\": \"$\""));
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].1.contains("trailing `$`"));
+        let problems = check_task_json(&task_with("\"failed\"", "\"failed $0\""));
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, "output[0].failure");
+        let problems = check_task_json(&task_with("[[$0]]", "[[$0]] $w"));
+        assert!(problems[0].1.contains("did you mean `$wd`?"));
+    }
+
+    #[test]
+    fn placeholders_are_scanned() {
+        let names = |template: &str| placeholders(template).into_iter().map(|(idx, name)| (idx, name.unwrap_or_default())).collect::<Vec<(usize, String)>>();
+        assert_eq!(names("[[$0]] $wd $w"), [(2, String::from("0")), (7, String::from("wd")), (11, String::from("w"))]);
+        // `$$wd` is a dollar sign followed by `wd`, not the Wikidata item
+        assert_eq!(names("$$wd"), [(0, String::from("$"))]);
+        assert_eq!(names("$$$wd"), [(0, String::from("$")), (2, String::from("wd"))]);
+        assert_eq!(names("a $"), [(2, String::new())]);
+        assert!(names("no placeholder").is_empty());
+    }
+
+    #[test]
+    fn config_values_are_checked() {
+        let config = CONFIG.replacen("\"timeout\": 60", "\"timeout\": 0", 1);
+        let problems = check(&config, None, &NamespaceTable::canonical());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].location, "default.timeout");
+    }
+}
@@ -0,0 +1,40 @@
+//! One-shot subcommands, run instead of the daemon.
+//!
+//! A subcommand returns the exit code of the process instead of panicking on bad input.
+
+use std::{collections::HashMap, fs};
+
+use serde_json::Value;
+
+use crate::types::{LoginCredential, SiteProfile};
+
+pub mod check;
+pub mod query;
+
+/// Exit code when the input is read but found wrong: it cannot be parsed, solved or validated
+pub(crate) const EXIT_FAILURE: i32 = 1;
+/// Exit code when the command line or the config files are wrong
+pub(crate) const EXIT_CONFIG_FAILURE: i32 = 2;
+
+/// Reads a site profile from the site information file
+pub(crate) fn read_profile(site: &str, profile: &str) -> Result<SiteProfile, String> {
+    let sites = fs::read_to_string(site).map_err(|e| format!("cannot open site information file: {}", e))?;
+    let sites: Value = serde_json::from_str(&sites).map_err(|e| format!("cannot parse site information file: {}", e))?;
+    let profile_value = sites.get(profile).ok_or_else(|| format!("cannot find site profile \"{}\"", profile))?;
+    serde_json::from_value(profile_value.clone()).map_err(|e| format!("cannot parse site profile \"{}\": {}", profile, e))
+}
+
+/// Reads every site profile from the site information file
+pub(crate) fn read_sites(site: &str) -> Result<HashMap<String, SiteProfile>, String> {
+    let sites = fs::read_to_string(site).map_err(|e| format!("cannot open site information file: {}", e))?;
+    let sites: Value = serde_json::from_str(&sites).map_err(|e| format!("cannot parse site information file: {}", e))?;
+    Ok(SiteProfile::all_from_value(&sites))
+}
+
+/// Reads a login credential from the login file
+pub(crate) fn read_login(login: &str, name: &str) -> Result<LoginCredential, String> {
+    let logins = fs::read_to_string(login).map_err(|e| format!("cannot open login file: {}", e))?;
+    let logins: Value = serde_json::from_str(&logins).map_err(|e| format!("cannot parse login file: {}", e))?;
+    let login_value = logins.get(name).ok_or_else(|| format!("cannot find login credential \"{}\"", name))?;
+    serde_json::from_value(login_value.clone()).map_err(|e| format!("cannot parse login credential \"{}\": {}", name, e))
+}
@@ -0,0 +1,197 @@
+//! `query` subcommand: evaluates an expression once, and prints the resulting pages to stdout.
+//!
+//! Failures are printed to stderr, or as a JSON object to stdout if the output format is JSON.
+
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::ArgMatches;
+use mediawiki::title::Title;
+use serde_json::{json, Value};
+
+use crate::apiservice::APIService;
+use crate::backend::{DumpLoader, MemoryBackend, WikiBackend};
+use crate::dbservice::DBService;
+use crate::parser::{NamespaceTable, PLBotParserError, Query, Span};
+use crate::solver::{SolveError, SolverConfig};
+use super::{EXIT_CONFIG_FAILURE, EXIT_FAILURE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Plain,
+    Json,
+    Tsv,
+}
+
+pub async fn run(args: &ArgMatches, api: &Arc<APIService>) -> i32 {
+    let format = match args.value_of("format") {
+        Some("json") => OutputFormat::Json,
+        Some("tsv") => OutputFormat::Tsv,
+        _ => OutputFormat::Plain,
+    };
+    let config = match solver_config(args) {
+        Ok(config) => config,
+        Err(e) => {
+            report_error(format, json!({ "kind": "config", "message": e }), &e);
+            return EXIT_CONFIG_FAILURE;
+        },
+    };
+    if let Some(paths) = args.values_of("dump") {
+        let paths: Vec<&Path> = paths.map(Path::new).collect();
+        let wiki = match load_dumps(&paths) {
+            Ok(wiki) => wiki,
+            Err(e) => {
+                report_error(format, json!({ "kind": "config", "message": e }), &e);
+                return EXIT_CONFIG_FAILURE;
+            },
+        };
+        return evaluate(&wiki, None, args.value_of("expr").unwrap(), format, &config).await;
+    }
+    let mut profile = match super::read_profile(args.value_of("site").unwrap(), args.value_of("profile").unwrap()) {
+        Ok(profile) => profile,
+        Err(e) => {
+            report_error(format, json!({ "kind": "config", "message": e }), &e);
+            return EXIT_CONFIG_FAILURE;
+        },
+    };
+    let login = if let Some(login) = args.value_of("login") {
+        match super::read_login(login, &profile.login) {
+            Ok(login) => Some(login),
+            Err(e) => {
+                report_error(format, json!({ "kind": "config", "message": e }), &e);
+                return EXIT_CONFIG_FAILURE;
+            },
+        }
+    } else {
+        // an anonymous user cannot satisfy any `assert`
+        profile.assert = None;
+        None
+    };
+
+    // the other wikis `site()` may refer to
+    if let Ok(sites) = super::read_sites(args.value_of("site").unwrap()) {
+        api.set_sites(sites).await;
+    }
+    // like the daemon, use the replica database of the profile if it is reachable
+    let db = match profile.db.as_deref() {
+        Some(db_loc) => match DBService::connect(db_loc).await {
+            Ok(db) => Some(db),
+            Err(e) => {
+                eprintln!("warning: cannot connect to database, using API only: {}", e);
+                None
+            },
+        },
+        None => None,
+    };
+    api.setup(login, profile).await;
+    api.try_init().await;
+    evaluate(api.as_ref(), db.as_ref(), args.value_of("expr").unwrap(), format, &config).await
+}
+
+fn load_dumps(paths: &[&Path]) -> Result<MemoryBackend, String> {
+    let mut loader = DumpLoader::new();
+    loader.load_files(paths).map_err(|e| format!("cannot load dumps: {}", e))?;
+    Ok(loader.finish())
+}
+
+/// Parses and solves `expr` on `backend`, or on `db` if present, then prints the pages. Returns the exit code.
+async fn evaluate(backend: &dyn WikiBackend, db: Option<&DBService>, expr: &str, format: OutputFormat, config: &SolverConfig) -> i32 {
+    // namespace names in the expression are those of the wiki
+    let namespaces = match backend.namespace_names().await {
+        Ok(names) => NamespaceTable::new(names),
+        Err(_) => NamespaceTable::canonical(),
+    };
+    let query = match crate::parser::parse_with_namespaces(expr, &namespaces) {
+        Ok(query) => query,
+        Err(e) => {
+            report_error(format, parse_error_to_json(&e), &e.to_string());
+            return EXIT_FAILURE;
+        },
+    };
+    let titles = match solve(backend, db, &query, config).await {
+        Ok(titles) => titles,
+        Err(e) => {
+            report_error(format, json!({ "kind": "solve", "message": e.to_string() }), &e.to_string());
+            return EXIT_FAILURE;
+        },
+    };
+    let mut titles = Vec::from_iter(titles);
+    titles.sort_by(|a, b| (a.namespace_id(), a.pretty()).cmp(&(b.namespace_id(), b.pretty())));
+    print_titles(backend, format, &titles).await;
+    0
+}
+
+/// Solves with the database if there is one, falling back to the API if the database fails, as `QueryExecutor` does
+async fn solve(backend: &dyn WikiBackend, db: Option<&DBService>, query: &Query, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+    if let Some(db) = db {
+        match crate::solver::solve_db(backend, db, query, config).await {
+            Err(SolveError::DBService(e)) => eprintln!("warning: database failure, falling back to API: {}", e),
+            result => return result,
+        }
+    }
+    crate::solver::solve_api(backend, query, config).await
+}
+
+fn solver_config(args: &ArgMatches) -> Result<SolverConfig, String> {
+    let querylimit = args.value_of_t::<i64>("limit").map_err(|e| e.to_string())?;
+    let fanout = args.value_of_t::<i64>("fanout").map_err(|e| e.to_string())?;
+    let parallelism = args.value_of_t::<usize>("parallelism").map_err(|e| e.to_string())?;
+    Ok(SolverConfig { querylimit, fanout, parallelism })
+}
+
+async fn print_titles(backend: &dyn WikiBackend, format: OutputFormat, titles: &[Title]) {
+    let mut names: Vec<String> = Vec::new();
+    for t in titles {
+        names.push(backend.full_pretty(t).await.ok().flatten().unwrap_or_else(|| t.pretty().to_owned()));
+    }
+    match format {
+        OutputFormat::Plain => {
+            for name in names {
+                println!("{}", name);
+            }
+        },
+        OutputFormat::Json => {
+            let items: Vec<Value> = titles.iter().zip(names).map(|(t, name)| json!({ "ns": t.namespace_id(), "title": name })).collect();
+            println!("{}", Value::Array(items));
+        },
+        OutputFormat::Tsv => {
+            println!("ns\ttitle");
+            for (t, name) in titles.iter().zip(names) {
+                println!("{}\t{}", t.namespace_id(), name);
+            }
+        },
+    }
+}
+
+fn report_error(format: OutputFormat, error: Value, message: &str) {
+    if format == OutputFormat::Json {
+        println!("{}", json!({ "error": error }));
+    } else {
+        eprintln!("error: {}", message);
+    }
+}
+
+fn span_to_json(span: &Span) -> Value {
+    json!({
+        "start": { "offset": span.start.offset, "line": span.start.line, "column": span.start.column },
+        "end": { "offset": span.end.offset, "line": span.end.line, "column": span.end.column },
+    })
+}
+
+fn parse_error_to_json(e: &PLBotParserError) -> Value {
+    match e {
+        PLBotParserError::Parse(errs) => {
+            let errors: Vec<Value> = errs.iter().map(|err| json!({
+                "message": err.to_string(),
+                "span": span_to_json(&err.span),
+                "token": err.token,
+                "expected": err.expected,
+            })).collect();
+            json!({ "kind": "parse", "errors": errors })
+        },
+        PLBotParserError::Semantic(message, span) => {
+            json!({ "kind": "semantic", "errors": [{ "message": message, "span": span_to_json(span) }] })
+        },
+    }
+}
@@ -0,0 +1,96 @@
+//! DB Service holds the connection pool to the replica database of a wiki.
+//!
+//! Queries are written against the MediaWiki schema, using only SQL that MariaDB and SQLite both understand,
+//! so that a local SQLite file loaded with the same tables can stand in for the replicas.
+
+use mediawiki::{api::NamespaceID, title::Title};
+use sqlx::{any::{AnyPoolOptions, AnyRow}, AnyPool, Row};
+use tracing::{event, Level};
+
+/// The maximum number of connections to the database
+const MAX_CONNECTIONS: u32 = 4;
+
+#[derive(Debug)]
+pub enum DBServiceError {
+    Config(String),
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for DBServiceError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl core::fmt::Display for DBServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(e) => f.write_fmt(format_args!("cannot resolve database connection: {}", e)),
+            Self::Database(e) => e.fmt(f),
+        }
+    }
+}
+
+/// A value bound to a `?` placeholder
+#[derive(Debug, Clone)]
+pub enum SQLParam {
+    Int(i64),
+    Text(String),
+}
+
+#[derive(Debug)]
+pub struct DBService {
+    pool: AnyPool,
+}
+
+impl DBService {
+
+    /// Connects to the database described by `db`.
+    ///
+    /// `db` is either a connection URL (`mysql://...`, `sqlite://...`),
+    /// or the name of a Toolforge replica database such as `enwiki_p`, whose credentials are read from `replica.my.cnf`.
+    pub async fn connect(db: &str) -> Result<Self, DBServiceError> {
+        sqlx::any::install_default_drivers();
+        let url = if db.contains("://") {
+            db.to_owned()
+        } else {
+            toolforge::connection_info!(db, ANALYTICS).map_err(|e| DBServiceError::Config(e.to_string()))?.to_string()
+        };
+        event!(Level::INFO, "connecting to database");
+        let pool = AnyPoolOptions::new()
+            .max_connections(MAX_CONNECTIONS)
+            .connect(&url)
+            .await?;
+        Ok(DBService { pool })
+    }
+
+    async fn fetch(&self, sql: &str, params: &[SQLParam]) -> Result<Vec<AnyRow>, DBServiceError> {
+        let mut query = sqlx::query(sql);
+        for param in params {
+            query = match param {
+                SQLParam::Int(i) => query.bind(*i),
+                SQLParam::Text(s) => query.bind(s.clone()),
+            };
+        }
+        Ok(query.fetch_all(&self.pool).await?)
+    }
+
+    /// Runs a query whose first two columns are a namespace and a title in database form
+    pub async fn fetch_titles(&self, sql: &str, params: &[SQLParam]) -> Result<Vec<Title>, DBServiceError> {
+        let rows = self.fetch(sql, params).await?;
+        rows.iter().map(|row| row_to_title(row, 0)).collect()
+    }
+
+    /// Runs a query whose first four columns are two pairs of namespace and title in database form
+    pub async fn fetch_title_pairs(&self, sql: &str, params: &[SQLParam]) -> Result<Vec<(Title, Title)>, DBServiceError> {
+        let rows = self.fetch(sql, params).await?;
+        rows.iter().map(|row| Ok((row_to_title(row, 0)?, row_to_title(row, 2)?))).collect()
+    }
+
+}
+
+fn row_to_title(row: &AnyRow, start: usize) -> Result<Title, DBServiceError> {
+    let ns: i64 = row.try_get(start)?;
+    let title: String = row.try_get(start + 1)?;
+    Ok(Title::new(&Title::underscores_to_spaces(&title), ns as NamespaceID))
+}
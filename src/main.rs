@@ -1,32 +1,78 @@
-use std::fs;
-use lazy_static::lazy_static;
+use std::{collections::HashMap, fs, sync::{Arc, OnceLock}};
 use apiservice::APIService;
+use backend::{CachedBackend, WikiBackend};
+use dbservice::DBService;
 use routine::TaskFinder;
 use serde_json::Value;
-use tracing::{span, event, Level};
-use tracing_subscriber::{fmt::format::FmtSpan, filter, prelude::*};
+use tracing::{span, event, Instrument, Level};
+use tracing_subscriber::{fmt::format::FmtSpan, filter, prelude::*, registry::LookupSpan, Layer};
 
+mod backend;
+mod command;
 mod parser;
 mod solver;
 mod routine;
 
 mod arg;
 mod apiservice;
+mod dbservice;
+mod scheduler;
 mod types;
 
-lazy_static! {
-    static ref API_SERVICE: APIService = APIService::new();
-}
-
 /// The main function parses command line arguments, and extracts important information from config files.
-/// API_SERVICE is initialized, and TASK_FINDER is fired
+/// Each profile gets its own API service and task finder, all running in this process.
+/// A subcommand runs once instead, and its result becomes the exit code.
 #[tokio::main]
 async fn main() {
     let args = arg::build_argparse().get_matches();
+    if let Some((name, sub_args)) = args.subcommand() {
+        tracing_subscriber::registry().with(stderr_layer()).init();
+        let api_service = Arc::new(APIService::new());
+        let code = match name {
+            "query" => command::query::run(sub_args, &api_service).await,
+            "check" => command::check::run(sub_args, &api_service).await,
+            _ => unreachable!("unknown subcommand {}", name),
+        };
+        std::process::exit(code);
+    }
+
+    // the log files depend on the profiles in use, so the site information file is read while logging to stderr only
+    let (sites, profile_names) = tracing::subscriber::with_default(
+        tracing_subscriber::registry().with(stderr_layer()),
+        || read_site_info(&args),
+    );
 
-    // set up subscriber
-    let file_appender = tracing_appender::rolling::daily(format!("logs/{}", args.value_of("profile").unwrap()), "plbot.log");
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    // set up subscriber, each profile logs to its own directory
+    // the root span of each profile, events outside any of them are written to every log
+    let roots: Arc<Vec<OnceLock<span::Id>>> = Arc::new(profile_names.iter().map(|_| OnceLock::new()).collect());
+    let mut guards = Vec::new();
+    let mut layers = Vec::new();
+    for (idx, name) in profile_names.iter().enumerate() {
+        let file_appender = tracing_appender::rolling::daily(format!("logs/{}", name), "plbot.log");
+        let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+        guards.push(guard);
+        let roots = roots.clone();
+        layers.push(
+            tracing_subscriber::fmt::layer()
+                .with_writer(non_blocking)
+                .with_ansi(false)
+                .with_span_events(FmtSpan::NONE)
+                .with_filter(filter::dynamic_filter_fn(move |meta, cx| {
+                    // spans are always enabled, so that events can be traced back to their profile
+                    if meta.is_span() {
+                        return true;
+                    }
+                    if *meta.level() > Level::DEBUG {
+                        return false;
+                    }
+                    let root = cx.lookup_current().and_then(|span| {
+                        span.scope().find_map(|s| roots.iter().position(|root| root.get() == Some(&s.id())))
+                    });
+                    root.is_none_or(|root| root == idx)
+                }))
+                .boxed()
+        );
+    }
     tracing_subscriber::registry()
     /*
         .with(
@@ -35,50 +81,41 @@ async fn main() {
                 .with_filter(filter::LevelFilter::INFO)
         )
     */
-        .with(
-            tracing_subscriber::fmt::layer()
-                .with_writer(non_blocking)
-                .with_ansi(false)
-                .with_span_events(FmtSpan::NONE)
-                .with_filter(filter::LevelFilter::DEBUG)
-        )
+        .with(layers)
         .init();
 
-    let (profile, login) = span!(target: "main", Level::INFO, "bootstrap").in_scope(|| {
-        event!(Level::INFO, "reading config files");
-        event!(Level::DEBUG, "reading site information file");
-        let sites = fs::read_to_string(args.value_of("site").unwrap()).expect("cannot open site information file");
-        event!(Level::DEBUG, "parsing site information file");
-        let sites: Value = serde_json::from_str(&sites).expect("cannot parse site information file");
-
-        let profile = args.value_of("profile").unwrap();
-        event!(Level::DEBUG, "fetching profile \"{}\"", profile);
-        let profile: types::SiteProfile = serde_json::from_value(sites[profile].clone()).expect("cannot find specified site profile");
+    // profiles logging in with the same account share an HTTP client
+    let mut clients: HashMap<String, reqwest::Client> = HashMap::new();
+    // the services of every profile, kept until shutdown
+    let mut services = Vec::new();
+    for (idx, name) in profile_names.iter().enumerate() {
+        let profile_span = span!(target: "main", Level::INFO, "profile", profile = name.as_str());
+        _ = roots[idx].set(profile_span.id().expect("profile span is disabled"));
 
-        event!(Level::DEBUG, "reading login file");
-        let login = fs::read_to_string(args.value_of("login").unwrap()).expect("cannot open login file");
-        event!(Level::DEBUG, "parsing login file");
-        let login: Value = serde_json::from_str(&login).expect("cannot parse login file.");
-        event!(Level::DEBUG, "fetching login credential \"{}\"", &profile.login);
-        let login: types::LoginCredential = serde_json::from_value(login[&profile.login].clone()).expect("cannot find specified site profile");
+        let (profile, login) = span!(target: "main", parent: &profile_span, Level::INFO, "bootstrap").in_scope(|| {
+            event!(Level::INFO, "reading config files");
+            event!(Level::DEBUG, "fetching profile \"{}\"", name);
+            let profile: types::SiteProfile = serde_json::from_value(sites[name].clone()).expect("cannot find specified site profile");
 
-        event!(Level::INFO, "read config files successful");
-        (profile, login)
-    });
+            event!(Level::DEBUG, "reading login file");
+            let login = fs::read_to_string(args.value_of("login").unwrap()).expect("cannot open login file");
+            event!(Level::DEBUG, "parsing login file");
+            let login: Value = serde_json::from_str(&login).expect("cannot parse login file.");
+            event!(Level::DEBUG, "fetching login credential \"{}\"", &profile.login);
+            let login: types::LoginCredential = serde_json::from_value(login[&profile.login].clone()).expect("cannot find specified site profile");
 
-    let config_loc = profile.config.to_owned();
+            event!(Level::INFO, "read config files successful");
+            (profile, login)
+        });
 
-    lazy_static! {
-        static ref TASK_FINDER: TaskFinder = TaskFinder::new();
+        let client = clients.entry(profile.login.clone())
+            .or_insert_with(|| reqwest::Client::builder().cookie_store(true).build().expect("cannot build HTTP client"))
+            .clone();
+        let api_service = Arc::new(APIService::new());
+        let task_finder = start_profile(api_service.clone(), profile, login, client, types::SiteProfile::all_from_value(&sites)).instrument(profile_span).await;
+        services.push((api_service, task_finder));
     }
 
-    API_SERVICE.setup(login, profile).await;
-    API_SERVICE.try_init().await;
-    API_SERVICE.start().await;
-
-    TASK_FINDER.set_config_location(&config_loc).await;
-    TASK_FINDER.start().await;
-
     let ctrl_c_res = tokio::signal::ctrl_c().await;
     match ctrl_c_res {
         Ok(()) => event!(Level::INFO, "ctrl-c detected"),
@@ -86,3 +123,74 @@ async fn main() {
     }
 
 }
+
+/// Logs warnings and errors to stderr
+fn stderr_layer<S: tracing::Subscriber + for<'a> LookupSpan<'a>>() -> impl Layer<S> {
+    tracing_subscriber::fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_span_events(FmtSpan::NONE)
+        .with_filter(filter::LevelFilter::WARN)
+}
+
+/// Reads the site information file, and the names of the profiles to run. Exits if either cannot be read
+fn read_site_info(args: &clap::ArgMatches) -> (Value, Vec<String>) {
+    let sites = fs::read_to_string(args.value_of("site").unwrap())
+        .map_err(|e| format!("cannot open site information file: {}", e))
+        .and_then(|sites| serde_json::from_str::<Value>(&sites).map_err(|e| format!("cannot parse site information file: {}", e)));
+    let sites = match sites {
+        Ok(sites) if sites.is_object() => sites,
+        Ok(_) => exit_config_failure("site information file is not an object"),
+        Err(e) => exit_config_failure(&e),
+    };
+    let profile_names: Vec<String> = if args.is_present("all-profiles") {
+        let mut names: Vec<String> = sites.as_object().unwrap().keys().cloned().collect();
+        names.sort();
+        names
+    } else {
+        args.values_of("profile").unwrap().map(|name| name.to_owned()).collect()
+    };
+    (sites, profile_names)
+}
+
+fn exit_config_failure(message: &str) -> ! {
+    event!(Level::ERROR, "{}", message);
+    std::process::exit(command::EXIT_CONFIG_FAILURE);
+}
+
+/// Starts `api_service` and the task finder of a profile, and returns the task finder
+async fn start_profile(api_service: Arc<APIService>, profile: types::SiteProfile, login: types::LoginCredential, client: reqwest::Client, sites: HashMap<String, types::SiteProfile>) -> Arc<TaskFinder> {
+    let config_loc = profile.config.to_owned();
+    let db_loc = profile.db.to_owned();
+    let cache = profile.cache.to_owned();
+
+    api_service.set_client(client).await;
+    api_service.set_sites(sites).await;
+    api_service.setup(Some(login), profile).await;
+    api_service.try_init().await;
+    api_service.start().await;
+
+    let db = if let Some(db_loc) = db_loc {
+        match DBService::connect(&db_loc).await {
+            Ok(db) => Some(Arc::new(db)),
+            Err(err) => {
+                event!(Level::WARN, error = ?err, "cannot connect to database, using API only");
+                None
+            },
+        }
+    } else {
+        None
+    };
+
+    // all tasks of the profile share one cache
+    let backend: Arc<dyn WikiBackend> = if let Some(cache) = cache {
+        Arc::new(CachedBackend::new(api_service.clone(), &cache))
+    } else {
+        api_service.clone()
+    };
+    let task_finder = Arc::new(TaskFinder::new(backend));
+
+    task_finder.set_config_location(&config_loc).await;
+    task_finder.set_db(db).await;
+    task_finder.start().await;
+    task_finder
+}
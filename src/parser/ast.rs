@@ -1,17 +1,46 @@
 //! This file lists the data structures used in
 //! abstract syntax tree (AST) building.
 
+use std::collections::HashSet;
+
 use mediawiki::api::NamespaceID;
-use super::ir::{DepthNum, RedirectFilterStrategy};
+use super::ir::{ActivitySource, Comparison, DepthNum, RedirectFilterStrategy};
+use super::error::{Span, PLBotParserError};
+
+/// A whole query: a list of `let` bindings followed by the expression to evaluate.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Query {
+    pub bindings: Vec<Binding>,
+    pub body: Box<Expr>,
+}
+
+/// `let name = value;`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Binding {
+    pub name: Spanned<String>,
+    pub value: Box<Expr>,
+}
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) enum Expr {
     // The ultimate primitive
     Page(Vec<String>),
+    // Reference to a `let` binding
+    Var(Spanned<String>),
     // Generative functions
     Unary(UnaryOpcode, Box<Expr>),
+    // Evaluated on another wiki, named by its site profile
+    Site(String, Box<Expr>),
+    // Full-text search
+    Search(String),
+    // Special page report
+    Special(Spanned<String>),
+    // Pages with a page property
+    WithProp(String),
+    // Recent activity, such as new pages or log entries
+    Activity(ActivitySource, Vec<Spanned<ActivityArg>>),
     // Constrained
-    Constrained(Box<Expr>, Vec<Constraint>),
+    Constrained(Box<Expr>, Vec<Spanned<Constraint>>),
     // Set arithmetics
     Binary(Box<Expr>, BinaryOpcode, Box<Expr>),
 }
@@ -24,6 +53,12 @@ pub(crate) enum UnaryOpcode {
     InCategory,
     Toggle,
     Prefix,
+    Categories,
+    Templates,
+    Images,
+    FileUsage,
+    Redirects,
+    Subpages,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -36,10 +71,219 @@ pub(crate) enum BinaryOpcode {
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub(crate) enum Constraint {
-    Ns(Vec<NamespaceID>),
+    Ns(Vec<Spanned<NsItem>>),
     Depth(DepthNum),
     Redir(RedirectFilterStrategy),
     DirectLink(bool),
     ResolveRedir(bool),
     Limit(i64),
+    // the pattern is compiled when the constraints are constructed
+    Match { pattern: String, full: bool, negated: bool },
+    Size(Comparison, i64),
+    // the times are parsed when the constraints are constructed
+    Touched(Vec<(TimeBound, String)>),
+    Created(Vec<(TimeBound, String)>),
+    // action and level
+    Protected(Option<String>, Option<String>),
+    ContentModel(String),
+    // the pattern is compiled when the constraints are constructed
+    Contains(String),
+    // parameter name and value pattern, compiled when the constraints are constructed
+    Param(String, String),
+    NoParam(String),
+    // property name, and whether pages must have it
+    PageProp(String, bool),
+    // `has=true` or `has=false`, checked when the constraints are constructed
+    Wikidata(String, String),
+}
+
+/// Which side of a time span a bound of `.touched(...)` or `.created(...)` is on
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum TimeBound {
+    Before,
+    After,
+}
+
+/// One argument of an activity operator, such as `since="-7d"`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum ActivityArg {
+    // the times are parsed when the instruction is generated
+    Since(String),
+    Until(String),
+    // resolved by `NamespaceTable` like an item of `.ns(...)`
+    Ns(Spanned<NsItem>),
+}
+
+/// One item of `.ns(...)`, resolved by `NamespaceTable`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct NsItem {
+    pub spec: NsSpec,
+    // `!` in front: the namespaces are excluded
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum NsSpec {
+    Id(NamespaceID),
+    // both ends included
+    Range(NamespaceID, NamespaceID),
+    Name(String),
+}
+
+/// A node together with its location in the query source
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+/// Locates the first constraint in `constraints` that satisfies `pred`.
+/// If there is none, returns the span covering the whole list.
+pub(crate) fn span_of(constraints: &[Spanned<Constraint>], pred: impl Fn(&Constraint) -> bool) -> Span {
+    if let Some(c) = constraints.iter().find(|c| pred(&c.node)) {
+        c.span
+    } else {
+        let first = constraints.first().map(|c| c.span).expect("constraint list is never empty");
+        constraints.iter().fold(first, |acc, c| acc.to(&c.span))
+    }
+}
+
+impl Expr {
+    /// Collects every name referenced in this expression.
+    pub(crate) fn collect_vars<'a>(&'a self, out: &mut Vec<&'a Spanned<String>>) {
+        match self {
+            Expr::Page(_) | Expr::Search(_) | Expr::Special(_) | Expr::WithProp(_) | Expr::Activity(..) => {},
+            Expr::Var(v) => out.push(v),
+            Expr::Unary(_, e) => e.collect_vars(out),
+            Expr::Site(_, e) => e.collect_vars(out),
+            Expr::Constrained(e, _) => e.collect_vars(out),
+            Expr::Binary(l, _, r) => {
+                l.collect_vars(out);
+                r.collect_vars(out);
+            },
+        }
+    }
+}
+
+impl Query {
+    /// Checks that every name is bound exactly once, and only used after it is bound.
+    /// A binding cannot refer to itself or to any binding after it.
+    pub(crate) fn check_scope(&self) -> Result<(), PLBotParserError> {
+        let mut bound: HashSet<&str> = HashSet::new();
+        for binding in &self.bindings {
+            check_vars_bound(&binding.value, &bound)?;
+            if !bound.insert(&binding.name.node) {
+                return Err(PLBotParserError::Semantic(format!("name `{}` is already bound", binding.name.node), binding.name.span));
+            }
+        }
+        check_vars_bound(&self.body, &bound)
+    }
+
+    /// Returns the bindings that the body depends on, directly or through other bindings, in their original order.
+    pub(crate) fn used_bindings(&self) -> Vec<&Binding> {
+        let mut vars = Vec::new();
+        self.body.collect_vars(&mut vars);
+        let mut used: HashSet<&str> = vars.iter().map(|v| v.node.as_str()).collect();
+        let mut result: Vec<&Binding> = Vec::new();
+        for binding in self.bindings.iter().rev() {
+            if used.contains(binding.name.node.as_str()) {
+                let mut vars = Vec::new();
+                binding.value.collect_vars(&mut vars);
+                used.extend(vars.iter().map(|v| v.node.as_str()));
+                result.push(binding);
+            }
+        }
+        result.reverse();
+        result
+    }
+}
+
+fn check_vars_bound(expr: &Expr, bound: &HashSet<&str>) -> Result<(), PLBotParserError> {
+    let mut vars = Vec::new();
+    expr.collect_vars(&mut vars);
+    if let Some(v) = vars.iter().find(|v| !bound.contains(v.node.as_str())) {
+        Err(PLBotParserError::Semantic(format!("name `{}` is not bound", v.node), v.span))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::{parse_with_namespaces, NamespaceTable, PLBotParserError};
+
+    /// Returns the message of the semantic error and the source text it points at
+    fn semantic_error(src: &str) -> (String, &str) {
+        match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Semantic(msg, span)) => (msg, &src[span.start.offset..span.end.offset]),
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bound_names_can_be_used() {
+        let src = "let a = incat(\"Category:A\"); let b = a & linkto(\"B\"); b + a";
+        assert!(parse_with_namespaces(src, &NamespaceTable::canonical()).is_ok());
+    }
+
+    #[test]
+    fn unbound_name_is_rejected() {
+        let (msg, at) = semantic_error("let a = incat(\"A\"); a + b");
+        assert_eq!(msg, "name `b` is not bound");
+        assert_eq!(at, "b");
+    }
+
+    #[test]
+    fn duplicate_binding_is_rejected() {
+        let (msg, at) = semantic_error("let a = incat(\"A\"); let a = incat(\"B\"); a");
+        assert_eq!(msg, "name `a` is already bound");
+        assert_eq!(at, "a");
+    }
+
+    #[test]
+    fn binding_cannot_refer_to_itself() {
+        let (msg, _) = semantic_error("let a = a + incat(\"A\"); a");
+        assert_eq!(msg, "name `a` is not bound");
+    }
+
+    #[test]
+    fn binding_cannot_refer_to_a_later_one() {
+        let (msg, at) = semantic_error("let a = b; let b = incat(\"A\"); a");
+        assert_eq!(msg, "name `b` is not bound");
+        assert_eq!(at, "b");
+    }
+
+    #[test]
+    fn fetching_constraint_on_bound_name_is_rejected() {
+        let (msg, at) = semantic_error("let a = linkto(\"A\"); a.noredir()");
+        assert_eq!(msg, "constraint cannot be applied to a bound name, apply it inside the binding instead");
+        assert_eq!(at, ".noredir()");
+    }
+
+    #[test]
+    fn limit_on_bound_name_is_rejected() {
+        let (msg, at) = semantic_error("let a = incat(\"A\"); a.ns(0).limit(10)");
+        assert_eq!(msg, "constraint cannot be applied to a bound name, apply it inside the binding instead");
+        assert_eq!(at, ".limit(10)");
+    }
+
+    #[test]
+    fn filtering_constraint_on_bound_name_is_accepted() {
+        assert!(parse_with_namespaces("let a = incat(\"A\"); a.ns(0) + a.ns(14)", &NamespaceTable::canonical()).is_ok());
+    }
+
+    #[test]
+    fn bound_name_cannot_be_used_inside_site() {
+        let (msg, at) = semantic_error("let a = incat(\"A\"); site(\"meta\", a)");
+        assert_eq!(msg, "name `a` cannot be used inside `site()`");
+        assert_eq!(at, "a");
+    }
+
+    #[test]
+    fn limit_on_site_is_rejected() {
+        let (msg, at) = semantic_error("site(\"meta\", incat(\"A\")).ns(0).limit(10)");
+        assert_eq!(msg, "constraint cannot be applied to `site()`, apply it inside instead");
+        assert_eq!(at, ".limit(10)");
+        assert!(parse_with_namespaces("site(\"meta\", incat(\"A\").limit(10)).ns(0)", &NamespaceTable::canonical()).is_ok());
+    }
 }
@@ -2,16 +2,29 @@
 //! into generic Intermediate Representation (IR)
 //! defined in `plbot_base`
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
-use super::{ast::Expr, ast::UnaryOpcode, ast::BinaryOpcode, PLBotParseResult, optim::merge_constraints, optim::construct_constraints_from_vec, error::PLBotParserError};
-use super::ir::{Instruction, SetConstraint, RegID, RedirectFilterStrategy};
+use super::{ast::Query, ast::Expr, ast::UnaryOpcode, ast::BinaryOpcode, ast::Constraint, ast::ActivityArg, ast::Spanned, ast::span_of, PLBotParseResult, optim::merge_constraints, optim::construct_constraints_from_vec, optim::parse_time_point, error::PLBotParserError, namespace::NamespaceTable};
+use super::ir::{Instruction, SetConstraint, RegID, RedirectFilterStrategy, ActivitySource, TimePoint};
 
-pub(crate) fn to_ir(ast: &Expr) -> PLBotParseResult {
-    ir_helper(ast, 0)
+pub(crate) fn to_ir(ast: &Query, namespaces: &NamespaceTable) -> PLBotParseResult {
+    // lower every binding the body depends on once, in order, and remember where its result lives
+    // unused bindings are never lowered, so they cost nothing
+    let mut env: HashMap<&str, RegID> = HashMap::new();
+    let mut inst: Vec<Instruction> = Vec::new();
+    let mut reg_id: RegID = 0;
+    for binding in ast.used_bindings() {
+        let (mut binding_inst, binding_dest) = ir_helper(&binding.value, reg_id, &env, namespaces)?;
+        inst.append(&mut binding_inst);
+        env.insert(&binding.name.node, binding_dest);
+        reg_id = binding_dest + 1;
+    }
+    let (mut body_inst, body_dest) = ir_helper(&ast.body, reg_id, &env, namespaces)?;
+    inst.append(&mut body_inst);
+    Ok((inst, body_dest))
 }
 
-fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
+fn ir_helper(ast: &Expr, mut reg_id: RegID, env: &HashMap<&str, RegID>, namespaces: &NamespaceTable) -> PLBotParseResult {
     // do a postorder dfs to the tree
     // find any semantic error
     let mut stack: Vec<&Expr> = Vec::new();
@@ -25,11 +38,17 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
             Expr::Unary(_, c) => root = Some(c),
             Expr::Constrained(c, _) => root = Some(c),
             Expr::Page(..) => root = None,
+            Expr::Var(..) => root = None,
+            // lowered as a program of its own
+            Expr::Site(..) => root = None,
+            Expr::Search(..) => root = None,
+            Expr::Special(..) => root = None,
+            Expr::WithProp(..) => root = None,
+            Expr::Activity(..) => root = None,
         };
     }
 
-    while !stack.is_empty() {
-        let node = stack.pop().unwrap();
+    while let Some(node) = stack.pop() {
         let instruct: Instruction;
         match &node {
             Expr::Page(l) => {
@@ -37,6 +56,48 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
                 inst.push(instruct);
                 reg_id += 1;
             },
+            Expr::Var(v) => {
+                // `check_scope` ensures that the name is bound
+                let op = *env.get(v.node.as_str()).ok_or_else(|| PLBotParserError::Semantic(format!("name `{}` is not bound", v.node), v.span))?;
+                instruct = Instruction::Filter { dest: reg_id, op, cs: SetConstraint::new() };
+                inst.push(instruct);
+                reg_id += 1;
+            },
+            Expr::Site(site, e) => {
+                // the other wiki cannot see the results of this one, so bound names are not available
+                let mut vars = Vec::new();
+                e.collect_vars(&mut vars);
+                if let Some(v) = vars.first() {
+                    return Err(PLBotParserError::Semantic(format!("name `{}` cannot be used inside `site()`", v.node), v.span));
+                }
+                // the names of this wiki may mean nothing there, only the canonical ones are understood everywhere
+                let (site_inst, site_dest) = ir_helper(e, 0, &HashMap::new(), &NamespaceTable::canonical())?;
+                let query = super::optimize(site_inst, site_dest);
+                instruct = Instruction::Site { dest: reg_id, site: site.to_owned(), query: Box::new(query), cs: SetConstraint::new() };
+                inst.push(instruct);
+                reg_id += 1;
+            },
+            Expr::Search(query) => {
+                instruct = Instruction::Search { dest: reg_id, query: query.to_owned(), cs: SetConstraint::new() };
+                inst.push(instruct);
+                reg_id += 1;
+            },
+            Expr::Special(page) => {
+                let name = query_page_name(&page.node).ok_or_else(|| PLBotParserError::Semantic(format!("unknown special page report `{}`, expected a report such as `Lonelypages`", page.node.trim()), page.span))?;
+                instruct = Instruction::Special { dest: reg_id, page: name.to_owned(), cs: SetConstraint::new() };
+                inst.push(instruct);
+                reg_id += 1;
+            },
+            Expr::WithProp(prop) => {
+                instruct = Instruction::WithProp { dest: reg_id, prop: prop.trim().to_owned(), cs: SetConstraint::new() };
+                inst.push(instruct);
+                reg_id += 1;
+            },
+            Expr::Activity(source, args) => {
+                instruct = activity_instruction(reg_id, source, args, namespaces)?;
+                inst.push(instruct);
+                reg_id += 1;
+            },
             Expr::Unary(op, _) => {
                 instruct = match *op {
                     UnaryOpcode::Link => Instruction::Link{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
@@ -45,17 +106,23 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
                     UnaryOpcode::InCategory => Instruction::InCat{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
                     UnaryOpcode::Toggle => Instruction::Toggle{ dest: reg_id, op: reg_id - 1 },
                     UnaryOpcode::Prefix => Instruction::Prefix{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
+                    UnaryOpcode::Categories => Instruction::Categories{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
+                    UnaryOpcode::Templates => Instruction::Templates{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
+                    UnaryOpcode::Images => Instruction::Images{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
+                    UnaryOpcode::FileUsage => Instruction::FileUsage{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
+                    UnaryOpcode::Redirects => Instruction::Redirects{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
+                    UnaryOpcode::Subpages => Instruction::Subpages{ dest: reg_id, op: reg_id - 1, cs: SetConstraint::new() },
                 };
                 inst.push(instruct);
                 reg_id += 1;
             },
             Expr::Binary(l, op, r) => {
-                let mut lop = ir_helper(l, reg_id)?;
+                let mut lop = ir_helper(l, reg_id, env, namespaces)?;
                 let left_dest = lop.1;
                 reg_id = left_dest + 1;
                 inst.append(&mut lop.0);
                 
-                let mut rop = ir_helper(r, reg_id)?;
+                let mut rop = ir_helper(r, reg_id, env, namespaces)?;
                 let right_dest = rop.1;
                 reg_id = right_dest + 1;
                 inst.append(&mut rop.0);
@@ -73,12 +140,16 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
                 // apply the constraint to the corresponding instruction
                 // the tree formulation ensures that this would always be the last element of `inst`, aka `reg_id - 1`
                 // the instruction construction process ensures that `inst` is sorted by `dest` field in ascending order
-                let constraint_struct = construct_constraints_from_vec(c)?;
+                let constraint_struct = construct_constraints_from_vec(c, namespaces)?;
                 // rejects if ns has some negative number
                 let mut stack: Vec<(RegID, SetConstraint)> = vec![(reg_id - 1, constraint_struct)];
                 while let Some((target, con)) = stack.pop() {
                     let ires = inst.binary_search_by(|probe| probe.get_dest().cmp(&target));
                     if let Ok(idx) = ires {
+                        // template parameters are only known for the pages found through a template
+                        if !con.params.is_empty() && !matches!(inst[idx], Instruction::And {..} | Instruction::Or {..} | Instruction::Exclude {..} | Instruction::Xor {..} | Instruction::Nop {..} | Instruction::EmbeddedIn {..}) {
+                            return Err(PLBotParserError::Semantic(String::from("template parameter constraint can only be applied to `embed()`"), span_of(c, |c| matches!(c, Constraint::Param(..) | Constraint::NoParam(_)))));
+                        }
                         match &mut inst[idx] {
                             Instruction::And { dest: _, op1, op2 } |
                             Instruction::Or { dest: _, op1, op2 } |
@@ -91,47 +162,55 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
                             Instruction::Link { dest, op, cs } => {
                                 // rejects if constraint has a depth or directlink field, else merge
                                 if con.depth.is_some() || con.directlink.is_some() {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint")));
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::DirectLink(_)))));
                                 }
                                 // also rejects if constraint has a redirect constraint other than `All`
-                                if con.redir.is_some() && con.redir.unwrap() != RedirectFilterStrategy::All {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid redirect strategy")));
+                                if con.redir.is_some_and(|r| r != RedirectFilterStrategy::All) {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid redirect strategy"), span_of(c, |c| matches!(c, Constraint::Redir(_)))));
                                 }
-                                let new_constraint = merge_constraints(cs, &con)?;
+                                let new_constraint = merge_constraints(cs, &con, c)?;
                                 let new_inst = Instruction::Link { dest: *dest, op: *op, cs: new_constraint };
                                 inst[idx] = new_inst;
                             },
                             Instruction::LinkTo { dest, op, cs } => {
                                 // rejects if constraint has a depth field, else merge
                                 if con.depth.is_some() {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid depth constraint")));
+                                    return Err(PLBotParserError::Semantic(String::from("invalid depth constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_)))));
                                 }
-                                let new_constraint = merge_constraints(cs, &con)?;
+                                let new_constraint = merge_constraints(cs, &con, c)?;
                                 let new_inst = Instruction::LinkTo { dest: *dest, op: *op, cs: new_constraint };
                                 inst[idx] = new_inst;
                             },
                             Instruction::EmbeddedIn { dest, op, cs } => {
                                 // rejects if constraint has a depth or directlink field, else merge
                                 if con.depth.is_some() || con.directlink.is_some() {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint")));
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::DirectLink(_)))));
                                 }
-                                let new_constraint = merge_constraints(cs, &con)?;
+                                let new_constraint = merge_constraints(cs, &con, c)?;
                                 let new_inst = Instruction::EmbeddedIn { dest: *dest, op: *op, cs: new_constraint };
                                 inst[idx] = new_inst;
                             }
                             Instruction::InCat { dest, op, cs } => {
                                 // rejects if constraint has a redirect constraint other than `All`, or constraint has a directlink constraint. Otherwise merge the constraints
-                                if con.redir.is_some() && con.redir.unwrap() != RedirectFilterStrategy::All {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid redirect strategy")));
+                                if con.redir.is_some_and(|r| r != RedirectFilterStrategy::All) {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid redirect strategy"), span_of(c, |c| matches!(c, Constraint::Redir(_)))));
                                 }
                                 if con.directlink.is_some() {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid directlink constraint")));
+                                    return Err(PLBotParserError::Semantic(String::from("invalid directlink constraint"), span_of(c, |c| matches!(c, Constraint::DirectLink(_)))));
                                 }
-                                let new_constraint = merge_constraints(cs, &con)?;
+                                let new_constraint = merge_constraints(cs, &con, c)?;
                                 let new_inst = Instruction::InCat { dest: *dest, op: *op, cs: new_constraint };
                                 inst[idx] = new_inst;
                             }
                             Instruction::Toggle { dest: _, op } => {
+                                // a full title changes when toggled, so a pattern on it cannot be moved to the other side
+                                if con.titlematch.iter().any(|m| m.full) {
+                                    return Err(PLBotParserError::Semantic(String::from("a full title pattern cannot be applied to `toggle()`"), span_of(c, |c| matches!(c, Constraint::Match { full: true, .. }))));
+                                }
+                                // so does everything known about the page
+                                if !con.pagefilter.is_empty() || !con.contains.is_empty() || !con.pageprops.is_empty() {
+                                    return Err(PLBotParserError::Semantic(String::from("a page metadata, property or content constraint cannot be applied to `toggle()`"), span_of(c, |c| matches!(c, Constraint::Size(..) | Constraint::Touched(_) | Constraint::Created(_) | Constraint::Protected(..) | Constraint::ContentModel(_) | Constraint::Contains(_) | Constraint::PageProp(..) | Constraint::Wikidata(..)))));
+                                }
                                 // switch every ns constraint, then pass through this instruction
                                 let ns = con.ns.clone();
                                 
@@ -140,7 +219,7 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
                                     for i in ns_vec.iter_mut() {
                                         *i ^= 0b1;
                                     }
-                                    let new_con = SetConstraint { ns: Some(HashSet::from_iter(ns_vec.into_iter())), depth: con.depth, redir: con.redir, directlink: con.directlink, resolveredir: con.resolveredir, limit: con.limit };
+                                    let new_con = SetConstraint { ns: Some(HashSet::from_iter(ns_vec)), depth: con.depth, redir: con.redir, directlink: con.directlink, resolveredir: con.resolveredir, limit: con.limit, titlematch: con.titlematch.clone(), pagefilter: Vec::new(), contains: Vec::new(), params: Vec::new(), pageprops: Vec::new() };
                                     stack.push((*op, new_con));
                                 } else {
                                     stack.push((*op, con.clone()));
@@ -150,28 +229,109 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
                                 // rejects if constraint has a depth, resolveredir, or directlink field
                                 // else merge
                                 if con.depth.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint")));
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
                                 }
-                                let new_constraint = merge_constraints(cs, &con)?;
+                                let new_constraint = merge_constraints(cs, &con, c)?;
                                 let new_inst = Instruction::Prefix { dest: *dest, op: *op, cs: new_constraint };
                                 inst[idx] = new_inst;
                             },
+                            Instruction::Categories { cs, .. } |
+                            Instruction::Templates { cs, .. } |
+                            Instruction::Images { cs, .. } => {
+                                // the pages are looked up from the input pages, which cannot be narrowed down by redirect
+                                // rejects if constraint has a depth, redir, or directlink field, else merge
+                                if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
+                            Instruction::FileUsage { cs, .. } => {
+                                // rejects if constraint has a depth or directlink field, else merge
+                                if con.depth.is_some() || con.directlink.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::DirectLink(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
+                            Instruction::Redirects { cs, .. } => {
+                                // every result is a redirect to an input page, so resolving or filtering them makes no sense
+                                // rejects if constraint has a depth, redir, resolveredir, or directlink field, else merge
+                                if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
+                            Instruction::Subpages { cs, .. } => {
+                                // same as `Prefix`
+                                // rejects if constraint has a depth, resolveredir, or directlink field, else merge
+                                if con.depth.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
                             Instruction::Nop { dest: _, op } => {
                                 // pass through this instruction
                                 stack.push((*op, con.clone()));
                             }
+                            Instruction::Filter { dest, op, cs } => {
+                                // the binding has already been fetched, so only filters that can be applied afterwards are allowed
+                                // a limit would keep an arbitrary subset of the fetched pages, so it is not one of them
+                                // rejects if constraint has a depth, redir, resolveredir, directlink, or limit field, else merge
+                                if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() || con.resolveredir.is_some() || con.limit.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("constraint cannot be applied to a bound name, apply it inside the binding instead"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_) | Constraint::Limit(_)))));
+                                }
+                                let new_constraint = merge_constraints(cs, &con, c)?;
+                                let new_inst = Instruction::Filter { dest: *dest, op: *op, cs: new_constraint };
+                                inst[idx] = new_inst;
+                            },
                             Instruction::Set { dest, titles, cs } => {
                                 // rejects if constraint has a depth, redir, resolveredir, or directlink field, else merge
                                 if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
-                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint")));
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
                                 }
-                                let new_constraint = merge_constraints(cs, &con)?;
+                                let new_constraint = merge_constraints(cs, &con, c)?;
                                 let new_inst = Instruction::Set { dest: *dest, titles: (*titles).clone(), cs: new_constraint };
                                 inst[idx] = new_inst;
                             },
+                            Instruction::Special { cs, .. } |
+                            Instruction::WithProp { cs, .. } => {
+                                // a report or a property lists what it lists, only filters can be applied to it
+                                // rejects if constraint has a depth, redir, resolveredir, or directlink field, else merge
+                                if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
+                            Instruction::Search { cs, .. } => {
+                                // the search engine cannot tell redirects apart, and resolves them itself
+                                // rejects if constraint has a depth, redir, resolveredir, or directlink field, else merge
+                                if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
+                            Instruction::Activity { source, cs, .. } => {
+                                // rejects if constraint has a depth, resolveredir, or directlink field
+                                // logs and contributions cannot tell redirects apart, so also rejects a redir field for them, else merge
+                                if con.depth.is_some() || con.directlink.is_some() || con.resolveredir.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid constraint"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_)))));
+                                }
+                                if con.redir.is_some() && !source.filters_redirects() {
+                                    return Err(PLBotParserError::Semantic(String::from("invalid redirect strategy"), span_of(c, |c| matches!(c, Constraint::Redir(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
+                            Instruction::Site { cs, .. } => {
+                                // the constraints apply to the mapped pages of this wiki, and only filters can be applied to them
+                                // the pages are mapped all at once, so a limit would keep an arbitrary subset of them
+                                // rejects if constraint has a depth, redir, resolveredir, directlink, or limit field, else merge
+                                if con.depth.is_some() || con.redir.is_some() || con.directlink.is_some() || con.resolveredir.is_some() || con.limit.is_some() {
+                                    return Err(PLBotParserError::Semantic(String::from("constraint cannot be applied to `site()`, apply it inside instead"), span_of(c, |c| matches!(c, Constraint::Depth(_) | Constraint::Redir(_) | Constraint::DirectLink(_) | Constraint::ResolveRedir(_) | Constraint::Limit(_)))));
+                                }
+                                *cs = merge_constraints(cs, &con, c)?;
+                            },
                         }
                     } else {
-                        return Err(PLBotParserError::Semantic(String::from("internal instruction not found while generating")));
+                        return Err(PLBotParserError::Semantic(String::from("internal instruction not found while generating"), span_of(c, |_| false)));
                     }
                 }
             }
@@ -180,3 +340,52 @@ fn ir_helper(ast: &Expr, mut reg_id: RegID) -> PLBotParseResult {
 
     Ok((inst, reg_id - 1))
 }
+
+/// The special page reports the API lists, from MediaWiki and from the extensions installed on Wikimedia wikis
+const QUERY_PAGES: &[&str] = &[
+    "Ancientpages", "BrokenRedirects", "Deadendpages", "DisambiguationPageLinks", "DisambiguationPages", "DoubleRedirects",
+    "Fewestrevisions", "GadgetUsage", "GloballyUnusedFiles", "GloballyWantedFiles", "ListDuplicatedFiles", "Listredirects",
+    "Lonelypages", "Longpages", "MediaStatistics", "MostGloballyLinkedFiles", "Mostcategories", "Mostimages",
+    "Mostinterwikis", "Mostlinked", "Mostlinkedcategories", "Mostlinkedtemplates", "Mostrevisions", "Shortpages",
+    "Uncategorizedcategories", "Uncategorizedimages", "Uncategorizedpages", "Uncategorizedtemplates", "UnconnectedPages",
+    "Unusedcategories", "Unusedimages", "Unusedtemplates", "Unwatchedpages", "Wantedcategories", "Wantedfiles",
+    "Wantedpages", "Wantedtemplates", "Withoutinterwiki",
+];
+
+/// The name the API knows a special page report by, whatever the case of `name`
+fn query_page_name(name: &str) -> Option<&'static str> {
+    let name = name.trim();
+    QUERY_PAGES.iter().copied().find(|page| page.eq_ignore_ascii_case(name))
+}
+
+/// Builds the instruction of an activity operator, such as `newpages(ns=0, since="-7d")`.
+/// Every argument may be given once. `ns` becomes the namespace constraint of the instruction.
+fn activity_instruction(dest: RegID, source: &ActivitySource, args: &[Spanned<ActivityArg>], namespaces: &NamespaceTable) -> Result<Instruction, PLBotParserError> {
+    let mut since: Option<TimePoint> = None;
+    let mut until: Option<TimePoint> = None;
+    let mut cs = SetConstraint::new();
+    for arg in args {
+        let (slot, time) = match &arg.node {
+            ActivityArg::Since(time) => (&mut since, time),
+            ActivityArg::Until(time) => (&mut until, time),
+            ActivityArg::Ns(item) => {
+                if cs.ns.is_some() {
+                    return Err(PLBotParserError::Semantic(String::from("duplicate argument `ns`"), arg.span));
+                }
+                cs.ns = Some(namespaces.resolve(std::slice::from_ref(item))?);
+                continue;
+            },
+        };
+        if slot.is_some() {
+            return Err(PLBotParserError::Semantic(String::from("duplicate time argument"), arg.span));
+        }
+        let point = parse_time_point(time).ok_or_else(|| PLBotParserError::Semantic(format!("invalid time `{}`, expected a date such as `2020-01-01`, a timestamp such as `2020-01-01T00:00:00Z`, or a relative time such as `-7d`", time), arg.span))?;
+        *slot = Some(point);
+    }
+    // a relative bound and a fixed one are not ordered, so they are not checked
+    if since.zip(until).is_some_and(|(since, until)| since >= until) {
+        let span = args.iter().skip(1).fold(args[0].span, |acc, arg| acc.to(&arg.span));
+        return Err(PLBotParserError::Semantic(String::from("time span is empty"), span));
+    }
+    Ok(Instruction::Activity { dest, source: source.clone(), since, until, cs })
+}
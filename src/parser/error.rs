@@ -1,7 +1,149 @@
+use lalrpop_util::{ParseError, lexer::Token};
+
+/// A position in the query source.
+///
+/// `offset` is a byte offset, `line` and `column` are 1-based and count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn from_offset(src: &str, offset: usize) -> Self {
+        let offset = usize::min(offset, src.len());
+        let before = &src[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count() + 1;
+        Location { offset, line, column }
+    }
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}:{}", self.line, self.column))
+    }
+}
+
+/// A range `[start, end)` in the query source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn new(src: &str, start: usize, end: usize) -> Self {
+        Span { start: Location::from_offset(src, start), end: Location::from_offset(src, end) }
+    }
+
+    /// Returns the smallest span covering both `self` and `other`
+    pub fn to(&self, other: &Span) -> Self {
+        let start = if self.start.offset <= other.start.offset { self.start } else { other.start };
+        let end = if self.end.offset >= other.end.offset { self.end } else { other.end };
+        Span { start, end }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("{}-{}", self.start, self.end))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxErrorKind {
+    InvalidToken,
+    UnexpectedEnd,
+    UnexpectedToken,
+    ExtraToken,
+    NumberOutOfRange,
+}
+
+/// An error raised by an action of the grammar, with the byte range of the offending literal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GrammarError {
+    NumberOutOfRange(usize, usize),
+}
+
+/// One syntax error found by the grammar.
+///
+/// `token`: the offending token, if there is one.
+///
+/// `expected`: the tokens that would have been accepted at this position.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub kind: SyntaxErrorKind,
+    pub span: Span,
+    pub token: Option<String>,
+    pub expected: Vec<String>,
+}
+
+impl SyntaxError {
+    pub(crate) fn from_lalrpop(src: &str, err: ParseError<usize, Token<'_>, GrammarError>) -> Self {
+        match err {
+            ParseError::InvalidToken { location } => {
+                // the lexer stops at the first character it does not understand
+                let end = src[location..].chars().next().map(|c| location + c.len_utf8()).unwrap_or(location);
+                SyntaxError { kind: SyntaxErrorKind::InvalidToken, span: Span::new(src, location, end), token: Some(src[location..end].to_string()), expected: Vec::new() }
+            },
+            ParseError::UnrecognizedEOF { location, expected } => {
+                SyntaxError { kind: SyntaxErrorKind::UnexpectedEnd, span: Span::new(src, location, location), token: None, expected: describe_expected(&expected) }
+            },
+            ParseError::UnrecognizedToken { token: (l, t, r), expected } => {
+                SyntaxError { kind: SyntaxErrorKind::UnexpectedToken, span: Span::new(src, l, r), token: Some(t.1.to_string()), expected: describe_expected(&expected) }
+            },
+            ParseError::ExtraToken { token: (l, t, r) } => {
+                SyntaxError { kind: SyntaxErrorKind::ExtraToken, span: Span::new(src, l, r), token: Some(t.1.to_string()), expected: Vec::new() }
+            },
+            ParseError::User { error: GrammarError::NumberOutOfRange(l, r) } => {
+                SyntaxError { kind: SyntaxErrorKind::NumberOutOfRange, span: Span::new(src, l, r), token: Some(src[l..r].to_string()), expected: Vec::new() }
+            },
+        }
+    }
+}
+
+/// Turns the terminal names reported by LALRPOP into something a task author can read.
+/// Literal terminals come quoted (`"\"(\""`), regex terminals come as their raw pattern.
+fn describe_expected(expected: &[String]) -> Vec<String> {
+    expected.iter().map(|e| {
+        if e.starts_with("r#") {
+            if e.contains("[0-9]") {
+                String::from("number")
+            } else if e.contains("\\\\\"") || e.contains("\\\"") {
+                String::from("string")
+            } else {
+                String::from("name")
+            }
+        } else {
+            format!("`{}`", e.trim_matches('"'))
+        }
+    }).collect()
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_fmt(format_args!("at {}: ", self.span.start))?;
+        match self.kind {
+            SyntaxErrorKind::InvalidToken => f.write_fmt(format_args!("invalid token `{}`", self.token.as_deref().unwrap_or("")))?,
+            SyntaxErrorKind::UnexpectedEnd => f.write_str("unexpected end of query")?,
+            SyntaxErrorKind::UnexpectedToken => f.write_fmt(format_args!("unexpected token `{}`", self.token.as_deref().unwrap_or("")))?,
+            SyntaxErrorKind::ExtraToken => f.write_fmt(format_args!("extra token `{}`", self.token.as_deref().unwrap_or("")))?,
+            SyntaxErrorKind::NumberOutOfRange => f.write_fmt(format_args!("number `{}` is out of range", self.token.as_deref().unwrap_or("")))?,
+        }
+        if !self.expected.is_empty() {
+            f.write_fmt(format_args!(", expected one of {}", self.expected.join(", ")))?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub enum PLBotParserError {
-    Parse,
-    Semantic(String),
+    Parse(Vec<SyntaxError>),
+    Semantic(String, Span),
 }
 
 impl std::error::Error for PLBotParserError {}
@@ -9,8 +151,53 @@ impl std::error::Error for PLBotParserError {}
 impl std::fmt::Display for PLBotParserError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Parse => f.write_str("parse fails"),
-            Self::Semantic(s) => f.write_fmt(format_args!("semantic error: {}", s)),
+            Self::Parse(errs) => {
+                f.write_str("parse fails")?;
+                for e in errs {
+                    f.write_fmt(format_args!("; {}", e))?;
+                }
+                Ok(())
+            },
+            Self::Semantic(s, span) => f.write_fmt(format_args!("semantic error at {}: {}", span.start, s)),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn location_counts_lines_and_columns() {
+        let src = "incat(\"A\")\n+ link(\"B\")";
+        assert_eq!(Location::from_offset(src, 0), Location { offset: 0, line: 1, column: 1 });
+        assert_eq!(Location::from_offset(src, 6), Location { offset: 6, line: 1, column: 7 });
+        // the newline itself is the last character of the first line
+        assert_eq!(Location::from_offset(src, 10), Location { offset: 10, line: 1, column: 11 });
+        assert_eq!(Location::from_offset(src, 11), Location { offset: 11, line: 2, column: 1 });
+        assert_eq!(Location::from_offset(src, 13), Location { offset: 13, line: 2, column: 3 });
+    }
+
+    #[test]
+    fn location_columns_count_characters() {
+        // `é` takes two bytes but is one column
+        let src = "page(\"é\") +";
+        assert_eq!(Location::from_offset(src, 10), Location { offset: 10, line: 1, column: 10 });
+    }
+
+    #[test]
+    fn location_is_clamped_to_the_end() {
+        let src = "a\nb";
+        assert_eq!(Location::from_offset(src, 100), Location { offset: 3, line: 2, column: 2 });
+    }
+
+    #[test]
+    fn span_to_covers_both() {
+        let src = "0123456789";
+        let a = Span::new(src, 2, 4);
+        let b = Span::new(src, 6, 9);
+        assert_eq!(a.to(&b), Span::new(src, 2, 9));
+        assert_eq!(b.to(&a), Span::new(src, 2, 9));
+        assert_eq!(a.to(&a), a);
+    }
+}
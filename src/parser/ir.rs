@@ -9,8 +9,12 @@
 
 #![allow(dead_code)]
 
+use std::cmp::Ordering;
+use chrono::{DateTime, Utc};
 use mediawiki::api::NamespaceID;
+use regex::Regex;
 use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
 
 pub type RegID = u64;
 pub type DepthNum = i64;
@@ -23,37 +27,242 @@ pub type DepthNum = i64;
 /// `OnlyRedirect`: explicitly query for redirects.
 /// 
 /// `All`: query for both redirects and non-redirects.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RedirectFilterStrategy {
     NoRedirect,
     OnlyRedirect,
     All,
 }
 
-impl ToString for RedirectFilterStrategy {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for RedirectFilterStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::NoRedirect => String::from("nonredirects"),
-            Self::OnlyRedirect => String::from("redirects"),
-            Self::All => String::from("all"),
+            Self::NoRedirect => f.write_str("nonredirects"),
+            Self::OnlyRedirect => f.write_str("redirects"),
+            Self::All => f.write_str("all"),
         }
     }
 }
 
+/// `Pattern` is a compiled regular expression, compared and hashed by its source.
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl std::ops::Deref for Pattern {
+    type Target = Regex;
+
+    fn deref(&self) -> &Regex {
+        &self.0
+    }
+}
+
+// `Regex` is neither `Eq` nor `Hash`
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.as_str().hash(state);
+    }
+}
+
+/// `TitleMatch` keeps the pages whose title matches a regular expression.
+/// 
+/// `full`: match against the full title, namespace prefix included. Otherwise only the title without the namespace is matched.
+/// 
+/// `negated`: keep the pages whose title does not match instead.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TitleMatch {
+    pub regex: Pattern,
+    pub full: bool,
+    pub negated: bool,
+}
+
+impl TitleMatch {
+    /// Tells whether a page with this title is kept
+    pub fn keeps(&self, title: &str) -> bool {
+        self.regex.is_match(title) != self.negated
+    }
+}
+
+/// `Comparison` compares a number of a page, such as its size, with a given one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+}
+
+impl Comparison {
+    /// Tells whether `lhs` compares to `rhs` this way
+    pub fn holds(&self, lhs: i64, rhs: i64) -> bool {
+        match self {
+            Self::Less => lhs < rhs,
+            Self::LessEqual => lhs <= rhs,
+            Self::Greater => lhs > rhs,
+            Self::GreaterEqual => lhs >= rhs,
+            Self::Equal => lhs == rhs,
+        }
+    }
+}
+
+/// `TimeRange` is a time span, open on the sides without a bound. Both bounds are excluded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct TimeRange {
+    pub after: Option<TimePoint>,
+    pub before: Option<TimePoint>,
+}
+
+/// The bounds of a `TimeRange` at a given time, `after` then `before`
+pub type TimeBounds = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+impl TimeRange {
+    /// The bounds of this span when the query is evaluated at `now`. `None` if a bound is out of the range of `DateTime`.
+    pub fn resolve(&self, now: DateTime<Utc>) -> Option<TimeBounds> {
+        let resolve = |point: Option<TimePoint>| match point {
+            Some(point) => point.resolve(now).map(Some),
+            None => Some(None),
+        };
+        Some((resolve(self.after)?, resolve(self.before)?))
+    }
+
+    /// Tells whether `time` is within this span when the query is evaluated at `now`. Nothing is within a span whose bounds cannot be resolved.
+    pub fn contains(&self, time: &DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.resolve(now).is_some_and(|(after, before)| after.is_none_or(|after| *time > after) && before.is_none_or(|before| *time < before))
+    }
+}
+
+/// `PageFilter` keeps the pages whose metadata satisfy a condition. The metadata are looked up after the instruction is evaluated.
+/// 
+/// `Size`: the page size in bytes.
+/// 
+/// `Touched`: the time of the last edit.
+/// 
+/// `Created`: the time of the first edit.
+/// 
+/// `Protected`: the page has a protection. `action` and `level` narrow it down to one action (such as `edit`) and one level (such as `autoconfirmed`).
+/// 
+/// `ContentModel`: the content model of the page, such as `wikitext`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PageFilter {
+    Size(Comparison, i64),
+    Touched(TimeRange),
+    Created(TimeRange),
+    Protected { action: Option<String>, level: Option<String> },
+    ContentModel(String),
+}
+
+/// `ParamFilter` keeps the pages calling a template with a given parameter. Only to be used with `EmbeddedIn`,
+/// where the calls of the templates the pages are found from are checked.
+/// 
+/// `Matches`: the parameter is given and its value matches the pattern.
+/// 
+/// `Missing`: the parameter is not given, or is empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ParamFilter {
+    Matches(String, Pattern),
+    Missing(String),
+}
+
+/// `TimePoint` is a point in time, either fixed or relative to the time the query is evaluated.
+/// 
+/// `Relative`: that many seconds before the evaluation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimePoint {
+    Absolute(DateTime<Utc>),
+    Relative(i64),
+}
+
+impl TimePoint {
+    /// The time this point stands for when the query is evaluated at `now`. `None` if it is out of the range of `DateTime`.
+    pub fn resolve(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Absolute(time) => Some(*time),
+            Self::Relative(seconds) => now.checked_sub_signed(chrono::TimeDelta::try_seconds(*seconds)?),
+        }
+    }
+}
+
+/// Points of one kind are ordered in time. A relative point and a fixed one are not ordered, since that depends on when the query is evaluated.
+impl PartialOrd for TimePoint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Absolute(a), Self::Absolute(b)) => a.partial_cmp(b),
+            // further back is earlier
+            (Self::Relative(a), Self::Relative(b)) => b.partial_cmp(a),
+            _ => None,
+        }
+    }
+}
+
+/// `ActivitySource` tells where an `Activity` instruction finds its pages.
+/// 
+/// `NewPages`: the pages created, from the recent changes.
+/// 
+/// `RecentChanges`: the pages edited or created, from the recent changes.
+/// 
+/// `Log`: the pages of the log entries of a type, such as `move`, or of an action, such as `delete/delete`.
+/// 
+/// `Contribs`: the pages a user edited.
+/// 
+/// `CreatedBy`: the pages a user created.
+/// 
+/// `LastEditor`: the pages whose last edit is by a user.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ActivitySource {
+    NewPages,
+    RecentChanges,
+    Log(String),
+    Contribs(String),
+    CreatedBy(String),
+    LastEditor(String),
+}
+
+impl ActivitySource {
+    /// Whether redirects can be told apart when querying
+    pub fn filters_redirects(&self) -> bool {
+        matches!(self, Self::NewPages | Self::RecentChanges)
+    }
+}
+
+/// `PropFilter` keeps the pages with, or without, a page property such as `disambiguation`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PropFilter {
+    pub name: String,
+    pub present: bool,
+}
+
 /// `SetConstraint` are modifier to some instructions.
-/// They are intended for `Link`, `LinkTo`, `InCat`, `Prefix`, `EmbeddedIn` and `Set` instructions.
+/// They are intended for every generating instruction, such as `Link`, `LinkTo`, `InCat`, `Prefix` or `EmbeddedIn`, and for `Set` instructions.
 /// They are not effective to `Toggle` and and all binary instructions.
 /// 
 /// `ns`: the namespace(s) to filter on
 /// 
 /// `depth`: query depth into the category tree. Only to be used with `InCat`.
 /// 
-/// `redir`: how to deal with redirect pages. Refer to `RedirectStrategy` for more information. Only to be used with `LinkTo`, `Prefix` and `EmbeddedIn`.
+/// `redir`: how to deal with redirect pages. Refer to `RedirectStrategy` for more information. Only to be used with `LinkTo`, `Prefix`, `Subpages`, `FileUsage` and `EmbeddedIn`.
 /// 
 /// `directlink`: how to deal with linking via redirects. Only to be used with `LinkTo`.
 /// 
 /// `resolveredir`: If a page is a redirect, how to deal with it.
-#[derive(Debug, Clone)]
+/// 
+/// `titlematch`: title patterns every page must satisfy. Applied after the instruction is evaluated.
+/// 
+/// `pagefilter`: metadata conditions every page must satisfy. Applied after the title patterns.
+/// 
+/// `contains`: patterns the current wikitext of every page must match. Applied last, since the content of every page is fetched.
+/// 
+/// `pageprops`: page properties every page must have or lack. Applied after the metadata conditions.
+/// 
+/// `params`: template parameter conditions one template call of every page must satisfy. Only to be used with `EmbeddedIn`.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SetConstraint {
     pub ns: Option<HashSet<NamespaceID>>,
     pub depth: Option<DepthNum>,
@@ -61,6 +270,11 @@ pub struct SetConstraint {
     pub directlink: Option<bool>,
     pub resolveredir: Option<bool>,
     pub limit: Option<i64>,
+    pub titlematch: Vec<TitleMatch>,
+    pub pagefilter: Vec<PageFilter>,
+    pub contains: Vec<Pattern>,
+    pub params: Vec<ParamFilter>,
+    pub pageprops: Vec<PropFilter>,
 }
 
 impl SetConstraint {
@@ -72,6 +286,11 @@ impl SetConstraint {
             directlink: None,
             resolveredir: None,
             limit: None,
+            titlematch: Vec::new(),
+            pagefilter: Vec::new(),
+            contains: Vec::new(),
+            params: Vec::new(),
+            pageprops: Vec::new(),
         }
     }
 }
@@ -82,7 +301,29 @@ impl Default for SetConstraint {
     }
 }
 
-#[derive(Debug, Clone)]
+impl Hash for SetConstraint {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // `HashSet` is not `Hash`, hash the namespaces in a fixed order instead
+        let ns = self.ns.as_ref().map(|ns| {
+            let mut ns_vec = Vec::from_iter(ns.iter().copied());
+            ns_vec.sort_unstable();
+            ns_vec
+        });
+        ns.hash(state);
+        self.depth.hash(state);
+        self.redir.hash(state);
+        self.directlink.hash(state);
+        self.resolveredir.hash(state);
+        self.limit.hash(state);
+        self.titlematch.hash(state);
+        self.pagefilter.hash(state);
+        self.contains.hash(state);
+        self.params.hash(state);
+        self.pageprops.hash(state);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Instruction {
     // Binary
     And { dest: RegID, op1: RegID, op2: RegID },
@@ -96,8 +337,34 @@ pub enum Instruction {
     InCat { dest: RegID, op: RegID, cs: SetConstraint },
     Toggle { dest: RegID, op: RegID },
     Prefix { dest: RegID, op: RegID, cs: SetConstraint },
+    // The categories of the pages
+    Categories { dest: RegID, op: RegID, cs: SetConstraint },
+    // The pages the pages transclude
+    Templates { dest: RegID, op: RegID, cs: SetConstraint },
+    // The files the pages use
+    Images { dest: RegID, op: RegID, cs: SetConstraint },
+    // The pages using the files
+    FileUsage { dest: RegID, op: RegID, cs: SetConstraint },
+    // The redirects to the pages
+    Redirects { dest: RegID, op: RegID, cs: SetConstraint },
+    // The subpages of the pages
+    Subpages { dest: RegID, op: RegID, cs: SetConstraint },
+    // Reads the register of a `let` binding. Each use of a binding gets its own `Filter`,
+    // so that constraints applied to that use do not leak into the shared register.
+    Filter { dest: RegID, op: RegID, cs: SetConstraint },
     // Primitive
     Set { dest: RegID, titles: Vec<String>, cs: SetConstraint },
+    // Runs `query` on the wiki of site profile `site`, then maps the result to this wiki through interlanguage links.
+    // `query` has registers of its own.
+    Site { dest: RegID, site: String, query: Box<(Vec<Instruction>, RegID)>, cs: SetConstraint },
+    // The pages found by the search engine of the wiki
+    Search { dest: RegID, query: String, cs: SetConstraint },
+    // The pages listed by a special page report, such as `Lonelypages`
+    Special { dest: RegID, page: String, cs: SetConstraint },
+    // The pages with a page property, such as `wikibase_item`
+    WithProp { dest: RegID, prop: String, cs: SetConstraint },
+    // The pages with recent activity of some kind between `since` and `until`
+    Activity { dest: RegID, source: ActivitySource, since: Option<TimePoint>, until: Option<TimePoint>, cs: SetConstraint },
     // Null
     Nop { dest: RegID, op: RegID },
 }
@@ -109,11 +376,11 @@ impl Instruction {
     }
 
     pub fn is_unary_op(&self) -> bool {
-        matches!(*self, Self::Link {..} | Self::LinkTo {..} | Self::EmbeddedIn {..} | Self::InCat {..} | Self::Toggle {..} | Self::Prefix {..})
+        matches!(*self, Self::Link {..} | Self::LinkTo {..} | Self::EmbeddedIn {..} | Self::InCat {..} | Self::Toggle {..} | Self::Prefix {..} | Self::Categories {..} | Self::Templates {..} | Self::Images {..} | Self::FileUsage {..} | Self::Redirects {..} | Self::Subpages {..} | Self::Filter {..})
     }
 
     pub fn is_primitive_op(&self) -> bool {
-        matches!(*self, Self::Set {..})
+        matches!(*self, Self::Set {..} | Self::Site {..} | Self::Search {..} | Self::Special {..} | Self::WithProp {..} | Self::Activity {..})
     }
 
     pub fn is_nop(&self) -> bool {
@@ -132,7 +399,19 @@ impl Instruction {
             Self::InCat { dest, .. } => dest,
             Self::Toggle { dest, ..} => dest,
             Self::Prefix { dest, .. } => dest,
+            Self::Categories { dest, .. } => dest,
+            Self::Templates { dest, .. } => dest,
+            Self::Images { dest, .. } => dest,
+            Self::FileUsage { dest, .. } => dest,
+            Self::Redirects { dest, .. } => dest,
+            Self::Subpages { dest, .. } => dest,
+            Self::Filter { dest, .. } => dest,
             Self::Set { dest, .. } => dest,
+            Self::Site { dest, .. } => dest,
+            Self::Search { dest, .. } => dest,
+            Self::Special { dest, .. } => dest,
+            Self::WithProp { dest, .. } => dest,
+            Self::Activity { dest, .. } => dest,
             Self::Nop { dest, .. } => dest,
         }
     }
@@ -149,11 +428,113 @@ impl Instruction {
             Self::InCat { dest, .. } => *dest = new_dest,
             Self::Toggle { dest, ..} => *dest = new_dest,
             Self::Prefix { dest, .. } => *dest = new_dest,
+            Self::Categories { dest, .. } => *dest = new_dest,
+            Self::Templates { dest, .. } => *dest = new_dest,
+            Self::Images { dest, .. } => *dest = new_dest,
+            Self::FileUsage { dest, .. } => *dest = new_dest,
+            Self::Redirects { dest, .. } => *dest = new_dest,
+            Self::Subpages { dest, .. } => *dest = new_dest,
+            Self::Filter { dest, .. } => *dest = new_dest,
             Self::Set { dest, .. } => *dest = new_dest,
+            Self::Site { dest, .. } => *dest = new_dest,
+            Self::Search { dest, .. } => *dest = new_dest,
+            Self::Special { dest, .. } => *dest = new_dest,
+            Self::WithProp { dest, .. } => *dest = new_dest,
+            Self::Activity { dest, .. } => *dest = new_dest,
             Self::Nop { dest, .. } => *dest = new_dest,
         };
     }
 
+    /// Returns the registers this instruction reads from
+    pub fn get_operands(&self) -> Vec<RegID> {
+        match *self {
+            Self::And { op1, op2, .. } |
+            Self::Or { op1, op2, .. } |
+            Self::Exclude { op1, op2, .. } |
+            Self::Xor { op1, op2, .. } => vec![op1, op2],
+            Self::Link { op, .. } |
+            Self::LinkTo { op, .. } |
+            Self::EmbeddedIn { op, .. } |
+            Self::InCat { op, .. } |
+            Self::Toggle { op, .. } |
+            Self::Prefix { op, .. } |
+            Self::Categories { op, .. } |
+            Self::Templates { op, .. } |
+            Self::Images { op, .. } |
+            Self::FileUsage { op, .. } |
+            Self::Redirects { op, .. } |
+            Self::Subpages { op, .. } |
+            Self::Filter { op, .. } |
+            Self::Nop { op, .. } => vec![op],
+            Self::Set { .. } |
+            Self::Site { .. } |
+            Self::Search { .. } |
+            Self::Special { .. } |
+            Self::WithProp { .. } |
+            Self::Activity { .. } => vec![],
+        }
+    }
+
+    /// Rewrites every register this instruction reads from
+    pub fn map_operands(&mut self, f: impl Fn(RegID) -> RegID) {
+        match self {
+            Self::And { op1, op2, .. } |
+            Self::Or { op1, op2, .. } |
+            Self::Exclude { op1, op2, .. } |
+            Self::Xor { op1, op2, .. } => {
+                *op1 = f(*op1);
+                *op2 = f(*op2);
+            },
+            Self::Link { op, .. } |
+            Self::LinkTo { op, .. } |
+            Self::EmbeddedIn { op, .. } |
+            Self::InCat { op, .. } |
+            Self::Toggle { op, .. } |
+            Self::Prefix { op, .. } |
+            Self::Categories { op, .. } |
+            Self::Templates { op, .. } |
+            Self::Images { op, .. } |
+            Self::FileUsage { op, .. } |
+            Self::Redirects { op, .. } |
+            Self::Subpages { op, .. } |
+            Self::Filter { op, .. } |
+            Self::Nop { op, .. } => {
+                *op = f(*op);
+            },
+            Self::Set { .. } |
+            Self::Site { .. } |
+            Self::Search { .. } |
+            Self::Special { .. } |
+            Self::WithProp { .. } |
+            Self::Activity { .. } => {},
+        }
+    }
+
+    /// Returns the constraint of this instruction, if it takes one
+    pub fn get_constraint(&self) -> Option<&SetConstraint> {
+        match self {
+            Self::Link { cs, .. } |
+            Self::LinkTo { cs, .. } |
+            Self::EmbeddedIn { cs, .. } |
+            Self::InCat { cs, .. } |
+            Self::Prefix { cs, .. } |
+            Self::Categories { cs, .. } |
+            Self::Templates { cs, .. } |
+            Self::Images { cs, .. } |
+            Self::FileUsage { cs, .. } |
+            Self::Redirects { cs, .. } |
+            Self::Subpages { cs, .. } |
+            Self::Filter { cs, .. } |
+            Self::Set { cs, .. } |
+            Self::Site { cs, .. } |
+            Self::Search { cs, .. } |
+            Self::Special { cs, .. } |
+            Self::WithProp { cs, .. } |
+            Self::Activity { cs, .. } => Some(cs),
+            _ => None,
+        }
+    }
+
     pub fn ns_empty(&self) -> bool {
         match self {
             Self::Link { cs, .. } |
@@ -161,7 +542,19 @@ impl Instruction {
             Self::EmbeddedIn { cs, .. } |
             Self::InCat { cs, .. } |
             Self::Prefix { cs, .. } |
-            Self::Set { cs, .. } => {
+            Self::Categories { cs, .. } |
+            Self::Templates { cs, .. } |
+            Self::Images { cs, .. } |
+            Self::FileUsage { cs, .. } |
+            Self::Redirects { cs, .. } |
+            Self::Subpages { cs, .. } |
+            Self::Filter { cs, .. } |
+            Self::Set { cs, .. } |
+            Self::Site { cs, .. } |
+            Self::Search { cs, .. } |
+            Self::Special { cs, .. } |
+            Self::WithProp { cs, .. } |
+            Self::Activity { cs, .. } => {
                 if let Some(ns) = &cs.ns {
                     ns.is_empty()
                 } else {
@@ -172,4 +565,82 @@ impl Instruction {
         }
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn title_match(regex: &str, negated: bool) -> TitleMatch {
+        TitleMatch { regex: Pattern(Regex::new(regex).unwrap()), full: false, negated }
+    }
+
+    #[test]
+    fn title_match_keeps_matching_titles() {
+        let sandbox = title_match("/Sandbox$", false);
+        assert!(sandbox.keeps("Foo/Sandbox"));
+        assert!(!sandbox.keeps("Foo/Sandbox/1"));
+        // the pattern is not anchored unless it says so
+        assert!(title_match("oo", false).keeps("Foo"));
+        assert!(!title_match("^oo", false).keeps("Foo"));
+    }
+
+    #[test]
+    fn comparisons_hold() {
+        let cases = [
+            (Comparison::Less, [true, false, false]),
+            (Comparison::LessEqual, [true, true, false]),
+            (Comparison::Greater, [false, false, true]),
+            (Comparison::GreaterEqual, [false, true, true]),
+            (Comparison::Equal, [false, true, false]),
+        ];
+        for (op, expected) in cases {
+            assert_eq!([op.holds(1, 2), op.holds(2, 2), op.holds(3, 2)], expected, "{:?}", op);
+        }
+    }
+
+    #[test]
+    fn time_points_resolve_from_now() {
+        let now = Utc.with_ymd_and_hms(2020, 1, 2, 0, 0, 0).unwrap();
+        assert_eq!(TimePoint::Relative(24 * 60 * 60).resolve(now), Some(Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap()));
+        assert_eq!(TimePoint::Absolute(now).resolve(Utc.with_ymd_and_hms(2030, 1, 1, 0, 0, 0).unwrap()), Some(now));
+        // out of the range of `DateTime`, instead of a panic
+        assert_eq!(TimePoint::Relative(i64::MAX / 1000).resolve(now), None);
+        assert_eq!(TimePoint::Relative(i64::MAX).resolve(now), None);
+    }
+
+    #[test]
+    fn time_points_of_one_kind_are_ordered() {
+        let at = |day| TimePoint::Absolute(Utc.with_ymd_and_hms(2020, 1, day, 0, 0, 0).unwrap());
+        assert!(at(1) < at(2));
+        assert!(TimePoint::Relative(60) < TimePoint::Relative(30));
+        assert_eq!(at(1).partial_cmp(&TimePoint::Relative(30)), None);
+    }
+
+    #[test]
+    fn time_range_excludes_its_bounds() {
+        let at = |day| Utc.with_ymd_and_hms(2020, 1, day, 0, 0, 0).unwrap();
+        let now = at(10);
+        let range = TimeRange { after: Some(TimePoint::Absolute(at(2))), before: Some(TimePoint::Absolute(at(4))) };
+        assert!(!range.contains(&at(2), now));
+        assert!(range.contains(&at(3), now));
+        assert!(!range.contains(&at(4), now));
+        let open = TimeRange { after: Some(TimePoint::Absolute(at(2))), before: None };
+        assert!(open.contains(&at(31), now));
+        assert!(TimeRange::default().contains(&at(1), now));
+        // relative bounds are taken from `now`
+        let last_week = TimeRange { after: Some(TimePoint::Relative(7 * 24 * 60 * 60)), before: None };
+        assert!(last_week.contains(&at(4), now));
+        assert!(!last_week.contains(&at(3), now));
+        let unresolvable = TimeRange { after: Some(TimePoint::Relative(i64::MAX)), before: None };
+        assert_eq!(unresolvable.resolve(now), None);
+        assert!(!unresolvable.contains(&at(4), now));
+    }
+
+    #[test]
+    fn negated_title_match_keeps_the_others() {
+        let not_sandbox = title_match("/Sandbox$", true);
+        assert!(!not_sandbox.keeps("Foo/Sandbox"));
+        assert!(not_sandbox.keeps("Foo"));
+    }
+}
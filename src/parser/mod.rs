@@ -10,28 +10,195 @@ mod grammar;
 mod optim;
 mod convert;
 mod error;
+mod namespace;
 pub(crate) mod ir;
 
-pub use error::PLBotParserError;
+pub use error::{PLBotParserError, Span};
+pub use namespace::NamespaceTable;
+use error::SyntaxError;
 
 pub type Query = (Vec<ir::Instruction>, ir::RegID);
 
 type PLBotParseResult = Result<Query, PLBotParserError>;
 
-pub fn parse(src: &str) -> PLBotParseResult {
-    let ast_res = grammar::ExprParser::new().parse(src);
+/// Parses a query, resolving namespace names with the namespaces of a wiki
+pub fn parse_with_namespaces(src: &str, namespaces: &NamespaceTable) -> PLBotParseResult {
+    // collect every syntax error the grammar can recover from, plus the one it cannot
+    let mut recovered = Vec::new();
+    let ast_res = grammar::QueryParser::new().parse(src, &mut recovered, src);
+    let mut syntax_errors: Vec<SyntaxError> = recovered.into_iter().map(|e| SyntaxError::from_lalrpop(src, e.error)).collect();
     let ast = match ast_res {
         Ok(e) => {
             e
         },
-        Err(_) => {
-            return Err(PLBotParserError::Parse);
+        Err(e) => {
+            syntax_errors.push(SyntaxError::from_lalrpop(src, e));
+            return Err(PLBotParserError::Parse(syntax_errors));
         },
     };
-    let (mut ir_ls, ir_fin) = convert::to_ir(&ast)?;
+    if !syntax_errors.is_empty() {
+        return Err(PLBotParserError::Parse(syntax_errors));
+    }
+    ast.check_scope()?;
+    let (ir_ls, ir_fin) = convert::to_ir(&ast, namespaces)?;
+    Ok(optimize(ir_ls, ir_fin))
+}
+
+/// Runs every optimization pass on a lowered program
+fn optimize(mut ir_ls: Vec<ir::Instruction>, ir_fin: ir::RegID) -> Query {
     optim::remove_redundent_talk(&mut ir_ls);
     optim::remove_empty_ns(&mut ir_ls);
 
     optim::remove_nop(&mut ir_ls);
-    Ok((ir_ls, ir_fin))
+    let ir_fin = optim::simplify(&mut ir_ls, ir_fin);
+    (ir_ls, ir_fin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::SyntaxErrorKind;
+    use std::collections::HashSet;
+
+    fn syntax_errors(src: &str) -> Vec<SyntaxError> {
+        match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Parse(errs)) => errs,
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn valid_query_parses() {
+        assert!(parse_with_namespaces("incat(\"Category:A\").ns(0) & linkto(\"B\")", &NamespaceTable::canonical()).is_ok());
+    }
+
+    #[test]
+    fn unexpected_token_is_located() {
+        let errs = syntax_errors("incat(\"A\") + ) ");
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].kind, SyntaxErrorKind::UnexpectedToken);
+        assert_eq!(errs[0].token.as_deref(), Some(")"));
+        assert_eq!((errs[0].span.start.line, errs[0].span.start.column), (1, 14));
+        assert_eq!(errs[0].span.end.offset, 14);
+    }
+
+    #[test]
+    fn unexpected_end_is_located() {
+        let src = "incat(\"A\"";
+        let errs = syntax_errors(src);
+        let last = errs.last().unwrap();
+        assert_eq!(last.kind, SyntaxErrorKind::UnexpectedEnd);
+        assert_eq!(last.token, None);
+        assert_eq!(last.span.start.offset, src.len());
+        assert_eq!((last.span.start.line, last.span.start.column), (1, 10));
+        assert!(last.expected.contains(&String::from("`)`")));
+    }
+
+    #[test]
+    fn invalid_token_is_located() {
+        let errs = syntax_errors("incat(\"A\")\n+ #");
+        let last = errs.last().unwrap();
+        assert_eq!(last.kind, SyntaxErrorKind::InvalidToken);
+        assert_eq!(last.token.as_deref(), Some("#"));
+        assert_eq!((last.span.start.line, last.span.start.column), (2, 3));
+    }
+
+    #[test]
+    fn number_out_of_range_is_located() {
+        let src = "incat(\"A\")\n.limit(99999999999999999999)";
+        let errs = syntax_errors(src);
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].kind, SyntaxErrorKind::NumberOutOfRange);
+        assert_eq!(errs[0].token.as_deref(), Some("99999999999999999999"));
+        assert_eq!((errs[0].span.start.line, errs[0].span.start.column), (2, 8));
+        assert_eq!(&src[errs[0].span.start.offset..errs[0].span.end.offset], "99999999999999999999");
+        assert!(errs[0].to_string().contains("number `99999999999999999999` is out of range"));
+    }
+
+    #[test]
+    fn recovery_reports_every_error() {
+        let errs = syntax_errors("incat(,) + link(\"B\")\n& embed(,)");
+        assert_eq!(errs.len(), 2, "{:?}", errs);
+        assert_eq!(errs[0].token.as_deref(), Some(","));
+        assert_eq!((errs[0].span.start.line, errs[0].span.start.column), (1, 7));
+        assert_eq!(errs[1].token.as_deref(), Some(","));
+        assert_eq!((errs[1].span.start.line, errs[1].span.start.column), (2, 9));
+    }
+
+    #[test]
+    fn semantic_error_points_at_constraint() {
+        let src = "incat(\"A\").depth(2).direct()";
+        match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Semantic(_, span)) => {
+                assert_eq!(&src[span.start.offset..span.end.offset], ".direct()");
+            },
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn special_page_reports_are_checked() {
+        match parse_with_namespaces("special(\" lonelyPages\")", &NamespaceTable::canonical()) {
+            Ok((ir_ls, _)) => assert!(matches!(&ir_ls[..], [ir::Instruction::Special { page, .. }] if page == "Lonelypages")),
+            Err(e) => panic!("expected a report, got {:?}", e),
+        }
+        let src = "special(\"Lonelypages\") + special(\"Nonexistent\")";
+        match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Semantic(msg, span)) => {
+                assert!(msg.contains("unknown special page report `Nonexistent`"));
+                assert_eq!(&src[span.start.offset..span.end.offset], "\"Nonexistent\"");
+            },
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wikidata_arguments_are_not_keywords() {
+        let src = "let has = incat(\"Category:A\"); let true = has.wikidata(has=true); true + has.wikidata(has = false)";
+        assert!(parse_with_namespaces(src, &NamespaceTable::canonical()).is_ok());
+    }
+
+    #[test]
+    fn wikidata_arguments_are_checked() {
+        let src = "incat(\"A\").wikidata(has=maybe)";
+        match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Semantic(msg, span)) => {
+                assert_eq!(msg, "unknown argument `has=maybe`, expected `has=true` or `has=false`");
+                assert_eq!(&src[span.start.offset..span.end.offset], ".wikidata(has=maybe)");
+            },
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+        match parse_with_namespaces("incat(\"A\").wikidata(has=true).wikidata(has=false)", &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Semantic(msg, _)) => assert_eq!(msg, "conflict page property constraint"),
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn activity_time_out_of_range_is_rejected() {
+        let src = "newpages(since=\"-99999999999999d\")";
+        match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Err(PLBotParserError::Semantic(msg, span)) => {
+                assert!(msg.starts_with("invalid time `-99999999999999d`"));
+                assert_eq!(&src[span.start.offset..span.end.offset], "since=\"-99999999999999d\"");
+            },
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn activity_namespace_is_resolved() {
+        let ns_of = |src: &str| match parse_with_namespaces(src, &NamespaceTable::canonical()) {
+            Ok((ir_ls, _)) => match ir_ls.into_iter().next() {
+                Some(ir::Instruction::Activity { cs, .. }) => cs.ns,
+                other => panic!("expected an activity, got {:?}", other),
+            },
+            Err(e) => panic!("{} does not parse: {:?}", src, e),
+        };
+        assert_eq!(ns_of("newpages(ns=0)"), Some(HashSet::from([0])));
+        assert_eq!(ns_of("newpages(ns=User talk, since=\"-7d\")"), Some(HashSet::from([3])));
+        assert_eq!(ns_of("contribs(\"A\", ns=\"Template\")"), Some(HashSet::from([10])));
+        assert_eq!(ns_of("recentlychanged(ns=2..3)"), Some(HashSet::from([2, 3])));
+        assert!(matches!(parse_with_namespaces("newpages(ns=Nonexistent)", &NamespaceTable::canonical()), Err(PLBotParserError::Semantic(..))));
+    }
 }
@@ -0,0 +1,205 @@
+//! Resolves the namespaces written in `.ns(...)` constraints.
+//!
+//! A namespace can be written as its number, a range of numbers, or its name or alias on the wiki.
+//! Names are looked up the way MediaWiki does: case-insensitively, with underscores read as spaces.
+//! Ranges and exclusions stand for every namespace of the wiki they cover, so without the namespaces of the wiki,
+//! they are only accepted as far as the canonical namespaces go.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use mediawiki::api::NamespaceID;
+
+use super::ast::{NsItem, NsSpec, Spanned};
+use super::error::{PLBotParserError, Span};
+
+/// Namespaces every MediaWiki installation has, by their canonical names
+const CANONICAL_NAMESPACES: &[(&str, NamespaceID)] = &[
+    ("Media", -2),
+    ("Special", -1),
+    ("", 0),
+    ("Main", 0),
+    ("Talk", 1),
+    ("User", 2),
+    ("User talk", 3),
+    ("Project", 4),
+    ("Project talk", 5),
+    ("File", 6),
+    ("File talk", 7),
+    ("Image", 6),
+    ("Image talk", 7),
+    ("MediaWiki", 8),
+    ("MediaWiki talk", 9),
+    ("Template", 10),
+    ("Template talk", 11),
+    ("Help", 12),
+    ("Help talk", 13),
+    ("Category", 14),
+    ("Category talk", 15),
+];
+/// Namespaces above this one are added by extensions or the wiki itself
+const LAST_CANONICAL_NAMESPACE: NamespaceID = 15;
+
+/// The names and aliases of the namespaces of a wiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceTable {
+    // normalized name or alias -> namespace
+    lookup: HashMap<String, NamespaceID>,
+    // whether the namespaces of the wiki are known, not only the canonical ones
+    complete: bool,
+}
+
+impl NamespaceTable {
+
+    /// Creates a table from the names and aliases of every namespace of a wiki. The canonical names are always included
+    pub fn new(names: impl IntoIterator<Item = (String, NamespaceID)>) -> Self {
+        let mut table = Self::canonical();
+        table.lookup.extend(names.into_iter().map(|(name, id)| (normalize(&name), id)));
+        table.complete = true;
+        table
+    }
+
+    /// Creates a table that only knows the canonical names
+    pub fn canonical() -> Self {
+        NamespaceTable { lookup: CANONICAL_NAMESPACES.iter().map(|(name, id)| (normalize(name), *id)).collect(), complete: false }
+    }
+
+    pub fn get(&self, name: &str) -> Option<NamespaceID> {
+        self.lookup.get(&normalize(name)).copied()
+    }
+
+    /// The namespaces pages can be in, i.e. every known namespace but the virtual ones
+    fn page_namespaces(&self) -> BTreeSet<NamespaceID> {
+        self.lookup.values().copied().filter(|id| *id >= 0).collect()
+    }
+
+    /// Resolves the items of one `.ns(...)` constraint.
+    ///
+    /// Without any positive item, the constraint stands for every namespace pages can be in.
+    /// Negated items are then taken away.
+    ///
+    /// With only the canonical names, a range past them or a constraint without any positive item is an error,
+    /// since it would leave out the namespaces of the wiki.
+    pub(crate) fn resolve(&self, items: &[Spanned<NsItem>]) -> Result<HashSet<NamespaceID>, PLBotParserError> {
+        let mut included: HashSet<NamespaceID> = HashSet::new();
+        let mut excluded: HashSet<NamespaceID> = HashSet::new();
+        let mut has_positive = false;
+        for item in items {
+            let ids: Vec<NamespaceID> = match &item.node.spec {
+                NsSpec::Id(id) => vec![*id],
+                NsSpec::Range(from, to) => {
+                    if from > to {
+                        return Err(PLBotParserError::Semantic(format!("namespace range `{}..{}` is empty", from, to), item.span));
+                    }
+                    if !self.complete && *to > LAST_CANONICAL_NAMESPACE {
+                        return Err(PLBotParserError::Semantic(format!("namespace range `{}..{}` goes past the canonical namespaces, and the namespaces of the wiki are unknown here, list them instead", from, to), item.span));
+                    }
+                    self.page_namespaces().range(*from..=*to).copied().collect()
+                },
+                NsSpec::Name(name) => {
+                    let id = self.get(name).ok_or_else(|| PLBotParserError::Semantic(format!("unknown namespace `{}`", name), item.span))?;
+                    vec![id]
+                },
+            };
+            if item.node.negated {
+                excluded.extend(ids);
+            } else {
+                has_positive = true;
+                included.extend(ids);
+            }
+        }
+        if !has_positive {
+            if !self.complete {
+                let span = items.first().map(|first| first.span.to(&items[items.len() - 1].span)).unwrap_or(Span::new("", 0, 0));
+                return Err(PLBotParserError::Semantic(String::from("the namespaces of the wiki are unknown here, list the namespaces to keep instead of those to leave out"), span));
+            }
+            included = self.page_namespaces().into_iter().collect();
+        }
+        Ok(included.difference(&excluded).copied().collect())
+    }
+
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().replace('_', " ").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(spec: NsSpec, negated: bool) -> Spanned<NsItem> {
+        Spanned { node: NsItem { spec, negated }, span: Span::new("", 0, 0) }
+    }
+
+    fn id(id: NamespaceID) -> Spanned<NsItem> {
+        item(NsSpec::Id(id), false)
+    }
+
+    fn name(name: &str) -> Spanned<NsItem> {
+        item(NsSpec::Name(name.to_owned()), false)
+    }
+
+    fn not(mut item: Spanned<NsItem>) -> Spanned<NsItem> {
+        item.node.negated = true;
+        item
+    }
+
+    fn wiki() -> NamespaceTable {
+        NamespaceTable::new(vec![
+            (String::from("Wikipedia"), 4),
+            (String::from("WP"), 4),
+            (String::from("Portal"), 100),
+            (String::from("Portal talk"), 101),
+            (String::from("Draft"), 118),
+        ])
+    }
+
+    fn set(ids: &[NamespaceID]) -> HashSet<NamespaceID> {
+        ids.iter().copied().collect()
+    }
+
+    fn error_of(table: &NamespaceTable, items: &[Spanned<NsItem>]) -> String {
+        match table.resolve(items) {
+            Err(PLBotParserError::Semantic(msg, _)) => msg,
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        let table = wiki();
+        assert_eq!(table.resolve(&[name("category_TALK"), name(" wp "), id(100)]).unwrap(), set(&[15, 4, 100]));
+        assert_eq!(table.resolve(&[name("Image")]).unwrap(), set(&[6]));
+        assert!(error_of(&table, &[name("Nonexistent")]).contains("unknown namespace `Nonexistent`"));
+        assert!(error_of(&NamespaceTable::canonical(), &[name("Portal")]).contains("unknown namespace"));
+    }
+
+    #[test]
+    fn ranges_cover_known_namespaces() {
+        let table = wiki();
+        assert_eq!(table.resolve(&[item(NsSpec::Range(14, 118), false)]).unwrap(), set(&[14, 15, 100, 101, 118]));
+        assert!(error_of(&table, &[item(NsSpec::Range(2, 1), false)]).contains("is empty"));
+        // the canonical namespaces are every namespace up to 15
+        assert_eq!(NamespaceTable::canonical().resolve(&[item(NsSpec::Range(10, 15), false)]).unwrap(), set(&[10, 11, 12, 13, 14, 15]));
+        assert!(error_of(&NamespaceTable::canonical(), &[item(NsSpec::Range(0, 101), false)]).contains("namespaces of the wiki are unknown"));
+    }
+
+    #[test]
+    fn exclusions_are_taken_away() {
+        let table = wiki();
+        let mut all = table.page_namespaces();
+        all.remove(&2);
+        all.remove(&3);
+        assert_eq!(table.resolve(&[not(name("User")), not(id(3))]).unwrap(), all.into_iter().collect());
+        assert_eq!(table.resolve(&[item(NsSpec::Range(0, 5), false), not(id(4)), not(name("Talk"))]).unwrap(), set(&[0, 2, 3, 5]));
+        // virtual namespaces never hold pages
+        assert!(!table.resolve(&[not(id(0))]).unwrap().contains(&-1));
+    }
+
+    #[test]
+    fn exclusions_need_the_wiki() {
+        let table = NamespaceTable::canonical();
+        assert!(error_of(&table, &[not(name("User"))]).contains("namespaces of the wiki are unknown"));
+        assert_eq!(table.resolve(&[id(0), id(2), not(id(2))]).unwrap(), set(&[0]));
+    }
+}
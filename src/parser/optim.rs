@@ -2,38 +2,47 @@
 //! on an Abstract Syntax Tree (AST).
 //! 
 
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 
-use super::ir::{Instruction, SetConstraint, RegID, DepthNum, RedirectFilterStrategy};
+use super::ir::{Instruction, SetConstraint, Pattern, TitleMatch, PageFilter, ParamFilter, PropFilter, TimeRange, TimePoint, RegID, DepthNum, RedirectFilterStrategy};
+use chrono::{DateTime, NaiveDate, TimeDelta, TimeZone, Utc};
 use mediawiki::api::NamespaceID;
+use regex::Regex;
 
-use super::{ast::*, error::PLBotParserError};
+use super::{ast::*, error::{PLBotParserError, Span}, namespace::NamespaceTable};
 
 /// Convert a `Vec` of `Constraint`s into a `SetConstraint`
-/// Merge all `Ns` constraints (using intersection), set all `Limit` constraints to the minimum, and reject any other duplicate-and-confilcting constraints
-pub(crate) fn construct_constraints_from_vec(orig: &[Constraint]) -> Result<SetConstraint, PLBotParserError> {
+/// Merge all `Ns` constraints (using intersection), set all `Limit` constraints to the minimum, keep every `Match`, `Contains`, `Param` and page metadata constraint, reject page properties required both present and absent, and reject any other duplicate-and-confilcting constraints
+/// Namespace names are resolved with `namespaces`, and title patterns are compiled here so that an invalid one fails the parse
+pub(crate) fn construct_constraints_from_vec(orig: &[Spanned<Constraint>], namespaces: &NamespaceTable) -> Result<SetConstraint, PLBotParserError> {
     let mut depth: Option<DepthNum> = None;
     let mut ns: Option<HashSet<NamespaceID>> = None;
     let mut redir: Option<RedirectFilterStrategy> = None;
     let mut directlink: Option<bool> = None;
     let mut resolveredir: Option<bool> = None;
     let mut limit: Option<i64> = None;
+    let mut titlematch: Vec<TitleMatch> = Vec::new();
+    let mut pagefilter: Vec<PageFilter> = Vec::new();
+    let mut contains: Vec<Pattern> = Vec::new();
+    let mut params: Vec<ParamFilter> = Vec::new();
+    let mut pageprops: Vec<PropFilter> = Vec::new();
 
     for c in orig {
-        match c {
+        match &c.node {
             Constraint::Ns(n) => {
+                let new_set = namespaces.resolve(n)?;
                 if let Some(old_set) = ns {
-                    let new_set = n.iter().copied().collect();
                     let intersect_set = old_set.intersection(&new_set).copied().collect();
                     ns = Some(intersect_set);
                 } else {
-                    ns = Some(n.iter().copied().collect());
+                    ns = Some(new_set);
                 }
             },
             Constraint::Depth(d) => {
                 if let Some(n) = depth {
                     if n != *d && (n >= 0 || *d >= 0) { // Disallow different depth constraints, except they are both negative
-                        return Err(PLBotParserError::Semantic("conflict depth".to_string()));
+                        return Err(PLBotParserError::Semantic("conflict depth".to_string(), c.span));
                     }
                 } else {
                     depth = Some(*d);
@@ -42,7 +51,7 @@ pub(crate) fn construct_constraints_from_vec(orig: &[Constraint]) -> Result<SetC
             Constraint::Redir(s) => {
                 if let Some(ss) = redir {
                     if ss != *s {
-                        return Err(PLBotParserError::Semantic("conflict redirect strategy".to_string()));
+                        return Err(PLBotParserError::Semantic("conflict redirect strategy".to_string(), c.span));
                     }
                 } else {
                     redir = Some(*s);
@@ -51,7 +60,7 @@ pub(crate) fn construct_constraints_from_vec(orig: &[Constraint]) -> Result<SetC
             Constraint::DirectLink(s) => {
                 if let Some(ss) = directlink {
                     if ss != *s {
-                        return Err(PLBotParserError::Semantic("conflict direct link constraint".to_string()));
+                        return Err(PLBotParserError::Semantic("conflict direct link constraint".to_string(), c.span));
                     }
                 } else {
                     directlink = Some(*s);
@@ -60,7 +69,7 @@ pub(crate) fn construct_constraints_from_vec(orig: &[Constraint]) -> Result<SetC
             Constraint::ResolveRedir(s) => {
                 if let Some(ss) = resolveredir {
                     if ss != *s {
-                        return Err(PLBotParserError::Semantic("conflict resolveredir constraint".to_string()));
+                        return Err(PLBotParserError::Semantic("conflict resolveredir constraint".to_string(), c.span));
                     }
                 } else {
                     resolveredir = Some(*s);
@@ -76,63 +85,216 @@ pub(crate) fn construct_constraints_from_vec(orig: &[Constraint]) -> Result<SetC
                 } else {
                     limit = Some(*l);
                 }
-            }
+            },
+            Constraint::Match { pattern, full, negated } => {
+                let new_match = TitleMatch { regex: compile_pattern(pattern, c.span)?, full: *full, negated: *negated };
+                if !titlematch.contains(&new_match) {
+                    titlematch.push(new_match);
+                }
+            },
+            Constraint::Size(op, bytes) => {
+                push_filter(&mut pagefilter, PageFilter::Size(*op, *bytes));
+            },
+            Constraint::Touched(bounds) => {
+                let range = construct_time_range(bounds, c.span)?;
+                push_filter(&mut pagefilter, PageFilter::Touched(range));
+            },
+            Constraint::Created(bounds) => {
+                let range = construct_time_range(bounds, c.span)?;
+                push_filter(&mut pagefilter, PageFilter::Created(range));
+            },
+            Constraint::Protected(action, level) => {
+                push_filter(&mut pagefilter, PageFilter::Protected { action: action.clone(), level: level.clone() });
+            },
+            Constraint::ContentModel(model) => {
+                push_filter(&mut pagefilter, PageFilter::ContentModel(model.clone()));
+            },
+            Constraint::Contains(pattern) => {
+                let pattern = compile_pattern(pattern, c.span)?;
+                if !contains.contains(&pattern) {
+                    contains.push(pattern);
+                }
+            },
+            Constraint::Param(name, pattern) => {
+                push_param(&mut params, ParamFilter::Matches(name.trim().to_owned(), compile_pattern(pattern, c.span)?));
+            },
+            Constraint::NoParam(name) => {
+                push_param(&mut params, ParamFilter::Missing(name.trim().to_owned()));
+            },
+            Constraint::PageProp(name, present) => {
+                push_prop(&mut pageprops, PropFilter { name: name.trim().to_owned(), present: *present }, c.span)?;
+            },
+            Constraint::Wikidata(key, value) => {
+                let present = match (key.as_str(), value.as_str()) {
+                    ("has", "true") => true,
+                    ("has", "false") => false,
+                    _ => return Err(PLBotParserError::Semantic(format!("unknown argument `{}={}`, expected `has=true` or `has=false`", key, value), c.span)),
+                };
+                // the Wikidata item of a page is in its `wikibase_item` property
+                push_prop(&mut pageprops, PropFilter { name: String::from("wikibase_item"), present }, c.span)?;
+            },
         }
     }
-    Ok( SetConstraint { ns, depth, redir, directlink, resolveredir, limit } )
+    Ok( SetConstraint { ns, depth, redir, directlink, resolveredir, limit, titlematch, pagefilter, contains, params, pageprops } )
+}
+
+fn push_param(params: &mut Vec<ParamFilter>, param: ParamFilter) {
+    if !params.contains(&param) {
+        params.push(param);
+    }
+}
+
+fn compile_pattern(pattern: &str, span: Span) -> Result<Pattern, PLBotParserError> {
+    Regex::new(pattern).map(Pattern).map_err(|e| PLBotParserError::Semantic(format!("invalid regular expression: {}", e), span))
+}
+
+fn push_prop(props: &mut Vec<PropFilter>, prop: PropFilter, span: Span) -> Result<(), PLBotParserError> {
+    if props.iter().any(|p| p.name == prop.name && p.present != prop.present) {
+        return Err(PLBotParserError::Semantic("conflict page property constraint".to_string(), span));
+    }
+    if !props.contains(&prop) {
+        props.push(prop);
+    }
+    Ok(())
+}
+
+fn push_filter(filters: &mut Vec<PageFilter>, filter: PageFilter) {
+    if !filters.contains(&filter) {
+        filters.push(filter);
+    }
+}
+
+/// Builds the time span of `.touched(...)` or `.created(...)`. Several bounds on one side narrow the span,
+/// so they must all be relative or all be fixed.
+fn construct_time_range(bounds: &[(TimeBound, String)], span: Span) -> Result<TimeRange, PLBotParserError> {
+    let mut range = TimeRange::default();
+    for (bound, time) in bounds {
+        let time = parse_time_point(time).ok_or_else(|| PLBotParserError::Semantic(format!("invalid time `{}`, expected a date such as `2020-01-01`, a timestamp such as `2020-01-01T00:00:00Z`, or a relative time such as `-7d`", time), span))?;
+        let (side, narrower) = match bound {
+            TimeBound::After => (&mut range.after, Ordering::Greater),
+            TimeBound::Before => (&mut range.before, Ordering::Less),
+        };
+        *side = match *side {
+            None => Some(time),
+            Some(other) => match time.partial_cmp(&other) {
+                Some(order) if order == narrower => Some(time),
+                Some(_) => Some(other),
+                None => return Err(PLBotParserError::Semantic(String::from("cannot narrow a time span with both a relative and a fixed time on one side"), span)),
+            },
+        };
+    }
+    // a relative bound and a fixed one are not ordered, so they are not checked
+    if range.after.zip(range.before).is_some_and(|(after, before)| after >= before) {
+        return Err(PLBotParserError::Semantic(String::from("time span is empty"), span));
+    }
+    Ok(range)
+}
+
+/// The furthest back a relative time may go, about a thousand years
+const MAX_RELATIVE_SECONDS: i64 = 1000 * 366 * 24 * 60 * 60;
+
+/// Parses a time as `parse_time` does, or a time relative to the evaluation such as `-7d`.
+/// Relative times are counted in `s`econds, `m`inutes, `h`ours, `d`ays or `w`eeks, up to `MAX_RELATIVE_SECONDS`.
+pub(crate) fn parse_time_point(time: &str) -> Option<TimePoint> {
+    if let Some(relative) = time.strip_prefix('-') {
+        let unit = match relative.chars().last()? {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let digits = &relative[..relative.len() - 1];
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let count: i64 = digits.parse().ok()?;
+        let seconds = count.checked_mul(unit).filter(|seconds| *seconds <= MAX_RELATIVE_SECONDS)?;
+        return TimeDelta::try_seconds(seconds).map(|_| TimePoint::Relative(seconds));
+    }
+    parse_time(time).map(TimePoint::Absolute)
+}
+
+/// Parses a date, taken at midnight UTC, or a timestamp in the format MediaWiki uses
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(time) {
+        return Some(t.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(time, "%Y-%m-%d").ok()?;
+    Some(Utc.from_utc_datetime(&date.and_hms_opt(0, 0, 0)?))
 }
 
 /// Merge two `SetConstraint`s into one
-/// `Ns` will be merged by intersection, `Limit` will get the minimum number, for other constraints, return error if they conflict.
-pub(crate) fn merge_constraints(orig: &SetConstraint, other: &SetConstraint) -> Result<SetConstraint, PLBotParserError> {
-    let ns = if orig.ns.is_none() {
-        other.ns.clone()
-    } else if other.ns.is_none() {
-        orig.ns.clone()
-    } else {
-        Some(orig.ns.as_ref().unwrap().intersection(other.ns.as_ref().unwrap()).copied().collect())
+/// `Ns` will be merged by intersection, `Limit` will get the minimum number, every title pattern, content pattern, template parameter and page metadata condition must hold, for other constraints, return error if they conflict.
+/// 
+/// `other` must have been constructed from `origin`, which is used to locate the offending constraint in the source.
+pub(crate) fn merge_constraints(orig: &SetConstraint, other: &SetConstraint, origin: &[Spanned<Constraint>]) -> Result<SetConstraint, PLBotParserError> {
+    let ns = match (&orig.ns, &other.ns) {
+        (None, n) | (n, None) => n.clone(),
+        (Some(n1), Some(n2)) => Some(n1.intersection(n2).copied().collect()),
     };
-    let depth = if orig.depth.is_none() {
-        other.depth
-    } else if other.depth.is_none() || (orig.depth.unwrap() == other.depth.unwrap()) || (orig.depth.unwrap() < 0 && other.depth.unwrap() < 0) {
-        orig.depth
-    } else {
-        return Err(PLBotParserError::Semantic(String::from("conflict depth")));
+    let depth = match (orig.depth, other.depth) {
+        (None, d) | (d, None) => d,
+        (Some(d1), Some(d2)) if d1 == d2 || (d1 < 0 && d2 < 0) => Some(d1),
+        _ => return Err(PLBotParserError::Semantic(String::from("conflict depth"), span_of(origin, |c| matches!(c, Constraint::Depth(_))))),
     };
-    let redir = if orig.redir.is_none() {
-        other.redir
-    } else if other.redir.is_none() || orig.redir.unwrap() == other.redir.unwrap() {
-        orig.redir
-    } else {
-        return Err(PLBotParserError::Semantic(String::from("conflict redirect strategy")));
+    let redir = match (orig.redir, other.redir) {
+        (None, r) | (r, None) => r,
+        (Some(r1), Some(r2)) if r1 == r2 => Some(r1),
+        _ => return Err(PLBotParserError::Semantic(String::from("conflict redirect strategy"), span_of(origin, |c| matches!(c, Constraint::Redir(_))))),
     };
-    let directlink = if orig.directlink.is_none() {
-        other.directlink
-    } else if other.directlink.is_none() || orig.directlink.unwrap() == other.directlink.unwrap() {
-        orig.directlink
-    } else {
-        return Err(PLBotParserError::Semantic(String::from("conflict directlink constraint")));
+    let directlink = match (orig.directlink, other.directlink) {
+        (None, d) | (d, None) => d,
+        (Some(d1), Some(d2)) if d1 == d2 => Some(d1),
+        _ => return Err(PLBotParserError::Semantic(String::from("conflict directlink constraint"), span_of(origin, |c| matches!(c, Constraint::DirectLink(_))))),
     };
-    let resolveredir = if orig.resolveredir.is_none() {
-        other.resolveredir
-    } else if other.resolveredir.is_none() || orig.resolveredir.unwrap() == other.resolveredir.unwrap() {
-        orig.resolveredir
-    } else {
-        return Err(PLBotParserError::Semantic(String::from("conflict resolveredir constraint")));
+    let resolveredir = match (orig.resolveredir, other.resolveredir) {
+        (None, r) | (r, None) => r,
+        (Some(r1), Some(r2)) if r1 == r2 => Some(r1),
+        _ => return Err(PLBotParserError::Semantic(String::from("conflict resolveredir constraint"), span_of(origin, |c| matches!(c, Constraint::ResolveRedir(_))))),
     };
-    let limit = if orig.limit.is_none() || orig.limit.unwrap() < 0 {
-        other.limit
-    } else if other.limit.is_none() || other.limit.unwrap() < 0 {
-        orig.limit
-    } else {
-        Some(i64::min(orig.limit.unwrap(), other.limit.unwrap()))
+    let limit = match (orig.limit, other.limit) {
+        (Some(l1), Some(l2)) if l1 >= 0 && l2 >= 0 => Some(i64::min(l1, l2)),
+        (Some(l1), _) if l1 >= 0 => Some(l1),
+        (_, l2) => l2,
     };
+    let mut titlematch = orig.titlematch.clone();
+    for m in &other.titlematch {
+        if !titlematch.contains(m) {
+            titlematch.push(m.clone());
+        }
+    }
+    let mut pagefilter = orig.pagefilter.clone();
+    for f in &other.pagefilter {
+        push_filter(&mut pagefilter, f.clone());
+    }
+    let mut contains = orig.contains.clone();
+    for p in &other.contains {
+        if !contains.contains(p) {
+            contains.push(p.clone());
+        }
+    }
+
+    let mut params = orig.params.clone();
+    for p in &other.params {
+        push_param(&mut params, p.clone());
+    }
+    let mut pageprops = orig.pageprops.clone();
+    for p in &other.pageprops {
+        if pageprops.iter().any(|q| q.name == p.name && q.present != p.present) {
+            return Err(PLBotParserError::Semantic(String::from("conflict page property constraint"), span_of(origin, |c| matches!(c, Constraint::PageProp(..) | Constraint::Wikidata(..)))));
+        }
+        if !pageprops.contains(p) {
+            pageprops.push(p.clone());
+        }
+    }
 
-    Ok(SetConstraint { ns, depth, redir, directlink, resolveredir, limit })
+    Ok(SetConstraint { ns, depth, redir, directlink, resolveredir, limit, titlematch, pagefilter, contains, params, pageprops })
 }
 
 /// Removes consecutive `Toggle` instructions
-pub(crate) fn remove_redundent_talk(ir: &mut Vec<Instruction>) {
+pub(crate) fn remove_redundent_talk(ir: &mut [Instruction]) {
     // iterate through every instruction
     // if we encounter a `Toggle { dest, op }`, check the corresponding instruction whose `dest` is the aforementioned `Toggle` instruction's op
     // if that instruction is also a `Toggle { dest2, op2 }` i.e. `dest2 == op`
@@ -157,7 +319,7 @@ pub(crate) fn remove_redundent_talk(ir: &mut Vec<Instruction>) {
 /// This function mainly tests if an instruction has a namespace constraint
 /// that is empty, i.e. a namespace constraint that allows pages from no namespaces.
 /// Such an constraint ensures that it will always have an empty result.
-pub(crate) fn remove_empty_ns(ir: &mut Vec<Instruction>) {
+pub(crate) fn remove_empty_ns(ir: &mut [Instruction]) {
     // iterate through every instruction
     // if we encounter an instruction that `instruct.ns_empty() == true`
     // the whole subtree where that instruction resides, should be nop
@@ -184,11 +346,28 @@ pub(crate) fn remove_empty_ns(ir: &mut Vec<Instruction>) {
                         Instruction::EmbeddedIn { dest, op, .. } |
                         Instruction::InCat { dest, op, .. } |
                         Instruction::Toggle { dest, op } |
-                        Instruction::Prefix { dest, op, .. } => {
+                        Instruction::Prefix { dest, op, .. } |
+                        Instruction::Categories { dest, op, .. } |
+                        Instruction::Templates { dest, op, .. } |
+                        Instruction::Images { dest, op, .. } |
+                        Instruction::FileUsage { dest, op, .. } |
+                        Instruction::Redirects { dest, op, .. } |
+                        Instruction::Subpages { dest, op, .. } => {
                             let emptyinst = Instruction::Nop { dest: *dest, op: *op };
                             stack.push(*op);
                             ir[idx] = emptyinst;
                         },
+                        Instruction::Filter { dest, .. } |
+                        Instruction::Site { dest, .. } |
+                        Instruction::Search { dest, .. } |
+                        Instruction::Special { dest, .. } |
+                        Instruction::WithProp { dest, .. } |
+                        Instruction::Activity { dest, .. } => {
+                            // do not touch the binding, other uses may still need it
+                            // the other wiki need not be asked at all
+                            let emptyinst = Instruction::Set { dest: *dest, titles: Vec::new(), cs: SetConstraint::new() };
+                            ir[idx] = emptyinst;
+                        },
                         Instruction::Set { dest: _, titles, cs } => {
                             titles.clear();
                             *cs = SetConstraint::new();
@@ -221,3 +400,400 @@ pub(crate) fn remove_nop(ir: &mut Vec<Instruction>) {
         }
     }
 }
+
+/// The outcome of simplifying one instruction
+enum Simplified {
+    /// The instruction always yields the same set as another register
+    Alias(RegID),
+    /// The instruction always yields an empty set
+    Empty,
+    /// The instruction should be replaced by another one
+    Replace(Box<Instruction>),
+    /// Nothing to simplify
+    Keep,
+}
+
+/// Applies set identities to one instruction whose operands are already simplified.
+/// 
+/// `empty` holds the registers known to yield an empty set, `done` holds the instructions simplified so far.
+fn simplify_one(inst: &Instruction, empty: &HashSet<RegID>, done: &[Instruction]) -> Simplified {
+    let find = |reg: RegID| done.binary_search_by(|probe| probe.get_dest().cmp(&reg)).ok().map(|idx| &done[idx]);
+    match *inst {
+        // x & x = x, ∅ & y = x & ∅ = ∅
+        Instruction::And { op1, op2, .. } => {
+            if op1 == op2 {
+                Simplified::Alias(op1)
+            } else if empty.contains(&op1) || empty.contains(&op2) {
+                Simplified::Empty
+            } else {
+                Simplified::Keep
+            }
+        },
+        // x + x = x, x + ∅ = ∅ + x = x
+        Instruction::Or { dest, op1, op2 } => {
+            if op1 == op2 || empty.contains(&op2) {
+                Simplified::Alias(op1)
+            } else if empty.contains(&op1) {
+                Simplified::Alias(op2)
+            } else if let (Some(Instruction::Set { titles: t1, cs: cs1, .. }), Some(Instruction::Set { titles: t2, cs: cs2, .. })) = (find(op1), find(op2)) {
+                // Two literals under the same constraint fold into one literal.
+                // Only union is folded: titles are not normalized yet, so "foo" and "Foo" may turn out to be the same page,
+                // which makes intersection and difference of the raw strings unsafe.
+                if cs1 == cs2 {
+                    let mut titles = t1.clone();
+                    titles.extend(t2.iter().cloned());
+                    Simplified::Replace(Box::new(Instruction::Set { dest, titles, cs: cs1.clone() }))
+                } else {
+                    Simplified::Keep
+                }
+            } else {
+                Simplified::Keep
+            }
+        },
+        // x - x = ∅, ∅ - y = ∅, x - ∅ = x
+        Instruction::Exclude { op1, op2, .. } => {
+            if op1 == op2 || empty.contains(&op1) {
+                Simplified::Empty
+            } else if empty.contains(&op2) {
+                Simplified::Alias(op1)
+            } else {
+                Simplified::Keep
+            }
+        },
+        // x ^ x = ∅, x ^ ∅ = ∅ ^ x = x
+        Instruction::Xor { op1, op2, .. } => {
+            if op1 == op2 {
+                Simplified::Empty
+            } else if empty.contains(&op2) {
+                Simplified::Alias(op1)
+            } else if empty.contains(&op1) {
+                Simplified::Alias(op2)
+            } else {
+                Simplified::Keep
+            }
+        },
+        // reading a binding without any constraint is just the binding itself
+        Instruction::Filter { op, ref cs, .. } if *cs == SetConstraint::new() => Simplified::Alias(op),
+        Instruction::Nop { op, .. } => Simplified::Alias(op),
+        // every unary instruction yields an empty set on an empty input
+        Instruction::Link { op, .. } |
+        Instruction::LinkTo { op, .. } |
+        Instruction::EmbeddedIn { op, .. } |
+        Instruction::InCat { op, .. } |
+        Instruction::Toggle { op, .. } |
+        Instruction::Prefix { op, .. } |
+        Instruction::Categories { op, .. } |
+        Instruction::Templates { op, .. } |
+        Instruction::Images { op, .. } |
+        Instruction::FileUsage { op, .. } |
+        Instruction::Redirects { op, .. } |
+        Instruction::Subpages { op, .. } |
+        Instruction::Filter { op, .. } => {
+            if empty.contains(&op) {
+                Simplified::Empty
+            } else {
+                Simplified::Keep
+            }
+        },
+        Instruction::Set { .. } |
+        Instruction::Site { .. } |
+        Instruction::Search { .. } |
+        Instruction::Special { .. } |
+        Instruction::WithProp { .. } |
+        Instruction::Activity { .. } => Simplified::Keep,
+    }
+}
+
+/// Merges identical instructions into one register, applies set identities, and drops instructions the result does not depend on.
+/// 
+/// Two instructions are identical if they have the same kind, read the same registers and have equal `SetConstraint`s.
+/// Commutative binary instructions are compared regardless of operand order.
+/// 
+/// Must run after `remove_nop`: `ir` must hold no `Nop`, be sorted by `dest` in ascending order,
+/// and every instruction must only read registers written before it. `to_ir` builds instructions in that order,
+/// and `remove_nop` keeps it, because a `Nop` always reads the register right before it.
+/// `find` in `simplify_one` relies on the order to look up operands.
+/// 
+/// Returns the register holding the final result, which may differ from `fin`.
+pub(crate) fn simplify(ir: &mut Vec<Instruction>, fin: RegID) -> RegID {
+    debug_assert!(ir.windows(2).all(|w| w[0].get_dest() < w[1].get_dest()), "IR is not sorted by dest");
+    debug_assert!(ir.iter().all(|inst| !inst.is_nop() && inst.get_operands().iter().all(|op| *op < inst.get_dest())), "IR has a Nop or reads a later register");
+    // `alias` maps a removed register to the register that holds the same set
+    let mut alias: HashMap<RegID, RegID> = HashMap::new();
+    // `seen` maps an instruction (with `dest` zeroed) to the register that first computed it
+    // the patterns in constraints hold a regex cache, but they are hashed and compared by their source only
+    #[allow(clippy::mutable_key_type)]
+    let mut seen: HashMap<Instruction, RegID> = HashMap::new();
+    let mut empty: HashSet<RegID> = HashSet::new();
+    let mut done: Vec<Instruction> = Vec::with_capacity(ir.len());
+
+    for mut inst in ir.drain(..) {
+        inst.map_operands(|reg| *alias.get(&reg).unwrap_or(&reg));
+        let dest = inst.get_dest();
+        match simplify_one(&inst, &empty, &done) {
+            Simplified::Alias(reg) => {
+                alias.insert(dest, reg);
+                continue;
+            },
+            Simplified::Empty => {
+                inst = Instruction::Set { dest, titles: Vec::new(), cs: SetConstraint::new() };
+            },
+            Simplified::Replace(new_inst) => {
+                inst = *new_inst;
+            },
+            Simplified::Keep => {},
+        }
+        // bring instructions into a canonical form before hash-consing
+        match &mut inst {
+            Instruction::And { op1, op2, .. } |
+            Instruction::Or { op1, op2, .. } |
+            Instruction::Xor { op1, op2, .. } if *op1 > *op2 => {
+                std::mem::swap(op1, op2);
+            },
+            Instruction::Set { titles, cs, .. } => {
+                titles.sort_unstable();
+                titles.dedup();
+                if titles.is_empty() {
+                    *cs = SetConstraint::new();
+                    empty.insert(dest);
+                }
+            },
+            _ => {},
+        }
+        let mut key = inst.clone();
+        key.set_dest(0);
+        if let Some(reg) = seen.get(&key) {
+            alias.insert(dest, *reg);
+            continue;
+        }
+        seen.insert(key, dest);
+        done.push(inst);
+    }
+    let fin = *alias.get(&fin).unwrap_or(&fin);
+
+    // walk backwards from the result, keep only what it depends on
+    let mut live: HashSet<RegID> = HashSet::from([fin]);
+    for inst in done.iter().rev() {
+        if live.contains(&inst.get_dest()) {
+            live.extend(inst.get_operands());
+        }
+    }
+    done.retain(|inst| live.contains(&inst.get_dest()));
+    *ir = done;
+    fin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(dest: RegID, titles: &[&str]) -> Instruction {
+        Instruction::Set { dest, titles: titles.iter().map(|t| t.to_string()).collect(), cs: SetConstraint::new() }
+    }
+
+    fn ns_cs(ns: NamespaceID) -> SetConstraint {
+        SetConstraint { ns: Some(HashSet::from([ns])), ..SetConstraint::new() }
+    }
+
+    fn link(dest: RegID, op: RegID) -> Instruction {
+        Instruction::Link { dest, op, cs: SetConstraint::new() }
+    }
+
+    #[test]
+    fn and_with_itself_is_an_alias() {
+        let mut ir = vec![set(0, &["A"]), link(1, 0), Instruction::And { dest: 2, op1: 1, op2: 1 }];
+        let fin = simplify(&mut ir, 2);
+        assert_eq!(fin, 1);
+        assert_eq!(ir, vec![set(0, &["A"]), link(1, 0)]);
+    }
+
+    #[test]
+    fn exclude_from_itself_is_empty() {
+        let mut ir = vec![set(0, &["A"]), link(1, 0), Instruction::Exclude { dest: 2, op1: 1, op2: 1 }];
+        let fin = simplify(&mut ir, 2);
+        assert_eq!(fin, 2);
+        assert_eq!(ir, vec![set(2, &[])]);
+    }
+
+    #[test]
+    fn union_of_sets_is_folded() {
+        let mut ir = vec![set(0, &["B", "A"]), set(1, &["C", "A"]), Instruction::Or { dest: 2, op1: 0, op2: 1 }];
+        let fin = simplify(&mut ir, 2);
+        assert_eq!(fin, 2);
+        assert_eq!(ir, vec![set(2, &["A", "B", "C"])]);
+    }
+
+    #[test]
+    fn union_of_sets_with_different_constraints_is_kept() {
+        let mut ir = vec![
+            set(0, &["A"]),
+            Instruction::Set { dest: 1, titles: vec!["B".to_string()], cs: ns_cs(14) },
+            Instruction::Or { dest: 2, op1: 0, op2: 1 },
+        ];
+        let expected = ir.clone();
+        let fin = simplify(&mut ir, 2);
+        assert_eq!(fin, 2);
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn commutative_instructions_are_merged() {
+        let mut ir = vec![
+            set(0, &["A"]),
+            link(1, 0),
+            Instruction::InCat { dest: 2, op: 0, cs: SetConstraint::new() },
+            Instruction::And { dest: 3, op1: 1, op2: 2 },
+            Instruction::And { dest: 4, op1: 2, op2: 1 },
+            Instruction::Or { dest: 5, op1: 3, op2: 4 },
+        ];
+        let fin = simplify(&mut ir, 5);
+        assert_eq!(fin, 3);
+        assert_eq!(ir, vec![
+            set(0, &["A"]),
+            link(1, 0),
+            Instruction::InCat { dest: 2, op: 0, cs: SetConstraint::new() },
+            Instruction::And { dest: 3, op1: 1, op2: 2 },
+        ]);
+    }
+
+    #[test]
+    fn instructions_with_different_constraints_are_not_merged() {
+        let mut ir = vec![
+            set(0, &["A"]),
+            link(1, 0),
+            Instruction::Link { dest: 2, op: 0, cs: ns_cs(0) },
+            Instruction::Xor { dest: 3, op1: 1, op2: 2 },
+        ];
+        let expected = ir.clone();
+        let fin = simplify(&mut ir, 3);
+        assert_eq!(fin, 3);
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn unary_instruction_on_empty_set_is_empty() {
+        let mut ir = vec![set(0, &[]), link(1, 0), Instruction::Or { dest: 2, op1: 1, op2: 1 }];
+        let fin = simplify(&mut ir, 2);
+        // the empty results are merged into the first empty set
+        assert_eq!(fin, 0);
+        assert_eq!(ir, vec![set(0, &[])]);
+    }
+
+    #[test]
+    fn unconstrained_filter_is_an_alias() {
+        let mut ir = vec![
+            set(0, &["A"]),
+            link(1, 0),
+            Instruction::Filter { dest: 2, op: 1, cs: SetConstraint::new() },
+            Instruction::Filter { dest: 3, op: 1, cs: ns_cs(0) },
+            Instruction::Exclude { dest: 4, op1: 2, op2: 3 },
+        ];
+        let fin = simplify(&mut ir, 4);
+        assert_eq!(fin, 4);
+        assert_eq!(ir, vec![
+            set(0, &["A"]),
+            link(1, 0),
+            Instruction::Filter { dest: 3, op: 1, cs: ns_cs(0) },
+            Instruction::Exclude { dest: 4, op1: 1, op2: 3 },
+        ]);
+    }
+
+    #[test]
+    fn dead_instructions_are_dropped() {
+        let mut ir = vec![set(0, &["A"]), link(1, 0), set(2, &["B"]), Instruction::Toggle { dest: 3, op: 2 }];
+        let fin = simplify(&mut ir, 3);
+        assert_eq!(fin, 3);
+        assert_eq!(ir, vec![set(2, &["B"]), Instruction::Toggle { dest: 3, op: 2 }]);
+    }
+
+    #[test]
+    fn remove_nop_keeps_ir_sorted() {
+        let mut ir = vec![set(0, &["A"]), link(1, 0), Instruction::Nop { dest: 2, op: 1 }, Instruction::Toggle { dest: 3, op: 2 }];
+        remove_nop(&mut ir);
+        assert_eq!(ir, vec![set(0, &["A"]), link(2, 0), Instruction::Toggle { dest: 3, op: 2 }]);
+        assert_eq!(simplify(&mut ir, 3), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "IR is not sorted by dest")]
+    fn unsorted_ir_is_rejected() {
+        let mut ir = vec![link(1, 0), set(0, &["A"])];
+        simplify(&mut ir, 1);
+    }
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, min: u32, sec: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, m, d, h, min, sec).unwrap()
+    }
+
+    fn semantic_message(res: Result<TimeRange, PLBotParserError>) -> String {
+        match res {
+            Err(PLBotParserError::Semantic(msg, _)) => msg,
+            other => panic!("expected a semantic error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn times_are_parsed() {
+        assert_eq!(parse_time("2020-01-02"), Some(utc(2020, 1, 2, 0, 0, 0)));
+        assert_eq!(parse_time("2020-01-02T03:04:05Z"), Some(utc(2020, 1, 2, 3, 4, 5)));
+        // an offset is taken into account
+        assert_eq!(parse_time("2020-01-02T03:04:05+02:00"), Some(utc(2020, 1, 2, 1, 4, 5)));
+        assert_eq!(parse_time("2020-02-30"), None);
+        assert_eq!(parse_time("2020-01-02 03:04:05"), None);
+        assert_eq!(parse_time("yesterday"), None);
+        assert_eq!(parse_time(""), None);
+    }
+
+    #[test]
+    fn time_points_are_parsed() {
+        assert_eq!(parse_time_point("-30m"), Some(TimePoint::Relative(30 * 60)));
+        assert_eq!(parse_time_point("-12h"), Some(TimePoint::Relative(12 * 60 * 60)));
+        assert_eq!(parse_time_point("-2w"), Some(TimePoint::Relative(14 * 24 * 60 * 60)));
+        assert_eq!(parse_time_point("-0s"), Some(TimePoint::Relative(0)));
+        assert_eq!(parse_time_point("2020-01-02"), Some(TimePoint::Absolute(utc(2020, 1, 2, 0, 0, 0))));
+        assert_eq!(parse_time_point("2020-01-02T03:04:05Z"), Some(TimePoint::Absolute(utc(2020, 1, 2, 3, 4, 5))));
+        assert_eq!(parse_time_point("-52000w"), Some(TimePoint::Relative(52000 * 7 * 24 * 60 * 60)));
+        for bad in ["-7x", "-d", "--1d", "-+1d", "- 1d", "-1.5d", "7d", "-99999999999999999w", "-99999999999999d", "-100000000d", "yesterday", ""] {
+            assert_eq!(parse_time_point(bad), None, "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn time_range_takes_the_narrowest_bounds() {
+        let span = Span::new("", 0, 0);
+        let bounds = [
+            (TimeBound::After, String::from("2020-01-01")),
+            (TimeBound::After, String::from("2020-06-01")),
+            (TimeBound::Before, String::from("2021-01-01T00:00:00Z")),
+            (TimeBound::Before, String::from("2022-01-01")),
+        ];
+        let range = construct_time_range(&bounds, span).unwrap();
+        assert_eq!(range, TimeRange { after: Some(TimePoint::Absolute(utc(2020, 6, 1, 0, 0, 0))), before: Some(TimePoint::Absolute(utc(2021, 1, 1, 0, 0, 0))) });
+        let open = construct_time_range(&[(TimeBound::Before, String::from("2020-01-01"))], span).unwrap();
+        assert_eq!(open, TimeRange { after: None, before: Some(TimePoint::Absolute(utc(2020, 1, 1, 0, 0, 0))) });
+        let relative = [
+            (TimeBound::After, String::from("-2w")),
+            (TimeBound::After, String::from("-7d")),
+            (TimeBound::Before, String::from("-1d")),
+            (TimeBound::Before, String::from("-12h")),
+        ];
+        let range = construct_time_range(&relative, span).unwrap();
+        assert_eq!(range, TimeRange { after: Some(TimePoint::Relative(7 * 24 * 60 * 60)), before: Some(TimePoint::Relative(24 * 60 * 60)) });
+        // one side relative and the other fixed
+        let mixed = [(TimeBound::After, String::from("-7d")), (TimeBound::Before, String::from("2020-01-01"))];
+        assert_eq!(construct_time_range(&mixed, span).unwrap(), TimeRange { after: Some(TimePoint::Relative(7 * 24 * 60 * 60)), before: Some(TimePoint::Absolute(utc(2020, 1, 1, 0, 0, 0))) });
+    }
+
+    #[test]
+    fn bad_time_ranges_are_rejected() {
+        let span = Span::new("", 0, 0);
+        let empty = [(TimeBound::After, String::from("2020-01-01")), (TimeBound::Before, String::from("2020-01-01"))];
+        assert_eq!(semantic_message(construct_time_range(&empty, span)), "time span is empty");
+        let empty = [(TimeBound::After, String::from("-1d")), (TimeBound::Before, String::from("-2d"))];
+        assert_eq!(semantic_message(construct_time_range(&empty, span)), "time span is empty");
+        let invalid = [(TimeBound::After, String::from("-7x"))];
+        assert!(semantic_message(construct_time_range(&invalid, span)).starts_with("invalid time `-7x`"));
+        let mixed = [(TimeBound::After, String::from("-7d")), (TimeBound::After, String::from("2020-01-01"))];
+        assert!(semantic_message(construct_time_range(&mixed, span)).starts_with("cannot narrow a time span"));
+    }
+}
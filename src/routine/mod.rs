@@ -6,6 +6,7 @@ pub mod taskrunner;
 mod queryexecutor;
 mod pagewriter;
 
-mod types;
+pub(crate) mod types;
 
 pub use taskfinder::TaskFinder;
+pub(crate) use pagewriter::placeholders;
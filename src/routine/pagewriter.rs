@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::{HashMap, HashSet}, sync::Arc};
 
 use futures::future::join_all;
 use md5::{Md5, Digest};
@@ -7,9 +7,31 @@ use tokio::sync::Mutex;
 use tracing::{event, Level, Instrument, span};
 
 use super::{types::OutputFormat, queryexecutor::{QueryExecutor, QueryExecutorError}};
-use crate::API_SERVICE;
+use crate::backend::WikiBackend;
+
+/// Finds the `$` sequences of an output template, with the character index of each `$`.
+/// Each is named by what follows the `$`, such as `0`, `wd`, or `$` for `$$`, and a trailing `$` has no name.
+pub(crate) fn placeholders(template: &str) -> Vec<(usize, Option<String>)> {
+    let mut found = Vec::new();
+    let mut chars = template.chars().enumerate().peekable();
+    while let Some((idx, c)) = chars.next() {
+        if c != '$' {
+            continue;
+        }
+        let name = chars.next().map(|(_, next)| {
+            if next == 'w' && chars.next_if(|(_, d)| *d == 'd').is_some() {
+                String::from("wd")
+            } else {
+                next.to_string()
+            }
+        });
+        found.push((idx, name));
+    }
+    found
+}
 
 pub(crate) struct PageWriter<'a> {
+    backend: Arc<dyn WikiBackend>,
     task_id: i64,
     query_executor: Mutex<QueryExecutor>,
     eager_mode: bool,
@@ -20,8 +42,9 @@ pub(crate) struct PageWriter<'a> {
 
 impl<'a> PageWriter<'a> {
 
-    pub fn new(query_exec: QueryExecutor) -> Self {
+    pub fn new(backend: Arc<dyn WikiBackend>, query_exec: QueryExecutor) -> Self {
         PageWriter {
+            backend,
             task_id: 0,
             query_executor: Mutex::new(query_exec),
             eager_mode: false,
@@ -73,11 +96,16 @@ impl<'a> PageWriter<'a> {
             Ok(_) => "success",
             Err(e) => match e {
                 QueryExecutorError::Timeout => "timeout",
-                QueryExecutorError::Parse => "parse",
+                QueryExecutorError::Parse(_) => "parse",
                 QueryExecutorError::Solve => "runtime",
             }
         };
-        format!("<noinclude>{{{{subst:{header}|taskid={id}|status={status}}}}}</noinclude>", header=self.header_template_name, id=self.task_id, status=status_text)
+        // Parse errors carry locations and expected tokens, pass them to the header so that task authors can see them
+        let detail = match result {
+            Err(QueryExecutorError::Parse(e)) => format!("|detail=<nowiki>{}</nowiki>", e.to_string().replace('<', "&lt;")),
+            _ => String::new(),
+        };
+        format!("<noinclude>{{{{subst:{header}|taskid={id}|status={status}{detail}}}}}</noinclude>", header=self.header_template_name, id=self.task_id, status=status_text, detail=detail)
     }
 
     fn substitute_str_template(&self, template: &str, total_num: usize) -> String {
@@ -101,19 +129,26 @@ impl<'a> PageWriter<'a> {
         output
     }
     
-    async fn substitute_str_template_with_title(&self, template: &str, t: &Title, current_num: usize, total_num: usize) -> String {
+    async fn substitute_str_template_with_title(&self, template: &str, t: &Title, current_num: usize, total_num: usize, items: &HashMap<Title, String>) -> String {
         let mut output: String = String::new();
         let mut escape: bool = false;
-        for char in template.chars() {
+        let mut chars = template.chars().peekable();
+        while let Some(char) = chars.next() {
             if escape {
-                // only accept $0 (full name), $1 (namespace), $2 (name), $@ (current index), $+ (total size), $$ ($)
+                // only accept $0 (full name), $1 (namespace), $2 (name), $@ (current index), $+ (total size), $wd (Wikidata item), $$ ($)
                 match char {
                     '$' => { output.push('$'); },
-                    '0' => { output.push_str(&API_SERVICE.full_pretty(t).await.unwrap_or_else(|_| Some("".to_string())).unwrap_or_else(|| "".to_string())); },
-                    '1' => { output.push_str(&API_SERVICE.namespace_name(t).await.unwrap_or_else(|_| Some("".to_string())).unwrap_or_else(|| "".to_string())); },
+                    '0' => { output.push_str(&self.backend.full_pretty(t).await.unwrap_or_else(|_| Some("".to_string())).unwrap_or_else(|| "".to_string())); },
+                    '1' => { output.push_str(&self.backend.namespace_name(t).await.unwrap_or_else(|_| Some("".to_string())).unwrap_or_else(|| "".to_string())); },
                     '2' => { output.push_str(t.pretty()); },
                     '@' => { output.push_str(&current_num.to_string()) },
                     '+' => { output.push_str(&total_num.to_string()) },
+                    'w' if chars.peek() == Some(&'d') => {
+                        chars.next();
+                        if let Some(item) = items.get(t) {
+                            output.push_str(item);
+                        }
+                    },
                     _ => { output.push('$'); output.push(char); },
                 }
                 escape = false;
@@ -126,6 +161,38 @@ impl<'a> PageWriter<'a> {
         output
     }
 
+    /// Looks up the Wikidata items linked to `titles`, in batches of 50. Pages without an item are left out.
+    async fn get_wikidata_items(&self, titles: &[Title]) -> HashMap<Title, String> {
+        let mut names: Vec<String> = Vec::new();
+        for t in titles {
+            if let Ok(Some(name)) = self.backend.full_pretty(t).await {
+                names.push(name);
+            }
+        }
+        let mut items: HashMap<Title, String> = HashMap::new();
+        for batch in names.chunks(50) {
+            let params = hashmap![
+                "action".to_string() => "query".to_string(),
+                "prop".to_string() => "pageprops".to_string(),
+                "ppprop".to_string() => "wikibase_item".to_string(),
+                "titles".to_string() => batch.join("|")
+            ];
+            match self.backend.get_all(&params).await {
+                Ok(res) => {
+                    if let Some(pages) = res["query"]["pages"].as_array() {
+                        for page in pages {
+                            if let Some(item) = page["pageprops"]["wikibase_item"].as_str() {
+                                items.insert(Title::new_from_api_result(page), item.to_owned());
+                            }
+                        }
+                    }
+                },
+                Err(e) => event!(Level::WARN, error = ?e, "cannot fetch wikidata items"),
+            }
+        }
+        items
+    }
+
     fn get_md5(&self, text: &str) -> String {
         let mut hasher = Md5::new();
         hasher.update(text);
@@ -140,14 +207,10 @@ impl<'a> PageWriter<'a> {
             "prop".to_string() => "info".to_string(),
             "titles".to_string() => outputformat.target.clone()
         ];
-        let page_query = {
-            API_SERVICE.get_lock().lock().await;
-            API_SERVICE.get(&params).await
-        };
-        if page_query.is_err() {
-            event!(Level::WARN, error = ?page_query.unwrap_err(), "cannot fetch page information");
-        } else {
-            let res = page_query.unwrap();
+        let page_query = self.backend.get(&params).await;
+        if let Err(e) = page_query {
+            event!(Level::WARN, error = ?e, "cannot fetch page information");
+        } else if let Ok(res) = page_query {
             let info = res["query"]["pages"].as_array().unwrap()[0].as_object().unwrap();
             if info.get("missing").is_some() {
                 event!(Level::INFO, "target page does not exist, skip");
@@ -177,10 +240,16 @@ impl<'a> PageWriter<'a> {
                                     Ok(outputformat.empty.clone())
                                 } else {
                                     let list_size = ls.len();
+                                    let items = if placeholders(&outputformat.success.item).iter().any(|(_, name)| name.as_deref() == Some("wd")) {
+                                        self.get_wikidata_items(ls).await
+                                    } else {
+                                        HashMap::new()
+                                    };
+                                    let items = &items;
                                     let mut output: String = String::new();
                                     output.push_str(&self.substitute_str_template(&outputformat.success.before, list_size));
                                     let item_str: String = join_all(ls.iter().enumerate().map(|(idx, t)| async move {
-                                        self.substitute_str_template_with_title(&outputformat.success.item, t, idx + 1, list_size).await
+                                        self.substitute_str_template_with_title(&outputformat.success.item, t, idx + 1, list_size, items).await
                                     })).await.join(&self.substitute_str_template(&outputformat.success.between, list_size));
                                     output.push_str(&item_str);
                                     output.push_str(&self.substitute_str_template(&outputformat.success.after, list_size));
@@ -210,10 +279,7 @@ impl<'a> PageWriter<'a> {
                                     "rvprop".to_string() => "content".to_string(),
                                     "rvlimit".to_string() => "1".to_string()
                                 ];
-                                let page_content = {
-                                    API_SERVICE.get_lock().lock().await;
-                                    API_SERVICE.get(&params).await
-                                };
+                                let page_content = self.backend.get(&params).await;
                                 if let Ok(page_content) = page_content {
                                     let page_content_str = page_content["query"]["pages"][0]["revisions"][0]["slots"]["main"]["content"].as_str();
                                     if let Some(page_content_str) = page_content_str {
@@ -265,14 +331,11 @@ impl<'a> PageWriter<'a> {
                             "summary".to_string() => summary,
                             "md5".to_string() => md5,
                             "nocreate".to_string() => "1".to_string(),
-                            "token".to_string() => API_SERVICE.csrf().await
+                            "token".to_string() => self.backend.csrf().await
                         ];
-                        let edit_result = {
-                            API_SERVICE.get_lock().lock().await;
-                            API_SERVICE.post_edit(&params).await
-                        };
-                        if edit_result.is_err() {
-                            event!(Level::WARN, error = ?edit_result.unwrap_err(), "cannot edit page");
+                        let edit_result = self.backend.post_edit(&params).await;
+                        if let Err(e) = edit_result {
+                            event!(Level::WARN, error = ?e, "cannot edit page");
                         } else {
                             event!(Level::INFO, "edit page successful");
                         }
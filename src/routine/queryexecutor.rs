@@ -1,16 +1,24 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
 use mediawiki::title::Title;
 use tracing::{event, Level};
 
-use crate::API_SERVICE;
+use crate::backend::WikiBackend;
+use crate::dbservice::DBService;
+use crate::parser::{NamespaceTable, PLBotParserError};
+use crate::solver::{SolveError, SolverConfig};
 use super::types::TaskConfig;
 
 pub enum QueryExecutorError {
     Timeout,
-    Parse,
+    Parse(PLBotParserError),
     Solve,
 }
 
 pub struct QueryExecutor {
+    backend: Arc<dyn WikiBackend>,
+    db: Option<Arc<DBService>>,
     query: String,
     querylimit: TaskConfig,
 
@@ -18,8 +26,21 @@ pub struct QueryExecutor {
 }
 
 impl QueryExecutor {
-    pub fn new(query: &str, limit: &TaskConfig) -> Self {
-        QueryExecutor { query: query.to_string(), querylimit: limit.clone(), result: None }
+    pub fn new(backend: Arc<dyn WikiBackend>, db: Option<Arc<DBService>>, query: &str, limit: &TaskConfig) -> Self {
+        QueryExecutor { backend, db, query: query.to_string(), querylimit: limit.clone(), result: None }
+    }
+
+    /// Solves with the database if there is one, falling back to the API if the database fails
+    async fn solve(&self, query_inst: &crate::parser::Query, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+        if let Some(db) = &self.db {
+            match crate::solver::solve_db(self.backend.as_ref(), db, query_inst, config).await {
+                Err(SolveError::DBService(e)) => {
+                    event!(Level::WARN, error = ?e, "database failure, falling back to API");
+                },
+                result => return result,
+            }
+        }
+        crate::solver::solve_api(self.backend.as_ref(), query_inst, config).await
     }
 
     pub async fn execute(&mut self) -> &Result<Vec<Title>, QueryExecutorError> {
@@ -27,39 +48,46 @@ impl QueryExecutor {
         if self.result.is_none() {
             event!(Level::INFO, "executor lazy loads");
             // run the query first
-            let parse_result = crate::parser::parse(&self.query);
-            if parse_result.is_err() {
-                event!(Level::WARN, error = ?parse_result.unwrap_err(), "parse failure");
-                self.result = Some(Err(QueryExecutorError::Parse));
-            } else {
-                let query_inst = parse_result.unwrap();
-                let query_result = {
-                    API_SERVICE.get_lock().lock().await;
-                    tokio::time::timeout(tokio::time::Duration::from_secs(self.querylimit.timeout), crate::solver::solve_api(&query_inst, self.querylimit.querylimit)).await
-                };
+            let namespaces = match self.backend.namespace_names().await {
+                Ok(names) => NamespaceTable::new(names),
+                Err(e) => {
+                    event!(Level::WARN, error = ?e, "cannot fetch namespace names, only canonical names are understood");
+                    NamespaceTable::canonical()
+                },
+            };
+            let parse_result = crate::parser::parse_with_namespaces(&self.query, &namespaces);
+            match parse_result {
+                Err(e) => {
+                    event!(Level::WARN, error = ?e, "parse failure");
+                    self.result = Some(Err(QueryExecutorError::Parse(e)));
+                },
+                Ok(query_inst) => {
+                    let config = SolverConfig { querylimit: self.querylimit.querylimit, fanout: self.querylimit.fanout, parallelism: self.querylimit.parallelism };
+                    let query_result = tokio::time::timeout(tokio::time::Duration::from_secs(self.querylimit.timeout), self.solve(&query_inst, &config)).await;
 
-                if query_result.is_err() {
-                    event!(Level::WARN, "query timeout");
-                    self.result = Some(Err(QueryExecutorError::Timeout));
-                } else {
-                    let query_result = query_result.unwrap();
-                    if query_result.is_err() {
-                        event!(Level::WARN, error = ?query_result.unwrap_err(), "solve failure");
-                        self.result = Some(Err(QueryExecutorError::Solve));
-                    } else {
-                        let query_result = query_result.unwrap();
-                        let mut titles_vec = Vec::from_iter(query_result.into_iter());
-                        titles_vec.sort_by(|a, b| {
-                            match a.namespace_id().cmp(&b.namespace_id()) {
-                                std::cmp::Ordering::Greater => std::cmp::Ordering::Greater,
-                                std::cmp::Ordering::Less => std::cmp::Ordering::Less,
-                                std::cmp::Ordering::Equal => a.pretty().cmp(b.pretty()),
-                            }
-                        });
-                        self.result = Some(Ok(titles_vec));
+                    match query_result {
+                        Err(_) => {
+                            event!(Level::WARN, "query timeout");
+                            self.result = Some(Err(QueryExecutorError::Timeout));
+                        },
+                        Ok(Err(e)) => {
+                            event!(Level::WARN, error = ?e, "solve failure");
+                            self.result = Some(Err(QueryExecutorError::Solve));
+                        },
+                        Ok(Ok(query_result)) => {
+                            let mut titles_vec = Vec::from_iter(query_result);
+                            titles_vec.sort_by(|a, b| {
+                                match a.namespace_id().cmp(&b.namespace_id()) {
+                                    std::cmp::Ordering::Greater => std::cmp::Ordering::Greater,
+                                    std::cmp::Ordering::Less => std::cmp::Ordering::Less,
+                                    std::cmp::Ordering::Equal => a.pretty().cmp(b.pretty()),
+                                }
+                            });
+                            self.result = Some(Ok(titles_vec));
+                            event!(Level::INFO, "query successful");
+                        },
                     }
-                    event!(Level::INFO, "query successful");
-                }
+                },
             }
         }
         self.result.as_ref().unwrap()
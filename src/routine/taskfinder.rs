@@ -4,12 +4,15 @@ use mediawiki::{hashmap, api::NamespaceID};
 use tokio::{task::JoinHandle, sync::{RwLock, Mutex}};
 use tracing::{event, Level, Instrument, span};
 
-use crate::API_SERVICE;
+use crate::backend::{self, WikiBackend};
+use crate::dbservice::DBService;
 
 use super::types::{SiteConfig, TaskConfig};
 use super::taskrunner::TaskRunner;
 
 pub struct TaskFinder {
+    backend: Arc<dyn WikiBackend>,
+    db: Mutex<Option<Arc<DBService>>>,
     on_site_config_location: Mutex<String>,
 
     global_activate: Arc<RwLock<bool>>,
@@ -23,8 +26,10 @@ pub struct TaskFinder {
 
 impl TaskFinder {
 
-    pub fn new() -> Self {
+    pub fn new(backend: Arc<dyn WikiBackend>) -> Self {
         TaskFinder {
+            backend,
+            db: Mutex::new(None),
             on_site_config_location: Mutex::new("".to_owned()),
 
             global_activate: Arc::new(RwLock::new(false)),
@@ -42,9 +47,17 @@ impl TaskFinder {
         *self_config_loc = config_location.to_owned();
     }
 
-    pub async fn start(&'static self) {
-        _ = tokio::task::spawn_blocking(|| self.stop()).await;
-        let handle = tokio::spawn(async {
+    /// Sets the replica database used by task runners created from now on
+    pub async fn set_db(&self, db: Option<Arc<DBService>>) {
+        let mut self_db = self.db.lock().await;
+        *self_db = db;
+    }
+
+    pub async fn start(self: &Arc<Self>) {
+        let this = self.clone();
+        _ = tokio::task::spawn_blocking(move || this.stop()).await;
+        let this = self.clone();
+        let handle = tokio::spawn(async move {
             loop {
                 event!(Level::INFO, "task finder starts");
                 // fetch on-site config
@@ -54,17 +67,14 @@ impl TaskFinder {
                         "action".to_string() => "query".to_string(),
                         "prop".to_string() => "revisions".to_string(),
                         "titles".to_string() => {
-                            let lock = self.on_site_config_location.lock().await;
+                            let lock = this.on_site_config_location.lock().await;
                             (*lock).clone()
                         },
                         "rvslots".to_string() => "*".to_string(),
                         "rvprop".to_string() => "content".to_string(),
                         "rvlimit".to_string() => "1".to_string()
                     ];
-                    let page_content = {
-                        API_SERVICE.get_lock().lock().await;
-                        API_SERVICE.get(&params).await
-                    };
+                    let page_content = backend::fresh(&this.backend).get(&params).await;
                     if let Ok(page_content) = page_content {
                         let page_content_str = page_content["query"]["pages"][0]["revisions"][0]["slots"]["main"]["content"].as_str();
                         if let Some(page_content_str) = page_content_str {
@@ -88,25 +98,25 @@ impl TaskFinder {
                     event!(Level::INFO, "on-site config fetch successful");
                     // update global params
                     {
-                        let mut global_activate = self.global_activate.write().await;
+                        let mut global_activate = this.global_activate.write().await;
                         *global_activate = config.activate;
                     }
                     {
-                        let mut global_query_config = self.global_query_config.write().await;
+                        let mut global_query_config = this.global_query_config.write().await;
                         *global_query_config = config.default;
                     }
                     {
-                        let mut global_denied_namespace = self.global_denied_namespace.write().await;
+                        let mut global_denied_namespace = this.global_denied_namespace.write().await;
                         *global_denied_namespace = HashSet::from_iter(config.denyns);
                     }
                     {
-                        let mut global_output_header = self.global_output_header.write().await;
+                        let mut global_output_header = this.global_output_header.write().await;
                         *global_output_header = config.resultheader;
                     }
                     event!(Level::INFO, "global params update successful");
                     // fetch tasks
                     // so long as we can get site config, there is always an `Api` present in the service
-                    let taskdir_title = API_SERVICE.title_new_from_full(&config.taskdir).await.unwrap(); 
+                    let taskdir_title = this.backend.title_new_from_full(&config.taskdir).await.unwrap(); 
                     let params = hashmap![
                         "action".to_string() => "query".to_string(),
                         "prop".to_string() => "info".to_string(),
@@ -116,10 +126,7 @@ impl TaskFinder {
                         "gaplimit".to_string() => "max".to_string(),
                         "gapfilterredir".to_string() => "nonredirects".to_string()
                     ];
-                    let tasks = {
-                        API_SERVICE.get_lock().lock().await;
-                        API_SERVICE.get_all(&params).await
-                    };
+                    let tasks = backend::fresh(&this.backend).get_all(&params).await;
                     if let Ok(tasks_result) = tasks {
                         let tasks = tasks_result["query"]["pages"].as_array().unwrap();
                         // gather all tasks
@@ -133,13 +140,14 @@ impl TaskFinder {
                         }
                         event!(Level::DEBUG, pool = ?task_pool, count = task_pool.len(), "task gathered");
                         {
-                            let mut task_map = self.task_map.lock().await;
+                            let db = this.db.lock().await.clone();
+                            let mut task_map = this.task_map.lock().await;
                             // kill all tasks whose id does not live in the pool
                             (*task_map).retain(|k, _| task_pool.contains(k));
                             // create and start new tasks
                             for id in task_pool {
                                 (*task_map).entry(id).or_insert_with(|| {
-                                    let mut task_runner: TaskRunner = TaskRunner::new(id, self.global_activate.clone(), self.global_query_config.clone(), self.global_denied_namespace.clone(), self.global_output_header.clone());
+                                    let mut task_runner: TaskRunner = TaskRunner::new(id, this.backend.clone(), db.clone(), this.global_activate.clone(), this.global_query_config.clone(), this.global_denied_namespace.clone(), this.global_output_header.clone());
                                     task_runner.start();
                                     task_runner
                                 });
@@ -149,7 +157,7 @@ impl TaskFinder {
                     } else {
                         // we always set the global activated to false to prevent any accidents
                         {
-                            let mut global_activate = self.global_activate.write().await;
+                            let mut global_activate = this.global_activate.write().await;
                             *global_activate = false;
                         }
                         event!(Level::WARN, error = ?tasks.unwrap_err(), "cannot get task list");
@@ -157,7 +165,7 @@ impl TaskFinder {
                 } else {
                     // we always set the global activated to false to prevent any accidents
                     {
-                        let mut global_activate = self.global_activate.write().await;
+                        let mut global_activate = this.global_activate.write().await;
                         *global_activate = false;
                     }
                 }
@@ -182,6 +190,9 @@ impl TaskFinder {
 
 impl Drop for TaskFinder {
     fn drop(&mut self) {
-        self.stop();
+        // `stop` cannot block here, the last reference may be dropped inside the runtime
+        if let Some(handle) = self.finderhandle.get_mut().take() {
+            handle.abort();
+        }
     }
 }
@@ -6,13 +6,17 @@ use mediawiki::hashmap;
 use tokio::{task::JoinHandle, sync::RwLock};
 use tracing::{event, Level, Instrument, span};
 
-use crate::API_SERVICE;
+use crate::backend::{self, WikiBackend};
+use crate::dbservice::DBService;
+use crate::scheduler;
 
 use super::types::{TaskInfo, TaskConfig};
 use super::{pagewriter::PageWriter, queryexecutor::QueryExecutor};
 
 pub struct TaskRunner {
     id: i64,
+    backend: Arc<dyn WikiBackend>,
+    db: Option<Arc<DBService>>,
     global_activate: Arc<RwLock<bool>>,
     global_query_config: Arc<RwLock<TaskConfig>>,
     global_denied_namespace: Arc<RwLock<HashSet<NamespaceID>>>,
@@ -25,6 +29,8 @@ impl TaskRunner {
 
     pub fn new(
         id: i64,
+        backend: Arc<dyn WikiBackend>,
+        db: Option<Arc<DBService>>,
         global_activate: Arc<RwLock<bool>>,
         global_query_config: Arc<RwLock<TaskConfig>>,
         global_denied_namespace: Arc<RwLock<HashSet<NamespaceID>>>,
@@ -32,6 +38,8 @@ impl TaskRunner {
     ) -> Self {
         TaskRunner {
             id,
+            backend,
+            db,
             global_activate,
             global_query_config,
             global_denied_namespace,
@@ -44,12 +52,15 @@ impl TaskRunner {
         self.stop();
         let handler: JoinHandle<()> = {
             let id = self.id;
+            let backend = self.backend.clone();
+            let db = self.db.clone();
             let global_activate = self.global_activate.clone();
             let global_query_config = self.global_query_config.clone();
             let global_denied_namespace = self.global_denied_namespace.clone();
             let global_output_header = self.global_output_header.clone();
 
-            tokio::spawn(async move {
+            // requests of this runner are queued on their own, see `scheduler`
+            tokio::spawn(scheduler::CLIENT.scope(id, async move {
                 // used in first run; we need to align the task runner to cron
                 let mut aligned_to_cron: bool = false;
                 loop {
@@ -65,10 +76,7 @@ impl TaskRunner {
                             "rvprop".to_string() => "content".to_string(),
                             "rvlimit".to_string() => "1".to_string()
                         ];
-                        let page_content = {
-                            API_SERVICE.get_lock().lock().await;
-                            API_SERVICE.get(&params).await
-                        };
+                        let page_content = backend::fresh(&backend).get(&params).await;
 
                         if let Ok(page_content) = page_content {
                             let page_content_str = page_content["query"]["pages"][0]["revisions"][0]["slots"]["main"]["content"].as_str();
@@ -100,7 +108,8 @@ impl TaskRunner {
                                 let value = global_query_config.read().await;
                                 let timeout = task.timeout.unwrap_or(value.timeout);
                                 let limit = task.querylimit.unwrap_or(value.querylimit);
-                                TaskConfig { timeout, querylimit: limit }
+                                let fanout = task.fanout.unwrap_or(value.fanout);
+                                TaskConfig { timeout, querylimit: limit, fanout, parallelism: value.parallelism }
                             };
                             let denied_ns = {
                                 let value = global_denied_namespace.read().await;
@@ -110,7 +119,9 @@ impl TaskRunner {
                                 let value = global_output_header.read().await;
                                 value.clone()
                             };
-                            let writer = PageWriter::new(QueryExecutor::new(&task.expr, &task_config))
+                            // the output pages are always read fresh, the query only if the task asks for it
+                            let query_backend = if task.nocache.unwrap_or(false) { backend::fresh(&backend) } else { backend.clone() };
+                            let writer = PageWriter::new(backend::fresh(&backend), QueryExecutor::new(query_backend, db.clone(), &task.expr, &task_config))
                                 .set_task_id(id)
                                 .set_output_format(&task.output)
                                 .set_eager_mode(task.eager.unwrap_or(false))
@@ -142,7 +153,7 @@ impl TaskRunner {
                         tokio::time::sleep(tokio::time::Duration::from_secs(10 * 60)).await;
                     }
                 }
-            }.instrument(span!(target: "Task Runner", Level::INFO, "task runner routine", task_id = id)))
+            }.instrument(span!(target: "Task Runner", Level::INFO, "task runner routine", task_id = id))))
         };
         self.runnerhandle = Some(handler);
     }
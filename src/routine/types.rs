@@ -1,7 +1,11 @@
-#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct TaskConfig {
     pub timeout: u64,
     pub querylimit: i64,
+    #[serde(default = "default_fanout")]
+    pub fanout: i64,
+    #[serde(default = "default_parallelism")]
+    pub parallelism: usize,
 }
 
 impl TaskConfig {
@@ -9,11 +13,21 @@ impl TaskConfig {
         TaskConfig {
             timeout: 0,
             querylimit: 0,
+            fanout: default_fanout(),
+            parallelism: default_parallelism(),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+fn default_fanout() -> i64 {
+    100
+}
+
+fn default_parallelism() -> usize {
+    4
+}
+
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct SiteConfig {
     pub activate: bool,
     pub taskdir: String,
@@ -22,7 +36,7 @@ pub struct SiteConfig {
     pub default: TaskConfig,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct TaskInfo {
     pub activate: bool,
     pub description: String,
@@ -31,10 +45,12 @@ pub struct TaskInfo {
     pub eager: Option<bool>,
     pub timeout: Option<u64>,
     pub querylimit: Option<i64>,
+    pub fanout: Option<i64>,
+    pub nocache: Option<bool>,
     pub output: Vec<OutputFormat>,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct OutputFormatSuccess {
     pub before: String,
     pub item: String,
@@ -42,7 +58,7 @@ pub struct OutputFormatSuccess {
     pub after: String,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub struct OutputFormat {
     pub target: String,
     pub failure: String,
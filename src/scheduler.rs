@@ -0,0 +1,252 @@
+//! Request scheduler of the API service.
+//!
+//! Reads and edits have separate budgets, each limiting the number of requests in flight and the rate at which they start.
+//! Waiting requests are queued by client, and clients take turns, so that a task sending thousands of requests
+//! does not hold back a task sending a few. The client of a request is the task it is sent from, see `CLIENT`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+use crate::types::{ThrottleBudget, ThrottleProfile};
+
+tokio::task_local! {
+    /// The client requests are sent on behalf of. Task runners set this to their task id.
+    pub static CLIENT: i64;
+}
+
+/// The client of requests sent outside any `CLIENT` scope, such as those of the task finder
+const DEFAULT_CLIENT: i64 = 0;
+
+#[derive(Debug)]
+pub struct Scheduler {
+    read: Arc<Lane>,
+    edit: Arc<Lane>,
+}
+
+impl Scheduler {
+
+    pub fn new() -> Self {
+        let throttle = ThrottleProfile::default();
+        Scheduler {
+            read: Arc::new(Lane::new(throttle.read)),
+            edit: Arc::new(Lane::new(throttle.edit)),
+        }
+    }
+
+    /// Replaces the budgets. Requests already in flight are not affected
+    pub fn configure(&self, throttle: &ThrottleProfile) {
+        self.read.configure(throttle.read.clone());
+        self.edit.configure(throttle.edit.clone());
+    }
+
+    /// Waits until a read request may be sent. The request must be sent before the permit is dropped
+    pub async fn read(&self) -> Permit {
+        self.read.clone().acquire().await
+    }
+
+    /// Waits until an edit request may be sent. The request must be sent before the permit is dropped
+    pub async fn edit(&self) -> Permit {
+        self.edit.clone().acquire().await
+    }
+
+}
+
+#[derive(Debug)]
+struct Lane {
+    state: Mutex<LaneState>,
+}
+
+#[derive(Debug)]
+struct LaneState {
+    budget: ThrottleBudget,
+    running: usize,
+    // the earliest time the next request may start
+    next_start: Instant,
+    waiting: HashMap<i64, VecDeque<oneshot::Sender<Permit>>>,
+    // clients with waiting requests, in the order they are served
+    turns: VecDeque<i64>,
+}
+
+impl LaneState {
+
+    fn has_room(&self) -> bool {
+        self.budget.concurrency.is_none_or(|concurrency| self.running < concurrency.max(1))
+    }
+
+    /// Reserves a start time respecting the rate limit
+    fn take_slot(&mut self) -> Instant {
+        let start = self.next_start.max(Instant::now());
+        if let Some(rps) = self.budget.rps.filter(|rps| *rps > 0.0) {
+            self.next_start = start + Duration::from_secs_f64(1.0 / rps);
+        }
+        start
+    }
+
+}
+
+impl Lane {
+
+    fn new(budget: ThrottleBudget) -> Self {
+        Lane {
+            state: Mutex::new(LaneState {
+                budget,
+                running: 0,
+                next_start: Instant::now(),
+                waiting: HashMap::new(),
+                turns: VecDeque::new(),
+            }),
+        }
+    }
+
+    fn configure(self: &Arc<Self>, budget: ThrottleBudget) {
+        let mut state = self.state.lock().unwrap();
+        state.budget = budget;
+        self.dispatch(&mut state);
+    }
+
+    async fn acquire(self: Arc<Self>) -> Permit {
+        let client = CLIENT.try_with(|c| *c).unwrap_or(DEFAULT_CLIENT);
+        let permit = {
+            let mut state = self.state.lock().unwrap();
+            if state.has_room() && state.turns.is_empty() {
+                state.running += 1;
+                let start = state.take_slot();
+                Ok(Permit { lane: Some(self.clone()), start })
+            } else {
+                let (tx, rx) = oneshot::channel();
+                let queue = state.waiting.entry(client).or_default();
+                queue.push_back(tx);
+                if queue.len() == 1 {
+                    state.turns.push_back(client);
+                }
+                Err(rx)
+            }
+        };
+        let permit = match permit {
+            Ok(permit) => permit,
+            // the sender is only dropped with the lane, which outlives every request
+            Err(rx) => rx.await.expect("scheduler lane dropped"),
+        };
+        tokio::time::sleep_until(permit.start).await;
+        permit
+    }
+
+    /// Hands out free room to waiting requests, one client at a time
+    fn dispatch(self: &Arc<Self>, state: &mut LaneState) {
+        while state.has_room() {
+            let client = match state.turns.pop_front() {
+                Some(client) => client,
+                None => break,
+            };
+            let queue = state.waiting.get_mut(&client).unwrap();
+            let tx = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                state.waiting.remove(&client);
+            } else {
+                state.turns.push_back(client);
+            }
+            state.running += 1;
+            let next_start = state.next_start;
+            let start = state.take_slot();
+            if let Err(mut permit) = tx.send(Permit { lane: Some(self.clone()), start }) {
+                // the request was given up while waiting
+                permit.lane = None;
+                state.running -= 1;
+                state.next_start = next_start;
+            }
+        }
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        self.dispatch(&mut state);
+    }
+
+}
+
+/// Room for one request in flight. It is given back when dropped
+#[derive(Debug)]
+pub struct Permit {
+    lane: Option<Arc<Lane>>,
+    start: Instant,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(lane) = self.lane.take() {
+            lane.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::timeout;
+
+    fn lane(concurrency: usize) -> Arc<Lane> {
+        Arc::new(Lane::new(ThrottleBudget { rps: None, concurrency: Some(concurrency) }))
+    }
+
+    fn running(lane: &Lane) -> usize {
+        lane.state.lock().unwrap().running
+    }
+
+    #[tokio::test]
+    async fn clients_take_turns() {
+        let lane = lane(1);
+        let held = lane.clone().acquire().await;
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut handles = Vec::new();
+        for client in [1, 1, 1, 2, 3] {
+            let (lane, order) = (lane.clone(), order.clone());
+            handles.push(tokio::spawn(CLIENT.scope(client, async move {
+                let _permit = lane.acquire().await;
+                order.lock().unwrap().push(client);
+            })));
+            // let the request join the queue before the next one
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![1, 2, 3, 1, 1]);
+        assert_eq!(running(&lane), 0);
+    }
+
+    #[tokio::test]
+    async fn permits_are_given_back() {
+        let lane = lane(2);
+        let first = lane.clone().acquire().await;
+        let second = lane.clone().acquire().await;
+        assert_eq!(running(&lane), 2);
+        // a request given up while waiting does not keep the room it is handed
+        assert!(timeout(Duration::from_millis(10), lane.clone().acquire()).await.is_err());
+        drop(first);
+        assert_eq!(running(&lane), 1);
+        let third = timeout(Duration::from_millis(10), lane.clone().acquire()).await.unwrap();
+        assert_eq!(running(&lane), 2);
+        drop(second);
+        drop(third);
+        assert_eq!(running(&lane), 0);
+    }
+
+    #[tokio::test]
+    async fn configure_lets_waiting_requests_in() {
+        let lane = lane(1);
+        let held = lane.clone().acquire().await;
+        let waiting = tokio::spawn(lane.clone().acquire());
+        tokio::task::yield_now().await;
+        lane.configure(ThrottleBudget { rps: None, concurrency: Some(2) });
+        let permit = timeout(Duration::from_millis(10), waiting).await.unwrap().unwrap();
+        assert_eq!(running(&lane), 2);
+        drop(permit);
+        drop(held);
+        assert_eq!(running(&lane), 0);
+    }
+}
@@ -1,11 +1,18 @@
 //! This module performs actions using MediaWiki API
 //! 
 
-use super::{util, error::SolveError};
-use std::collections::{HashSet, VecDeque};
+use super::{util, template, error::SolveError};
+use std::collections::{HashMap, HashSet, VecDeque};
+use chrono::{DateTime, SecondsFormat, Utc};
 use mediawiki::{api::NamespaceID, title::Title, hashmap};
-use crate::API_SERVICE;
-use crate::parser::ir::{DepthNum, RedirectFilterStrategy};
+use serde_json::Value;
+use crate::backend::WikiBackend;
+use crate::parser::ir::{DepthNum, PageFilter, ParamFilter, Pattern, PropFilter, RedirectFilterStrategy};
+
+/// The maximum number of titles MediaWiki API accepts in one request, without `apihighlimits`
+const TITLES_PER_REQUEST: usize = 50;
+/// The maximum number of bytes of page content one instruction may fetch to search in
+const CONTENT_BYTES_LIMIT: usize = 64 * 1024 * 1024;
 
 fn limit_to_max(limit: i64) -> Option<usize> {
     if limit < 0 {
@@ -15,12 +22,12 @@ fn limit_to_max(limit: i64) -> Option<usize> {
     }
 }
 
-async fn pages_object_to_titles_set(data: &serde_json::Value, redirected: bool, redirect_filter: RedirectFilterStrategy) -> HashSet<Title> {
+async fn pages_object_to_titles_set(backend: &dyn WikiBackend, data: &serde_json::Value, redirected: bool, redirect_filter: RedirectFilterStrategy) -> HashSet<Title> {
     if let Some(obj) = data.as_object() {
         let mut redirects: HashSet<Title> = HashSet::new();
         if let Some(redirs) = obj.get("redirects") {
             for itm in redirs.as_array().unwrap().iter() {
-                redirects.insert(API_SERVICE.title_new_from_full(itm["from"].as_str().unwrap()).await.unwrap());
+                redirects.insert(backend.title_new_from_full(itm["from"].as_str().unwrap()).await.unwrap());
             }
         }
         let mut pages: HashSet<Title> = HashSet::new();
@@ -50,9 +57,7 @@ async fn pages_object_to_titles_set(data: &serde_json::Value, redirected: bool,
 /// 
 /// `title`: The title of the page.
 /// 
-/// `api`: The MediaWiki API instance.
-/// 
-/// `assert`: The identity to assert for when using MediaWiki API. If set to `None`, won't apply assertion.
+/// `backend`: The wiki backend to query.
 /// 
 /// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
 /// 
@@ -64,15 +69,13 @@ async fn pages_object_to_titles_set(data: &serde_json::Value, redirected: bool,
 /// 
 /// `limit`: Query limit.
 #[allow(clippy::too_many_arguments)]
-pub(crate) async fn get_backlinks_one(title: &Title, ns: Option<&HashSet<NamespaceID>>, level_2: bool, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
-    let elem_name = API_SERVICE.full_pretty(title).await?;
-    if elem_name.is_none() {
-        Ok(HashSet::new())
-    } else {
+pub(crate) async fn get_backlinks_one(backend: &dyn WikiBackend, title: &Title, ns: Option<&HashSet<NamespaceID>>, level_2: bool, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let elem_name = backend.full_pretty(title).await?;
+    if let Some(elem_name) = elem_name {
         let mut params = hashmap![
             "action".to_string() => "query".to_string(),
             "generator".to_string() => "backlinks".to_string(),
-            "gbltitle".to_string() => elem_name.unwrap(),
+            "gbltitle".to_string() => elem_name,
             "gbllimit".to_string() => "max".to_string(),
             "gblfilterredir".to_string() => redirect_strat.to_string()
         ];
@@ -93,8 +96,8 @@ pub(crate) async fn get_backlinks_one(title: &Title, ns: Option<&HashSet<Namespa
                 params.insert("gblnamespace".to_string(), util::concat_params(ns_list));
             }
         }
-        let res = API_SERVICE.get_limit(&params, limit_to_max(limit)).await?;
-        let mut title_set = pages_object_to_titles_set(&res["query"], follow_redir, redirect_strat).await;
+        let res = backend.get_limit(&params, limit_to_max(limit)).await?;
+        let mut title_set = pages_object_to_titles_set(backend, &res["query"], follow_redir, redirect_strat).await;
         // Need to filter by namespace...
         if level_2 {
             if let Some(ns_list) = ns {
@@ -102,6 +105,8 @@ pub(crate) async fn get_backlinks_one(title: &Title, ns: Option<&HashSet<Namespa
             }
         }
         Ok(title_set)
+    } else {
+        Ok(HashSet::new())
     }
 }
 
@@ -110,9 +115,7 @@ pub(crate) async fn get_backlinks_one(title: &Title, ns: Option<&HashSet<Namespa
 /// 
 /// `title`: The title of the category.
 /// 
-/// `api`: The MediaWiki API instance.
-/// 
-/// `assert`: The identity to assert for when using MediaWiki API. If set to `None`, won't apply assertion.
+/// `backend`: The wiki backend to query.
 /// 
 /// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
 /// 
@@ -121,7 +124,7 @@ pub(crate) async fn get_backlinks_one(title: &Title, ns: Option<&HashSet<Namespa
 /// `follow_redir`: Whether should follow redirects.
 /// 
 /// `limit`: Query limit.
-pub(crate) async fn get_category_members_one(title: &Title, ns: Option<&HashSet<NamespaceID>>, depth: DepthNum, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+pub(crate) async fn get_category_members_one(backend: &dyn WikiBackend, title: &Title, ns: Option<&HashSet<NamespaceID>>, depth: DepthNum, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
     // Due to miser mode, we need to do some preparations to cs.
     let mut ns_clone = ns.cloned();
     let mut result_has_ns_category: bool = true;
@@ -143,7 +146,7 @@ pub(crate) async fn get_category_members_one(title: &Title, ns: Option<&HashSet<
         if this_cat.namespace_id() != super::def::NS_CATEGORY {
             return Err(SolveError::NotCategory);
         }
-        let cat_name = API_SERVICE.full_pretty(&this_cat).await?.unwrap();
+        let cat_name = backend.full_pretty(&this_cat).await?.unwrap();
         let mut params = hashmap![
             "action".to_string() => "query".to_string(),
             "generator".to_string() => "categorymembers".to_string(),
@@ -179,8 +182,8 @@ pub(crate) async fn get_category_members_one(title: &Title, ns: Option<&HashSet<
         }
         params.insert("gcmtype".to_string(), cmtype.join("|"));
         // fetch results
-        let res = API_SERVICE.get_limit(&params, limit_to_max(limit)).await?;
-        let mut title_set_2 = pages_object_to_titles_set(&res["query"], follow_redir, RedirectFilterStrategy::NoRedirect).await;
+        let res = backend.get_limit(&params, limit_to_max(limit)).await?;
+        let mut title_set_2 = pages_object_to_titles_set(backend, &res["query"], follow_redir, RedirectFilterStrategy::NoRedirect).await;
         if depth < 0 || this_depth < depth {
             // filter out subcategories from title_vec, and add to visit queue
             for sub in title_set_2.iter().filter(|&t| t.namespace_id() == super::def::NS_CATEGORY) {
@@ -208,16 +211,14 @@ pub(crate) async fn get_category_members_one(title: &Title, ns: Option<&HashSet<
 /// 
 /// `title`: The title of the page.
 /// 
-/// `api`: The MediaWiki API instance.
-/// 
-/// `assert`: The identity to assert for when using MediaWiki API. If set to `None`, won't apply assertion.
+/// `backend`: The wiki backend to query.
 /// 
 /// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
 /// 
 /// `redirect_strat`: The redirect strategy to use when querying.
 /// 
 /// `limit`: Query limit.
-pub(crate) async fn get_prefix_index_one(title: &Title, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, limit: i64) -> Result<HashSet<Title>, SolveError> {
+pub(crate) async fn get_prefix_index_one(backend: &dyn WikiBackend, title: &Title, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, limit: i64) -> Result<HashSet<Title>, SolveError> {
     let title_ns_id = title.namespace_id();
     if let Some(ns_list) = ns {
         if !ns_list.contains(&title_ns_id) {
@@ -232,8 +233,8 @@ pub(crate) async fn get_prefix_index_one(title: &Title, ns: Option<&HashSet<Name
         "gaplimit".to_string() => "max".to_string(),
         "gapfilterredir".to_string() => redirect_strat.to_string()
     ];
-    let res = API_SERVICE.get_limit(&params, limit_to_max(limit)).await?;
-    let title_set = pages_object_to_titles_set(&res["query"], false, redirect_strat).await;
+    let res = backend.get_limit(&params, limit_to_max(limit)).await?;
+    let title_set = pages_object_to_titles_set(backend, &res["query"], false, redirect_strat).await;
     Ok(title_set)
 }
 
@@ -243,9 +244,7 @@ pub(crate) async fn get_prefix_index_one(title: &Title, ns: Option<&HashSet<Name
 /// 
 /// `title`: The title of the page.
 /// 
-/// `api`: The MediaWiki API instance.
-/// 
-/// `assert`: The identity to assert for when using MediaWiki API. If set to `None`, won't apply assertion.
+/// `backend`: The wiki backend to query.
 /// 
 /// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
 /// 
@@ -254,15 +253,13 @@ pub(crate) async fn get_prefix_index_one(title: &Title, ns: Option<&HashSet<Name
 /// `follow_redir`: Whether should follow redirects.
 /// 
 /// `limit`: Query limit.
-pub(crate) async fn get_embed_one(title: &Title, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
-    let elem_name = API_SERVICE.full_pretty(title).await?;
-    if elem_name.is_none() {
-        Ok(HashSet::new())
-    } else {
+pub(crate) async fn get_embed_one(backend: &dyn WikiBackend, title: &Title, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let elem_name = backend.full_pretty(title).await?;
+    if let Some(elem_name) = elem_name {
         let mut params = hashmap![
             "action".to_string() => "query".to_string(),
             "generator".to_string() => "embeddedin".to_string(),
-            "geititle".to_string() => elem_name.unwrap(),
+            "geititle".to_string() => elem_name,
             "geilimit".to_string() => "max".to_string(),
             "geifilterredir".to_string() => redirect_strat.to_string()
         ];
@@ -272,34 +269,44 @@ pub(crate) async fn get_embed_one(title: &Title, ns: Option<&HashSet<NamespaceID
         if follow_redir {
             params.insert("redirects".to_string(), "1".to_string());
         }
-        let res = API_SERVICE.get_limit(&params, limit_to_max(limit)).await?;
-        let title_set = pages_object_to_titles_set(&res["query"], follow_redir, redirect_strat).await;
+        let res = backend.get_limit(&params, limit_to_max(limit)).await?;
+        let title_set = pages_object_to_titles_set(backend, &res["query"], follow_redir, redirect_strat).await;
         Ok(title_set)
+    } else {
+        Ok(HashSet::new())
     }
 }
 
-/// Retrives the in-wiki links of a page.
+/// Retrives the in-wiki links of a set of pages.
 /// 
-/// `title`: The title of the page.
+/// MediaWiki API accepts up to 50 titles per request for `prop=links`, so pages are queried in batches.
 /// 
-/// `api`: The MediaWiki API instance.
+/// `titles`: The titles of the pages.
 /// 
-/// `assert`: The identity to assert for when using MediaWiki API. If set to `None`, won't apply assertion.
+/// `backend`: The wiki backend to query.
 /// 
 /// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
 /// 
 /// `follow_redir`: Whether should follow redirects.
 /// 
-/// `limit`: Query limit
-pub(crate) async fn get_links_one(title: &Title, ns: Option<&HashSet<NamespaceID>>, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
-    let elem_name = API_SERVICE.full_pretty(title).await?;
-    if elem_name.is_none() {
-        Ok(HashSet::new())
-    } else {
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_links(backend: &dyn WikiBackend, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut names: Vec<String> = Vec::new();
+    for title in titles {
+        if let Some(name) = backend.full_pretty(title).await? {
+            names.push(name);
+        }
+    }
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        let batch_limit = match util::remaining_limit(limit, title_set.len()) {
+            Some(batch_limit) => batch_limit,
+            None => break,
+        };
         let mut params = hashmap![
             "action".to_string() => "query".to_string(),
             "generator".to_string() => "links".to_string(),
-            "titles".to_string() => elem_name.unwrap(),
+            "titles".to_string() => batch.join("|"),
             "gpllimit".to_string() => "max".to_string()
         ];
         if let Some(ns_list) = ns {
@@ -308,9 +315,829 @@ pub(crate) async fn get_links_one(title: &Title, ns: Option<&HashSet<NamespaceID
         if follow_redir {
             params.insert("redirects".to_string(), "1".to_string());
         }
-        let res = API_SERVICE.get_limit(&params, limit_to_max(limit)).await?;
-        let title_vec = pages_object_to_titles_set(&res["query"], follow_redir, RedirectFilterStrategy::NoRedirect).await;
-        let title_set = HashSet::from_iter(title_vec.into_iter());
-        Ok(title_set)
+        let res = backend.get_limit(&params, limit_to_max(batch_limit)).await?;
+        let batch_set = pages_object_to_titles_set(backend, &res["query"], follow_redir, RedirectFilterStrategy::NoRedirect).await;
+        title_set.extend(batch_set);
+    }
+    Ok(title_set)
+}
+
+/// Runs a generator that takes its pages from `titles=`, such as `categories` or `templates`, on a set of pages in batches.
+/// 
+/// `generator`: The name of the generator module.
+/// 
+/// `prefix`: The parameter prefix of the generator module, without the leading `g`.
+/// 
+/// `extra`: More parameters of the generator, without their prefix.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `ns_param`: Whether the generator filters namespaces itself. If not, the result is filtered afterwards.
+/// 
+/// `follow_redir`: Whether should follow redirects.
+/// 
+/// `redirect_strat`: Which pages to keep if `follow_redir` is set.
+/// 
+/// `limit`: Query limit, applied to the whole result.
+#[allow(clippy::too_many_arguments)]
+async fn get_from_titles(backend: &dyn WikiBackend, titles: &HashSet<Title>, generator: &str, prefix: &str, extra: &[(&str, String)], ns: Option<&HashSet<NamespaceID>>, ns_param: bool, follow_redir: bool, redirect_strat: RedirectFilterStrategy, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut names: Vec<String> = Vec::new();
+    for title in titles {
+        if let Some(name) = backend.full_pretty(title).await? {
+            names.push(name);
+        }
+    }
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        let batch_limit = match util::remaining_limit(limit, title_set.len()) {
+            Some(batch_limit) => batch_limit,
+            None => break,
+        };
+        let mut params = hashmap![
+            "action".to_string() => "query".to_string(),
+            "generator".to_string() => generator.to_string(),
+            "titles".to_string() => batch.join("|"),
+            format!("g{}limit", prefix) => "max".to_string()
+        ];
+        for (name, value) in extra {
+            params.insert(format!("g{}{}", prefix, name), value.to_owned());
+        }
+        if let (Some(ns_list), true) = (ns, ns_param) {
+            params.insert(format!("g{}namespace", prefix), util::concat_params(ns_list));
+        }
+        if follow_redir {
+            params.insert("redirects".to_string(), "1".to_string());
+        }
+        let res = backend.get_limit(&params, limit_to_max(batch_limit)).await?;
+        let batch_set = pages_object_to_titles_set(backend, &res["query"], follow_redir, redirect_strat).await;
+        title_set.extend(batch_set);
+    }
+    if let (Some(ns_list), false) = (ns, ns_param) {
+        title_set.retain(|title| ns_list.contains(&title.namespace_id()));
+    }
+    Ok(title_set)
+}
+
+/// Retrives the categories a set of pages are in, hidden categories included.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `ns`: Namespace filter. Categories are all in one namespace, so this only tells whether there is any result.
+/// 
+/// `follow_redir`: Whether should follow redirects.
+/// 
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_categories(backend: &dyn WikiBackend, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    if ns.is_some_and(|ns_list| !ns_list.contains(&super::def::NS_CATEGORY)) {
+        return Ok(HashSet::new());
+    }
+    get_from_titles(backend, titles, "categories", "cl", &[], None, false, follow_redir, RedirectFilterStrategy::NoRedirect, limit).await
+}
+
+/// Retrives the pages a set of pages transclude, templates or not.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `follow_redir`: Whether should follow redirects.
+/// 
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_templates(backend: &dyn WikiBackend, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    get_from_titles(backend, titles, "templates", "tl", &[], ns, true, follow_redir, RedirectFilterStrategy::NoRedirect, limit).await
+}
+
+/// Retrives the files a set of pages use.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `ns`: Namespace filter. Files are all in one namespace, so this only tells whether there is any result.
+/// 
+/// `follow_redir`: Whether should follow redirects.
+/// 
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_images(backend: &dyn WikiBackend, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    if ns.is_some_and(|ns_list| !ns_list.contains(&super::def::NS_FILE)) {
+        return Ok(HashSet::new());
+    }
+    get_from_titles(backend, titles, "images", "im", &[], None, false, follow_redir, RedirectFilterStrategy::NoRedirect, limit).await
+}
+
+/// Retrives the pages using a set of files.
+/// 
+/// `titles`: The titles of the files.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `redirect_strat`: The redirect strategy to use when querying.
+/// 
+/// `follow_redir`: Whether should follow redirects.
+/// 
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_file_usage(backend: &dyn WikiBackend, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let show: Vec<(&str, String)> = match redirect_strat {
+        RedirectFilterStrategy::NoRedirect => vec![("show", String::from("!redirect"))],
+        RedirectFilterStrategy::OnlyRedirect => vec![("show", String::from("redirect"))],
+        RedirectFilterStrategy::All => Vec::new(),
+    };
+    get_from_titles(backend, titles, "fileusage", "fu", &show, ns, true, follow_redir, redirect_strat, limit).await
+}
+
+/// Retrives the redirects to a set of pages.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_redirects(backend: &dyn WikiBackend, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    get_from_titles(backend, titles, "redirects", "rd", &[], ns, true, false, RedirectFilterStrategy::NoRedirect, limit).await
+}
+
+/// Retrives the pages from the recent changes between two times. The recent changes only go back a few weeks.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `new_only`: Whether to only retrieve the pages created, instead of those edited or created.
+/// 
+/// `since`, `until`: The time span. Open on the sides set to `None`.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `redirect_strat`: The redirect strategy to use when querying.
+/// 
+/// `limit`: Query limit, counted in pages. A page changed several times counts once.
+pub(crate) async fn get_recent_changes(backend: &dyn WikiBackend, new_only: bool, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "generator".to_string() => "recentchanges".to_string(),
+        "grctype".to_string() => if new_only { "new" } else { "edit|new" }.to_string(),
+        "grclimit".to_string() => "max".to_string()
+    ];
+    // recent changes are listed from the newest, so the span starts at `until`
+    if let Some(until) = until {
+        params.insert("grcstart".to_string(), to_timestamp(&until));
+    }
+    if let Some(since) = since {
+        params.insert("grcend".to_string(), to_timestamp(&since));
+    }
+    if let Some(ns_list) = ns {
+        params.insert("grcnamespace".to_string(), util::concat_params(ns_list));
+    }
+    match redirect_strat {
+        RedirectFilterStrategy::NoRedirect => { params.insert("grcshow".to_string(), "!redirect".to_string()); },
+        RedirectFilterStrategy::OnlyRedirect => { params.insert("grcshow".to_string(), "redirect".to_string()); },
+        RedirectFilterStrategy::All => {},
+    }
+    fn names(res: &Value) -> Vec<&str> {
+        res["query"]["pages"].as_array().map(|pages| pages.iter().filter_map(|page| page["title"].as_str()).collect()).unwrap_or_default()
+    }
+    list_distinct(backend, params, names, None, limit).await
+}
+
+/// Retrives the pages of the log entries between two times.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `log`: A log type such as `move`, or a log action such as `delete/delete`.
+/// 
+/// `since`, `until`: The time span. Open on the sides set to `None`.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `limit`: Query limit, counted in the pages left after the namespace filter. A page in several log entries counts once.
+pub(crate) async fn get_log_events(backend: &dyn WikiBackend, log: &str, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "list".to_string() => "logevents".to_string(),
+        "leprop".to_string() => "title".to_string(),
+        "lelimit".to_string() => "max".to_string()
+    ];
+    if log.contains('/') {
+        params.insert("leaction".to_string(), log.to_string());
+    } else {
+        params.insert("letype".to_string(), log.to_string());
+    }
+    // log entries are listed from the newest, so the span starts at `until`
+    if let Some(until) = until {
+        params.insert("lestart".to_string(), to_timestamp(&until));
+    }
+    if let Some(since) = since {
+        params.insert("leend".to_string(), to_timestamp(&since));
+    }
+    // the API filters one namespace at most, more are filtered afterwards
+    if let Some(ns_list) = ns {
+        if ns_list.len() == 1 {
+            params.insert("lenamespace".to_string(), util::concat_params(ns_list));
+        }
+    }
+    fn names(res: &Value) -> Vec<&str> {
+        res["query"]["logevents"].as_array().map(|events| events.iter().filter_map(|event| event["title"].as_str()).collect()).unwrap_or_default()
+    }
+    list_distinct(backend, params, names, ns, limit).await
+}
+
+/// Retrives the pages found by the search engine of the wiki, usually CirrusSearch.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `query`: The search query, such as `insource:/foo/ incategory:X`.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the namespaces the wiki searches by default are searched.
+/// 
+/// `limit`: Query limit.
+pub(crate) async fn get_search(backend: &dyn WikiBackend, query: &str, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "list".to_string() => "search".to_string(),
+        "srsearch".to_string() => query.to_string(),
+        "srprop".to_string() => "".to_string(),
+        "srinfo".to_string() => "".to_string(),
+        "srlimit".to_string() => "max".to_string()
+    ];
+    if let Some(ns_list) = ns {
+        params.insert("srnamespace".to_string(), util::concat_params(ns_list));
+    }
+    let res = backend.get_limit(&params, limit_to_max(limit)).await?;
+    let mut title_set: HashSet<Title> = HashSet::new();
+    if let Some(results) = res["query"]["search"].as_array() {
+        for name in results.iter().filter_map(|result| result["title"].as_str()) {
+            title_set.insert(backend.title_new_from_full(name).await?);
+        }
+    }
+    Ok(title_set)
+}
+
+/// Retrives the pages listed by a special page report, such as `Lonelypages` or `Wantedcategories`.
+/// The reports of large wikis are cached, and may be a few days old.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `page`: The name of the special page.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace. The API cannot filter reports, so they are filtered afterwards.
+/// 
+/// `limit`: Query limit, counted in the pages left after the namespace filter.
+pub(crate) async fn get_query_page(backend: &dyn WikiBackend, page: &str, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "list".to_string() => "querypage".to_string(),
+        "qppage".to_string() => page.to_string(),
+        "qplimit".to_string() => "max".to_string()
+    ];
+    fn names(res: &Value) -> Vec<&str> {
+        res["query"]["querypage"]["results"].as_array().map(|results| results.iter().filter_map(|result| result["title"].as_str()).collect()).unwrap_or_default()
+    }
+    list_distinct(backend, params, names, ns, limit).await
+}
+
+/// Retrives the pages with a page property, such as `wikibase_item` or `disambiguation`.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `prop`: The name of the page property.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace. The API cannot filter by namespace, so the result is filtered afterwards.
+/// 
+/// `limit`: Query limit, counted in the pages left after the namespace filter.
+pub(crate) async fn get_pages_with_prop(backend: &dyn WikiBackend, prop: &str, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "list".to_string() => "pageswithprop".to_string(),
+        "pwppropname".to_string() => prop.to_string(),
+        "pwpprop".to_string() => "title".to_string(),
+        "pwplimit".to_string() => "max".to_string()
+    ];
+    fn names(res: &Value) -> Vec<&str> {
+        res["query"]["pageswithprop"].as_array().map(|pages| pages.iter().filter_map(|page| page["title"].as_str()).collect()).unwrap_or_default()
+    }
+    list_distinct(backend, params, names, ns, limit).await
+}
+
+/// Keeps the pages that have, or lack, every page property of `props`. Missing pages are left out.
+/// 
+/// The page properties are looked up in batches with `prop=pageprops`.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `props`: The page properties to check.
+pub(crate) async fn filter_by_pageprops(backend: &dyn WikiBackend, titles: &HashSet<Title>, props: &[PropFilter]) -> Result<HashSet<Title>, SolveError> {
+    let mut names: Vec<String> = Vec::new();
+    for title in titles {
+        if let Some(name) = backend.full_pretty(title).await? {
+            names.push(name);
+        }
+    }
+    let prop_names: Vec<&str> = props.iter().map(|p| p.name.as_str()).collect();
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        let params = hashmap![
+            "action".to_string() => "query".to_string(),
+            "prop".to_string() => "pageprops".to_string(),
+            "ppprop".to_string() => prop_names.join("|"),
+            "titles".to_string() => batch.join("|")
+        ];
+        let res = backend.get_all(&params).await?;
+        if let Some(pages) = res["query"]["pages"].as_array() {
+            for page in pages {
+                if page.get("missing").is_some() || page.get("invalid").is_some() {
+                    continue;
+                }
+                if props.iter().all(|p| page["pageprops"].get(&p.name).is_some() == p.present) {
+                    title_set.insert(Title::new_from_api_result(page));
+                }
+            }
+        }
+    }
+    Ok(title_set)
+}
+
+/// Retrives the pages a user edited between two times.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `user`: The user name, with or without the user namespace.
+/// 
+/// `show`: Only retrieve some edits: `new` for the creations, `top` for the last edits of the pages.
+/// 
+/// `since`, `until`: The time span. Open on the sides set to `None`.
+/// 
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+/// 
+/// `limit`: Query limit, counted in pages. A page edited several times counts once.
+pub(crate) async fn get_user_contribs(backend: &dyn WikiBackend, user: &str, show: Option<&str>, since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let user_page = backend.title_new_from_full(user).await?;
+    let user_name = if user_page.namespace_id() == super::def::NS_USER {
+        user_page.pretty().to_string()
+    } else {
+        user.trim().to_string()
+    };
+    let mut params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "list".to_string() => "usercontribs".to_string(),
+        "ucuser".to_string() => user_name,
+        "ucprop".to_string() => "title".to_string(),
+        "uclimit".to_string() => "max".to_string()
+    ];
+    if let Some(show) = show {
+        params.insert("ucshow".to_string(), show.to_string());
+    }
+    // contributions are listed from the newest, so the span starts at `until`
+    if let Some(until) = until {
+        params.insert("ucstart".to_string(), to_timestamp(&until));
+    }
+    if let Some(since) = since {
+        params.insert("ucend".to_string(), to_timestamp(&since));
+    }
+    if let Some(ns_list) = ns {
+        params.insert("ucnamespace".to_string(), util::concat_params(ns_list));
+    }
+    fn names(res: &Value) -> Vec<&str> {
+        res["query"]["usercontribs"].as_array().map(|contribs| contribs.iter().filter_map(|contrib| contrib["title"].as_str()).collect()).unwrap_or_default()
+    }
+    list_distinct(backend, params, names, None, limit).await
+}
+
+/// Sends a list request whose items may repeat a page or be filtered out afterwards, following the continuations
+/// until `limit` distinct pages are kept. `names` gives the full page names of one response.
+async fn list_distinct(backend: &dyn WikiBackend, mut params: HashMap<String, String>, names: impl Fn(&Value) -> Vec<&str>, ns: Option<&HashSet<NamespaceID>>, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut title_set: HashSet<Title> = HashSet::new();
+    if limit == 0 {
+        return Ok(title_set);
+    }
+    loop {
+        let res = backend.get(&params).await?;
+        for name in names(&res) {
+            let title = backend.title_new_from_full(name).await?;
+            if ns.is_none_or(|ns_list| ns_list.contains(&title.namespace_id())) {
+                title_set.insert(title);
+                if util::remaining_limit(limit, title_set.len()).is_none() {
+                    return Ok(title_set);
+                }
+            }
+        }
+        match res.get("continue").and_then(|cont| cont.as_object()) {
+            Some(cont) => {
+                for (k, v) in cont {
+                    let v = match v {
+                        Value::String(s) => s.to_owned(),
+                        v => v.to_string(),
+                    };
+                    params.insert(k.to_owned(), v);
+                }
+            },
+            None => break,
+        }
+    }
+    Ok(title_set)
+}
+
+fn to_timestamp(time: &DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Maps pages of another wiki to the pages of this wiki they have interlanguage links to.
+/// Pages without such a link are left out. Fails if this wiki has no interlanguage prefix.
+/// 
+/// `remote`: The backend of the other wiki.
+/// 
+/// `local`: The backend of this wiki.
+/// 
+/// `titles`: The titles of the pages on the other wiki.
+pub(crate) async fn get_langlinks(remote: &dyn WikiBackend, local: &dyn WikiBackend, titles: &HashSet<Title>) -> Result<HashSet<Title>, SolveError> {
+    if titles.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "meta".to_string() => "siteinfo".to_string(),
+        "siprop".to_string() => "general|interwikimap".to_string()
+    ];
+    let siteinfo = local.get(&params).await?;
+    let prefix = interlanguage_prefix(&siteinfo).ok_or(SolveError::UnknownLanguage)?;
+    let mut names: Vec<String> = Vec::new();
+    for title in titles {
+        if let Some(name) = remote.full_pretty(title).await? {
+            names.push(name);
+        }
+    }
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        let params = hashmap![
+            "action".to_string() => "query".to_string(),
+            "prop".to_string() => "langlinks".to_string(),
+            "titles".to_string() => batch.join("|"),
+            "lllang".to_string() => prefix.clone(),
+            "lllimit".to_string() => "max".to_string()
+        ];
+        let res = remote.get_all(&params).await?;
+        if let Some(pages) = res["query"]["pages"].as_array() {
+            for page in pages {
+                for link in page["langlinks"].as_array().into_iter().flatten() {
+                    if let Some(name) = link["title"].as_str() {
+                        title_set.insert(local.title_new_from_full(name).await?);
+                    }
+                }
+            }
+        }
+    }
+    Ok(title_set)
+}
+
+/// The prefix other wikis use in interlanguage links to the wiki of `siteinfo`, a `meta=siteinfo` response with `general` and `interwikimap`.
+/// 
+/// It is the prefix of the language interwiki pointing at the wiki itself, which may differ from its content language
+/// (`simple` is in English, `be-x-old` points at `be-tarask`). Wikis without a language, such as Commons, have none.
+fn interlanguage_prefix(siteinfo: &Value) -> Option<String> {
+    let general = &siteinfo["query"]["general"];
+    let article_url = format!("{}{}", general["server"].as_str()?, general["articlepath"].as_str()?);
+    siteinfo["query"]["interwikimap"].as_array()?.iter()
+        .filter(|iw| iw["language"].as_bool() == Some(true))
+        .find(|iw| iw["url"].as_str().map(without_scheme) == Some(without_scheme(&article_url)))
+        .and_then(|iw| iw["prefix"].as_str())
+        .map(|prefix| prefix.to_owned())
+}
+
+/// A URL without its scheme, `//example.org/wiki/$1` for `https://example.org/wiki/$1`
+fn without_scheme(url: &str) -> &str {
+    url.find("//").map_or(url, |at| &url[at..])
+}
+
+/// Keeps the pages whose metadata satisfy every filter of `filters`. Missing pages are left out.
+/// 
+/// Size, last edit, protection and content model are looked up in batches with `prop=info|revisions`.
+/// The first edit can only be looked up one page at a time, so it is checked last, on the pages left.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `filters`: The conditions to check.
+/// 
+/// `fanout`: The maximum number of pages whose first edit may be looked up. Fails if more pages are left. A negative value means no limit.
+pub(crate) async fn filter_by_metadata(backend: &dyn WikiBackend, titles: &HashSet<Title>, filters: &[PageFilter], fanout: i64) -> Result<HashSet<Title>, SolveError> {
+    // relative times are taken from when the filters are applied
+    let now = Utc::now();
+    for filter in filters {
+        if let PageFilter::Touched(range) | PageFilter::Created(range) = filter {
+            range.resolve(now).ok_or(SolveError::TimeOutOfRange)?;
+        }
+    }
+    let mut names: Vec<String> = Vec::new();
+    for title in titles {
+        if let Some(name) = backend.full_pretty(title).await? {
+            names.push(name);
+        }
+    }
+    let mut kept: Vec<(String, Title)> = Vec::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        let params = hashmap![
+            "action".to_string() => "query".to_string(),
+            "prop".to_string() => "info|revisions".to_string(),
+            "inprop".to_string() => "protection".to_string(),
+            "rvprop".to_string() => "timestamp".to_string(),
+            "titles".to_string() => batch.join("|")
+        ];
+        let res = backend.get_all(&params).await?;
+        if let Some(pages) = res["query"]["pages"].as_array() {
+            for page in pages {
+                if page.get("missing").is_some() || page.get("invalid").is_some() {
+                    continue;
+                }
+                if filters.iter().all(|f| page_satisfies(page, f, now)) {
+                    if let Some(name) = page["title"].as_str() {
+                        kept.push((name.to_owned(), Title::new_from_api_result(page)));
+                    }
+                }
+            }
+        }
+    }
+    let created: Vec<&PageFilter> = filters.iter().filter(|f| matches!(f, PageFilter::Created(_))).collect();
+    if !created.is_empty() {
+        util::check_fanout_count(kept.len(), fanout)?;
+    }
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for (name, title) in kept {
+        if !created.is_empty() {
+            let params = hashmap![
+                "action".to_string() => "query".to_string(),
+                "prop".to_string() => "revisions".to_string(),
+                "titles".to_string() => name,
+                "rvprop".to_string() => "timestamp".to_string(),
+                "rvdir".to_string() => "newer".to_string(),
+                "rvlimit".to_string() => "1".to_string()
+            ];
+            let res = backend.get(&params).await?;
+            let first_edit = parse_timestamp(&res["query"]["pages"][0]["revisions"][0]["timestamp"]);
+            let satisfied = created.iter().all(|f| match (f, &first_edit) {
+                (PageFilter::Created(range), Some(time)) => range.contains(time, now),
+                _ => false,
+            });
+            if !satisfied {
+                continue;
+            }
+        }
+        title_set.insert(title);
+    }
+    Ok(title_set)
+}
+
+/// Keeps the pages whose current wikitext matches every pattern of `patterns`.
+/// 
+/// The content is fetched in batches. Fails once more than `CONTENT_BYTES_LIMIT` bytes are fetched.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `patterns`: The patterns to search for.
+/// 
+/// `limit`: The maximum number of pages to search in. Fails on more pages. A negative value means no limit.
+pub(crate) async fn filter_by_content(backend: &dyn WikiBackend, titles: &HashSet<Title>, patterns: &[Pattern], limit: i64) -> Result<HashSet<Title>, SolveError> {
+    util::check_fanout(titles, limit)?;
+    let names = full_names(backend, titles).await?;
+    let mut fetched: usize = 0;
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        for (title, content) in get_contents(backend, batch, &mut fetched).await? {
+            if patterns.iter().all(|p| p.is_match(&content)) {
+                title_set.insert(title);
+            }
+        }
+    }
+    Ok(title_set)
+}
+
+/// Keeps the pages with a call to one of `templates` satisfying every filter of `params`.
+/// A call through a redirect to one of the templates counts as well.
+/// 
+/// The content is fetched in batches. Fails once more than `CONTENT_BYTES_LIMIT` bytes are fetched.
+/// 
+/// `backend`: The wiki backend to query.
+/// 
+/// `titles`: The titles of the pages.
+/// 
+/// `templates`: The titles of the templates whose calls are checked.
+/// 
+/// `params`: The conditions on the parameters of a call.
+/// 
+/// `limit`: The maximum number of pages to check. Fails on more pages. A negative value means no limit.
+pub(crate) async fn filter_by_params(backend: &dyn WikiBackend, titles: &HashSet<Title>, templates: &HashSet<Title>, params: &[ParamFilter], limit: i64) -> Result<HashSet<Title>, SolveError> {
+    util::check_fanout(titles, limit)?;
+    let mut targets: HashSet<Title> = templates.clone();
+    let mut template_names: Vec<String> = Vec::new();
+    for template in templates {
+        if let Some(name) = backend.full_pretty(template).await? {
+            template_names.push(name);
+        }
+    }
+    for batch in template_names.chunks(TITLES_PER_REQUEST) {
+        let query = hashmap![
+            "action".to_string() => "query".to_string(),
+            "prop".to_string() => "redirects".to_string(),
+            "titles".to_string() => batch.join("|"),
+            "rdlimit".to_string() => "max".to_string()
+        ];
+        let res = backend.get_all(&query).await?;
+        if let Some(pages) = res["query"]["pages"].as_array() {
+            for redirect in pages.iter().filter_map(|page| page["redirects"].as_array()).flatten() {
+                targets.insert(Title::new_from_api_result(redirect));
+            }
+        }
+    }
+
+    let names = full_names(backend, titles).await?;
+    let mut fetched: usize = 0;
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in names.chunks(TITLES_PER_REQUEST) {
+        for (title, content) in get_contents(backend, batch, &mut fetched).await? {
+            for call in template::parse_template_calls(&content) {
+                if !targets.contains(&template_title(backend, &call.name).await?) {
+                    continue;
+                }
+                let satisfied = params.iter().all(|p| match p {
+                    ParamFilter::Matches(name, pattern) => call.get(name).is_some_and(|value| pattern.is_match(value)),
+                    ParamFilter::Missing(name) => call.get(name).is_none_or(|value| value.trim().is_empty()),
+                });
+                if satisfied {
+                    title_set.insert(title);
+                    break;
+                }
+            }
+        }
+    }
+    Ok(title_set)
+}
+
+/// The page a template call transcludes. A name without a namespace is in the template namespace, unless it starts with `:`.
+/// The name is normalized like any title, so that `{{infobox_person}}` transcludes `Template:Infobox person`.
+async fn template_title(backend: &dyn WikiBackend, name: &str) -> Result<Title, SolveError> {
+    if let Some(full) = name.strip_prefix(':') {
+        return Ok(backend.title_new_from_full(full.trim()).await?);
+    }
+    let title = backend.title_new_from_full(name).await?;
+    if title.namespace_id() == super::def::NS_MAIN {
+        Ok(Title::new(title.pretty(), super::def::NS_TEMPLATE))
+    } else {
+        Ok(title)
+    }
+}
+
+/// The full titles of `titles`
+async fn full_names(backend: &dyn WikiBackend, titles: &HashSet<Title>) -> Result<Vec<String>, SolveError> {
+    let mut names: Vec<String> = Vec::new();
+    for title in titles {
+        if let Some(name) = backend.full_pretty(title).await? {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// Fetches the current wikitext of one batch of pages. Missing pages are left out.
+/// 
+/// `fetched`: The number of bytes fetched so far, updated with this batch. Fails once it exceeds `CONTENT_BYTES_LIMIT`.
+async fn get_contents(backend: &dyn WikiBackend, batch: &[String], fetched: &mut usize) -> Result<Vec<(Title, String)>, SolveError> {
+    let params = hashmap![
+        "action".to_string() => "query".to_string(),
+        "prop".to_string() => "revisions".to_string(),
+        "rvprop".to_string() => "content".to_string(),
+        "rvslots".to_string() => "main".to_string(),
+        "titles".to_string() => batch.join("|")
+    ];
+    let res = backend.get_all(&params).await?;
+    let mut contents: Vec<(Title, String)> = Vec::new();
+    if let Some(pages) = res["query"]["pages"].as_array() {
+        for page in pages {
+            if let Some(content) = page["revisions"][0]["slots"]["main"]["content"].as_str() {
+                *fetched += content.len();
+                if *fetched > CONTENT_BYTES_LIMIT {
+                    return Err(SolveError::ContentTooLarge(CONTENT_BYTES_LIMIT));
+                }
+                contents.push((Title::new_from_api_result(page), content.to_owned()));
+            }
+        }
+    }
+    Ok(contents)
+}
+
+/// Checks one filter against a page object of `prop=info|revisions`, with relative times taken from `now`.
+/// The first edit is not in there, so `Created` always holds here.
+fn page_satisfies(page: &Value, filter: &PageFilter, now: DateTime<Utc>) -> bool {
+    match filter {
+        PageFilter::Size(op, bytes) => page["length"].as_i64().is_some_and(|length| op.holds(length, *bytes)),
+        PageFilter::Touched(range) => parse_timestamp(&page["revisions"][0]["timestamp"]).is_some_and(|time| range.contains(&time, now)),
+        PageFilter::Created(_) => true,
+        PageFilter::Protected { action, level } => page["protection"].as_array().is_some_and(|protections| {
+            protections.iter().any(|p| {
+                action.as_ref().is_none_or(|a| p["type"].as_str() == Some(a)) && level.as_ref().is_none_or(|l| p["level"].as_str() == Some(l))
+            })
+        }),
+        PageFilter::ContentModel(model) => page["contentmodel"].as_str() == Some(model),
+    }
+}
+
+fn parse_timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value.as_str()?).ok().map(|t| t.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::parser::ir::{Comparison, TimePoint, TimeRange};
+
+    fn siteinfo(server: &str, interwikimap: Value) -> Value {
+        json!({ "query": { "general": { "server": server, "articlepath": "/wiki/$1", "lang": "en" }, "interwikimap": interwikimap } })
+    }
+
+    #[test]
+    fn interlanguage_prefix_points_at_the_wiki() {
+        let interwikimap = json!([
+            { "prefix": "en", "local": true, "language": true, "url": "https://en.wikipedia.org/wiki/$1" },
+            { "prefix": "simple", "local": true, "language": true, "url": "https://simple.wikipedia.org/wiki/$1" },
+            { "prefix": "be-x-old", "local": true, "language": true, "url": "https://be-tarask.wikipedia.org/wiki/$1" },
+            { "prefix": "w", "local": true, "url": "https://en.wikipedia.org/wiki/$1" },
+            { "prefix": "commons", "local": true, "url": "https://commons.wikimedia.org/wiki/$1" },
+        ]);
+        assert_eq!(interlanguage_prefix(&siteinfo("//en.wikipedia.org", interwikimap.clone())).as_deref(), Some("en"));
+        // the content language of Simple English is `en`
+        assert_eq!(interlanguage_prefix(&siteinfo("https://simple.wikipedia.org", interwikimap.clone())).as_deref(), Some("simple"));
+        assert_eq!(interlanguage_prefix(&siteinfo("//be-tarask.wikipedia.org", interwikimap.clone())).as_deref(), Some("be-x-old"));
+        // only a plain interwiki points at Commons
+        assert_eq!(interlanguage_prefix(&siteinfo("//commons.wikimedia.org", interwikimap)), None);
+        assert_eq!(interlanguage_prefix(&json!({ "query": { "general": {} } })), None);
+    }
+
+    fn info_page() -> Value {
+        json!({
+            "title": "A",
+            "length": 1200,
+            "contentmodel": "wikitext",
+            "revisions": [{ "timestamp": "2020-06-01T00:00:00Z" }],
+            "protection": [
+                { "type": "edit", "level": "autoconfirmed", "expiry": "infinity" },
+                { "type": "move", "level": "sysop", "expiry": "infinity" },
+            ],
+        })
+    }
+
+    fn protected(action: Option<&str>, level: Option<&str>) -> PageFilter {
+        PageFilter::Protected { action: action.map(str::to_owned), level: level.map(str::to_owned) }
+    }
+
+    fn touched(after: &str, before: &str) -> PageFilter {
+        PageFilter::Touched(TimeRange { after: parse_timestamp(&json!(after)).map(TimePoint::Absolute), before: parse_timestamp(&json!(before)).map(TimePoint::Absolute) })
+    }
+
+    #[test]
+    fn page_satisfies_metadata_filters() {
+        let page = info_page();
+        assert!(page_satisfies(&page, &PageFilter::Size(Comparison::Greater, 1000), Utc::now()));
+        assert!(!page_satisfies(&page, &PageFilter::Size(Comparison::Less, 1200), Utc::now()));
+        assert!(page_satisfies(&page, &touched("2020-01-01T00:00:00Z", "2021-01-01T00:00:00Z"), Utc::now()));
+        assert!(!page_satisfies(&page, &touched("2020-06-01T00:00:00Z", "2021-01-01T00:00:00Z"), Utc::now()));
+        // relative times are taken from the time the filter is applied
+        let last_week = PageFilter::Touched(TimeRange { after: Some(TimePoint::Relative(7 * 24 * 60 * 60)), before: None });
+        let now = parse_timestamp(&json!("2020-06-03T00:00:00Z")).unwrap();
+        assert!(page_satisfies(&page, &last_week, now));
+        assert!(!page_satisfies(&page, &last_week, now + chrono::TimeDelta::days(7)));
+        assert!(page_satisfies(&page, &PageFilter::ContentModel(String::from("wikitext")), Utc::now()));
+        assert!(!page_satisfies(&page, &PageFilter::ContentModel(String::from("css")), Utc::now()));
+        // the first edit is checked separately
+        assert!(page_satisfies(&page, &PageFilter::Created(TimeRange::default()), Utc::now()));
+    }
+
+    #[test]
+    fn page_satisfies_protection_filters() {
+        let page = info_page();
+        assert!(page_satisfies(&page, &protected(None, None), Utc::now()));
+        assert!(page_satisfies(&page, &protected(Some("edit"), None), Utc::now()));
+        assert!(page_satisfies(&page, &protected(None, Some("sysop")), Utc::now()));
+        assert!(page_satisfies(&page, &protected(Some("move"), Some("sysop")), Utc::now()));
+        // action and level must hold for the same protection
+        assert!(!page_satisfies(&page, &protected(Some("edit"), Some("sysop")), Utc::now()));
+        assert!(!page_satisfies(&page, &protected(Some("create"), None), Utc::now()));
+    }
+
+    #[test]
+    fn missing_metadata_fail_the_filters() {
+        let page = json!({ "title": "A", "protection": [] });
+        assert!(!page_satisfies(&page, &PageFilter::Size(Comparison::GreaterEqual, 0), Utc::now()));
+        assert!(!page_satisfies(&page, &touched("2020-01-01T00:00:00Z", "2021-01-01T00:00:00Z"), Utc::now()));
+        assert!(!page_satisfies(&page, &protected(None, None), Utc::now()));
+        assert!(!page_satisfies(&page, &PageFilter::ContentModel(String::from("wikitext")), Utc::now()));
     }
 }
@@ -0,0 +1,357 @@
+//! This module performs actions using the replica database
+//!
+//! Instructions are compiled into SQL against the MediaWiki tables `page`, `pagelinks`, `categorylinks`,
+//! `templatelinks`, `linktarget` and `redirect`. Unlike the API, the database can answer for many pages in one query,
+//! so every function here takes a whole set of pages.
+
+use super::{util, error::SolveError};
+use std::collections::{HashMap, HashSet};
+use mediawiki::{api::NamespaceID, title::Title};
+use crate::dbservice::{DBService, SQLParam};
+use crate::parser::ir::{DepthNum, RedirectFilterStrategy};
+
+/// The maximum number of titles put into one query
+const TITLES_PER_QUERY: usize = 500;
+
+/// Titles are stored with underscores and without namespace prefix
+fn db_key(title: &Title) -> String {
+    title.pretty().replace(' ', "_")
+}
+
+/// Builds `((ns_col = ? AND title_col IN (?, ...)) OR ...)` matching `titles`
+fn titles_condition(ns_col: &str, title_col: &str, titles: &[&Title], params: &mut Vec<SQLParam>) -> String {
+    let mut by_ns: HashMap<NamespaceID, Vec<&Title>> = HashMap::new();
+    for title in titles {
+        by_ns.entry(title.namespace_id()).or_default().push(title);
+    }
+    let conds: Vec<String> = by_ns.into_iter().map(|(ns, titles)| {
+        params.push(SQLParam::Int(ns));
+        params.extend(titles.iter().map(|t| SQLParam::Text(db_key(t))));
+        format!("({} = ? AND {} IN ({}))", ns_col, title_col, vec!["?"; titles.len()].join(", "))
+    }).collect();
+    format!("({})", conds.join(" OR "))
+}
+
+/// Builds `col IN (?, ...)` matching `ns`. `ns` must not be empty.
+fn ns_condition(col: &str, ns: &HashSet<NamespaceID>, params: &mut Vec<SQLParam>) -> String {
+    params.extend(ns.iter().map(|n| SQLParam::Int(*n)));
+    format!("{} IN ({})", col, vec!["?"; ns.len()].join(", "))
+}
+
+fn redirect_condition(redirect_strat: RedirectFilterStrategy) -> Option<&'static str> {
+    match redirect_strat {
+        RedirectFilterStrategy::NoRedirect => Some("page_is_redirect = 0"),
+        RedirectFilterStrategy::OnlyRedirect => Some("page_is_redirect = 1"),
+        RedirectFilterStrategy::All => None,
+    }
+}
+
+fn limit_clause(limit: i64) -> String {
+    if limit < 0 {
+        String::new()
+    } else {
+        format!(" LIMIT {}", limit)
+    }
+}
+
+/// Splits `titles` into the redirects among them, and the pages they stand for.
+/// The latter holds every title that is not a redirect, plus the targets of the redirects.
+async fn resolve_redirects(db: &DBService, titles: HashSet<Title>) -> Result<(HashSet<Title>, HashSet<Title>), SolveError> {
+    let mut redirects: HashSet<Title> = HashSet::new();
+    let mut pages: HashSet<Title> = HashSet::new();
+    let titles_vec: Vec<&Title> = titles.iter().collect();
+    for batch in titles_vec.chunks(TITLES_PER_QUERY) {
+        let mut params = Vec::new();
+        let cond = titles_condition("page_namespace", "page_title", batch, &mut params);
+        let sql = format!(
+            "SELECT page_namespace, CAST(page_title AS CHAR), rd_namespace, CAST(rd_title AS CHAR) FROM page \
+            JOIN redirect ON rd_from = page_id \
+            WHERE {} AND (rd_interwiki IS NULL OR rd_interwiki = '')",
+            cond
+        );
+        for (from, to) in db.fetch_title_pairs(&sql, &params).await? {
+            redirects.insert(from);
+            pages.insert(to);
+        }
+    }
+    pages.extend(titles.into_iter().filter(|t| !redirects.contains(t)));
+    Ok((redirects, pages))
+}
+
+/// Counterpart of resolving redirects in an API query: picks the redirects, their targets, or both.
+async fn apply_redirect_strategy(db: &DBService, titles: HashSet<Title>, follow_redir: bool, redirect_strat: RedirectFilterStrategy) -> Result<HashSet<Title>, SolveError> {
+    if follow_redir {
+        let (redirects, pages) = resolve_redirects(db, titles).await?;
+        Ok(match redirect_strat {
+            RedirectFilterStrategy::NoRedirect => pages,
+            RedirectFilterStrategy::OnlyRedirect => redirects,
+            RedirectFilterStrategy::All => redirects.union(&pages).cloned().collect(),
+        })
+    } else {
+        Ok(titles)
+    }
+}
+
+/// Retrives the pages linking to, or transcluding, a set of pages, through `table`.
+/// `table` is `pagelinks` (prefix `pl`) or `templatelinks` (prefix `tl`). Both refer to their targets through `linktarget`.
+async fn get_linking_pages(db: &DBService, table: &str, prefix: &str, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    if ns.map(|ns_list| ns_list.is_empty()).unwrap_or(false) {
+        return Ok(HashSet::new());
+    }
+    let titles_vec: Vec<&Title> = titles.iter().collect();
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in titles_vec.chunks(TITLES_PER_QUERY) {
+        let batch_limit = match util::remaining_limit(limit, title_set.len()) {
+            Some(batch_limit) => batch_limit,
+            None => break,
+        };
+        let mut params = Vec::new();
+        let mut conds = vec![titles_condition("lt_namespace", "lt_title", batch, &mut params)];
+        if let Some(ns_list) = ns {
+            conds.push(ns_condition("page_namespace", ns_list, &mut params));
+        }
+        if let Some(cond) = redirect_condition(redirect_strat) {
+            conds.push(cond.to_string());
+        }
+        let sql = format!(
+            "SELECT DISTINCT page_namespace, CAST(page_title AS CHAR) FROM linktarget \
+            JOIN {table} ON {prefix}_target_id = lt_id \
+            JOIN page ON page_id = {prefix}_from \
+            WHERE {}{}",
+            conds.join(" AND "), limit_clause(batch_limit),
+            table = table, prefix = prefix
+        );
+        title_set.extend(db.fetch_titles(&sql, &params).await?);
+    }
+    Ok(title_set)
+}
+
+/// Retrives the backlinks of a set of pages. Refer to `apisolver::get_backlinks_one` for what a backlink is.
+///
+/// `titles`: The titles of the pages.
+///
+/// `db`: The database to query.
+///
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+///
+/// `level_2`: Whether to include pages that links to a redirect of any page in `titles`.
+///
+/// `redirect_strat`: The redirect strategy to use when querying.
+///
+/// `follow_redir`: Whether should follow redirects.
+///
+/// `limit`: Query limit, applied to the whole result.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn get_backlinks(db: &DBService, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, level_2: bool, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let mut targets = titles.to_owned();
+    if level_2 {
+        // Unlike the API, the namespace filter only applies to the linking pages,
+        // so redirects from other namespaces are still followed.
+        let titles_vec: Vec<&Title> = titles.iter().collect();
+        for batch in titles_vec.chunks(TITLES_PER_QUERY) {
+            let mut params = Vec::new();
+            let cond = titles_condition("rd_namespace", "rd_title", batch, &mut params);
+            let sql = format!(
+                "SELECT page_namespace, CAST(page_title AS CHAR) FROM redirect \
+                JOIN page ON page_id = rd_from \
+                WHERE {} AND (rd_interwiki IS NULL OR rd_interwiki = '')",
+                cond
+            );
+            targets.extend(db.fetch_titles(&sql, &params).await?);
+        }
+    }
+    let title_set = get_linking_pages(db, "pagelinks", "pl", &targets, ns, redirect_strat, limit).await?;
+    apply_redirect_strategy(db, title_set, follow_redir, redirect_strat).await
+}
+
+/// Retrives the pages that embed any page of a set.
+///
+/// `titles`: The titles of the pages.
+///
+/// `db`: The database to query.
+///
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+///
+/// `redirect_strat`: The redirect strategy to use when querying.
+///
+/// `follow_redir`: Whether should follow redirects.
+///
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_embed(db: &DBService, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let title_set = get_linking_pages(db, "templatelinks", "tl", titles, ns, redirect_strat, limit).await?;
+    apply_redirect_strategy(db, title_set, follow_redir, redirect_strat).await
+}
+
+/// Retrives the in-wiki links of a set of pages.
+///
+/// `titles`: The titles of the pages.
+///
+/// `db`: The database to query.
+///
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+///
+/// `follow_redir`: Whether should follow redirects.
+///
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_links(db: &DBService, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    if ns.map(|ns_list| ns_list.is_empty()).unwrap_or(false) {
+        return Ok(HashSet::new());
+    }
+    let titles_vec: Vec<&Title> = titles.iter().collect();
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in titles_vec.chunks(TITLES_PER_QUERY) {
+        let batch_limit = match util::remaining_limit(limit, title_set.len()) {
+            Some(batch_limit) => batch_limit,
+            None => break,
+        };
+        let mut params = Vec::new();
+        let mut conds = vec![titles_condition("page_namespace", "page_title", batch, &mut params)];
+        if let Some(ns_list) = ns {
+            conds.push(ns_condition("lt_namespace", ns_list, &mut params));
+        }
+        let sql = format!(
+            "SELECT DISTINCT lt_namespace, CAST(lt_title AS CHAR) FROM page \
+            JOIN pagelinks ON pl_from = page_id \
+            JOIN linktarget ON lt_id = pl_target_id \
+            WHERE {}{}",
+            conds.join(" AND "), limit_clause(batch_limit)
+        );
+        title_set.extend(db.fetch_titles(&sql, &params).await?);
+    }
+    apply_redirect_strategy(db, title_set, follow_redir, RedirectFilterStrategy::NoRedirect).await
+}
+
+/// Retrives the members of a set of categories. Dive into subcategories if possible.
+///
+/// Each level of the category tree is fetched at once, instead of one request per category.
+///
+/// `titles`: The titles of the categories.
+///
+/// `db`: The database to query.
+///
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+///
+/// `depth`: Maximum depth we should dive into. Refer to `apisolver::get_category_members_one`.
+///
+/// `follow_redir`: Whether should follow redirects.
+///
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_category_members(db: &DBService, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, depth: DepthNum, follow_redir: bool, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    if titles.iter().any(|t| t.namespace_id() != super::def::NS_CATEGORY) {
+        return Err(SolveError::NotCategory);
+    }
+    let mut ns_clone = ns.cloned();
+    let mut result_has_ns_category: bool = true;
+    let mut result_has_ns_file: bool = true;
+    if let Some(ns_list) = ns_clone.as_mut() {
+        result_has_ns_category = ns_list.remove(&super::def::NS_CATEGORY);
+        result_has_ns_file = ns_list.remove(&super::def::NS_FILE);
+    }
+    let mut result_set: HashSet<Title> = HashSet::new();
+    let mut visited_cats: HashSet<Title> = titles.to_owned();
+    let mut this_level: Vec<Title> = titles.iter().cloned().collect();
+    let mut this_depth: DepthNum = 0;
+    while !this_level.is_empty() {
+        let dive = depth < 0 || this_depth < depth;
+        // determine what cl_type and page_namespace should we match
+        let mut cltype: Vec<&str> = Vec::new();
+        let mut clnamespace: HashSet<NamespaceID> = HashSet::new();
+        if let Some(ns_list) = &ns_clone {
+            if !ns_list.is_empty() {
+                cltype.push("page");
+                clnamespace.extend(ns_list);
+            }
+        } else {
+            cltype.push("page");
+        }
+        if result_has_ns_file {
+            cltype.push("file");
+            clnamespace.insert(super::def::NS_FILE);
+        }
+        if result_has_ns_category || dive {
+            cltype.push("subcat");
+            clnamespace.insert(super::def::NS_CATEGORY);
+        }
+        if cltype.is_empty() {
+            break;
+        }
+        let mut level_set: HashSet<Title> = HashSet::new();
+        for batch in this_level.chunks(TITLES_PER_QUERY) {
+            let batch_limit = match util::remaining_limit(limit, result_set.len() + level_set.len()) {
+                Some(batch_limit) => batch_limit,
+                None => break,
+            };
+            let mut params: Vec<SQLParam> = batch.iter().map(|t| SQLParam::Text(db_key(t))).collect();
+            let mut conds = vec![format!("cl_to IN ({})", vec!["?"; batch.len()].join(", "))];
+            params.extend(cltype.iter().map(|t| SQLParam::Text(t.to_string())));
+            conds.push(format!("cl_type IN ({})", vec!["?"; cltype.len()].join(", ")));
+            if ns_clone.is_some() {
+                conds.push(ns_condition("page_namespace", &clnamespace, &mut params));
+            }
+            let sql = format!(
+                "SELECT DISTINCT page_namespace, CAST(page_title AS CHAR) FROM categorylinks \
+                JOIN page ON page_id = cl_from \
+                WHERE {}{}",
+                conds.join(" AND "), limit_clause(batch_limit)
+            );
+            level_set.extend(db.fetch_titles(&sql, &params).await?);
+        }
+        let mut next_level: Vec<Title> = Vec::new();
+        if dive {
+            for sub in level_set.iter().filter(|&t| t.namespace_id() == super::def::NS_CATEGORY) {
+                if visited_cats.insert(sub.to_owned()) {
+                    next_level.push(sub.to_owned());
+                }
+            }
+        }
+        if !result_has_ns_category {
+            level_set.retain(|f| f.namespace_id() != super::def::NS_CATEGORY);
+        }
+        result_set.extend(level_set);
+        this_level = next_level;
+        this_depth += 1;
+    }
+    apply_redirect_strategy(db, result_set, follow_redir, RedirectFilterStrategy::NoRedirect).await
+}
+
+/// Retrives the pages with any of the given prefixes.
+///
+/// Pages whose namespace is not in `ns` are skipped, as with `apisolver::get_prefix_index_one`.
+///
+/// `titles`: The prefixes.
+///
+/// `db`: The database to query.
+///
+/// `ns`: Namespace filter. If set to `None`, then the result is not filtered by namespace.
+///
+/// `redirect_strat`: The redirect strategy to use when querying.
+///
+/// `limit`: Query limit, applied to the whole result.
+pub(crate) async fn get_prefix_index(db: &DBService, titles: &HashSet<Title>, ns: Option<&HashSet<NamespaceID>>, redirect_strat: RedirectFilterStrategy, limit: i64) -> Result<HashSet<Title>, SolveError> {
+    let titles_vec: Vec<&Title> = titles.iter().filter(|t| ns.map(|ns_list| ns_list.contains(&t.namespace_id())).unwrap_or(true)).collect();
+    let mut title_set: HashSet<Title> = HashSet::new();
+    for batch in titles_vec.chunks(TITLES_PER_QUERY) {
+        let batch_limit = match util::remaining_limit(limit, title_set.len()) {
+            Some(batch_limit) => batch_limit,
+            None => break,
+        };
+        let mut params = Vec::new();
+        let prefix_conds: Vec<String> = batch.iter().map(|t| {
+            // `_` stands for a space in titles, and is a wildcard in `LIKE`
+            let pattern = db_key(t).replace('!', "!!").replace('%', "!%").replace('_', "!_");
+            params.push(SQLParam::Int(t.namespace_id()));
+            params.push(SQLParam::Text(format!("{}%", pattern)));
+            "(page_namespace = ? AND page_title LIKE ? ESCAPE '!')".to_string()
+        }).collect();
+        let mut conds = vec![format!("({})", prefix_conds.join(" OR "))];
+        if let Some(cond) = redirect_condition(redirect_strat) {
+            conds.push(cond.to_string());
+        }
+        let sql = format!(
+            "SELECT page_namespace, CAST(page_title AS CHAR) FROM page WHERE {}{}",
+            conds.join(" AND "), limit_clause(batch_limit)
+        );
+        title_set.extend(db.fetch_titles(&sql, &params).await?);
+    }
+    Ok(title_set)
+}
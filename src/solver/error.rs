@@ -2,14 +2,19 @@ use std::error::Error;
 use std::fmt;
 
 use crate::apiservice::APIServiceError;
+use crate::dbservice::DBServiceError;
 
 #[derive(Debug)]
 pub enum SolveError {
     MediaWiki(mediawiki::media_wiki_error::MediaWikiError),
     APIService(APIServiceError),
-    QueryForMultiplePages,
+    DBService(DBServiceError),
+    TooManyPages(usize, i64),
     UnknownIntermediateValue,
     NotCategory,
+    UnknownLanguage,
+    ContentTooLarge(usize),
+    TimeOutOfRange,
 }
 
 impl Error for SolveError {}
@@ -19,10 +24,14 @@ impl fmt::Display for SolveError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::MediaWiki(e) => e.fmt(f),
-            Self::QueryForMultiplePages => f.write_str("cannot query for multiple pages"),
+            Self::TooManyPages(count, limit) => f.write_fmt(format_args!("cannot query for {} pages at once, the limit is {}", count, limit)),
             Self::APIService(e) => f.write_fmt(format_args!("API Service fails with error: \"{}\"", e)),
+            Self::DBService(e) => f.write_fmt(format_args!("DB Service fails with error: \"{}\"", e)),
             Self::UnknownIntermediateValue => f.write_str("cannot access an intermediate value before it is initialized"),
             Self::NotCategory => f.write_str("cannot query for members of something not a category"),
+            Self::UnknownLanguage => f.write_str("cannot find the interlanguage prefix of this wiki, pages of other wikis cannot be mapped to it"),
+            Self::ContentTooLarge(limit) => f.write_fmt(format_args!("cannot search more than {} bytes of page content, narrow down the pages first", limit)),
+            Self::TimeOutOfRange => f.write_str("cannot compute a time this far from now"),
         }
     }
 }
@@ -38,3 +47,9 @@ impl From<APIServiceError> for SolveError {
         Self::APIService(e)
     }
 }
+
+impl From<DBServiceError> for SolveError {
+    fn from(e: DBServiceError) -> Self {
+        Self::DBService(e)
+    }
+}
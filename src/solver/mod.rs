@@ -3,146 +3,849 @@ extern crate mediawiki;
 mod util;
 mod error;
 mod apisolver;
+mod template;
+mod dbsolver;
 mod def;
 
 pub use error::SolveError;
-use crate::{parser::{ir::RegID, ir::RedirectFilterStrategy}, API_SERVICE};
+use crate::backend::WikiBackend;
+use crate::dbservice::DBService;
+use crate::parser::{ir::RegID, ir::RedirectFilterStrategy, ir::ActivitySource};
 use util::{get_set_1, get_set_2};
 
 use crate::parser::{Query, ir::Instruction};
 
-use std::collections::{HashSet, HashMap};
+use std::collections::{HashSet, HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use futures::stream::{FuturesUnordered, StreamExt};
 use mediawiki::{title::Title};
 
-pub(crate) type Register = HashMap<RegID, HashSet<Title>>;
+pub(crate) type Register = HashMap<RegID, Arc<HashSet<Title>>>;
+
+/// Per-query settings of the solver
+///
+/// `querylimit`: the limit used by instructions without a `.limit()` constraint. Negative for no limit.
+///
+/// `fanout`: the maximum number of pages a unary instruction may run on. Negative for no limit.
+///
+/// `parallelism`: the maximum number of instructions evaluated at the same time. Treated as 1 if set to 0.
+#[derive(Debug, Clone, Copy)]
+pub struct SolverConfig {
+    pub querylimit: i64,
+    pub fanout: i64,
+    pub parallelism: usize,
+}
+
+/// Solves a query with MediaWiki API.
+pub async fn solve_api(backend: &dyn WikiBackend, query: &Query, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+    solve(backend, None, query, config).await
+}
+
+/// Solves a query with the replica database.
+/// `backend` is still used to parse the titles written in the query.
+pub async fn solve_db(backend: &dyn WikiBackend, db: &DBService, query: &Query, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+    solve(backend, Some(db), query, config).await
+}
+
+/// Evaluates a query as a dataflow graph.
+///
+/// An instruction becomes ready once every register it reads has been written.
+/// Ready instructions are evaluated concurrently, at most `config.parallelism` at a time.
+/// A register is released as soon as the last instruction reading it has finished.
+///
+/// Generating instructions go to the database if `db` is present, and to the API otherwise.
+async fn solve(backend: &dyn WikiBackend, db: Option<&DBService>, query: &Query, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+    let insts = &query.0;
+    // For every instruction, the distinct registers it reads
+    let operands: Vec<Vec<RegID>> = insts.iter().map(|inst| {
+        let mut ops = inst.get_operands();
+        ops.sort_unstable();
+        ops.dedup();
+        ops
+    }).collect();
+    // For every register, the instructions that read it
+    let mut dependents: HashMap<RegID, Vec<usize>> = HashMap::new();
+    for (idx, ops) in operands.iter().enumerate() {
+        for op in ops {
+            dependents.entry(*op).or_default().push(idx);
+        }
+    }
+    let mut remaining_readers: HashMap<RegID, usize> = dependents.iter().map(|(reg, readers)| (*reg, readers.len())).collect();
+    let mut waiting_for: Vec<usize> = operands.iter().map(|ops| ops.len()).collect();
+    let mut ready: VecDeque<usize> = waiting_for.iter().enumerate().filter(|(_, n)| **n == 0).map(|(idx, _)| idx).collect();
 
-pub async fn solve_api(query: &Query, default_limit: i64) -> Result<HashSet<Title>, SolveError> {
-    // prepare a mock register pool using HashMap
     let mut reg: Register = HashMap::new();
-    for inst in query.0.iter() {
-        match inst {
-            Instruction::And { dest, op1, op2 } => {
-                let (set1, set2) = get_set_2(&reg, op1, op2)?;
-                let intersect: HashSet<Title> = set1.intersection(set2).cloned().collect();
-                reg.insert(*dest, intersect);
-            },
-            Instruction::Or { dest, op1, op2 } => {
-                let (set1, set2) = get_set_2(&reg, op1, op2)?;
-                let union: HashSet<Title> = set1.union(set2).cloned().collect();
-                reg.insert(*dest, union);
-            },
-            Instruction::Exclude { dest, op1, op2 } => {
-                let (set1, set2) = get_set_2(&reg, op1, op2)?;
-                let diff: HashSet<Title> = set1.difference(set2).cloned().collect();
-                reg.insert(*dest, diff);
-            },
-            Instruction::Xor { dest, op1, op2 } => {
-                let (set1, set2) = get_set_2(&reg, op1, op2)?;
-                let xor: HashSet<Title> = set1.symmetric_difference(set2).cloned().collect();
-                reg.insert(*dest, xor);
-            },
-            Instruction::Link { dest, op, cs } => {
-                let set = get_set_1(&reg, op)?;
-                if set.is_empty() {
-                    reg.insert(*dest, HashSet::new());
-                } else if set.len() > 1 {
-                    return Err(SolveError::QueryForMultiplePages);
-                } else {
-                    let mut result_set: HashSet<Title> = HashSet::new();
-                    for t in set.iter() {
-                        let res_one = apisolver::get_links_one(t, cs.ns.as_ref(), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await?;
-                        result_set.extend(res_one);
+    let mut running = FuturesUnordered::new();
+    let parallelism = usize::max(config.parallelism, 1);
+    loop {
+        while running.len() < parallelism {
+            if let Some(idx) = ready.pop_front() {
+                let inputs = util::get_inputs(&reg, &operands[idx])?;
+                let inst = &insts[idx];
+                running.push(async move { (idx, solve_one(backend, db, inst, inputs, config).await) });
+            } else {
+                break;
+            }
+        }
+        if let Some((idx, result)) = running.next().await {
+            let set = result?;
+            for op in &operands[idx] {
+                if let Some(n) = remaining_readers.get_mut(op) {
+                    *n -= 1;
+                    if *n == 0 && *op != query.1 {
+                        reg.remove(op);
                     }
-                    reg.insert(*dest, result_set);
                 }
-            },
-            Instruction::LinkTo { dest, op, cs } => {
-                let set = get_set_1(&reg, op)?;
-                if set.is_empty() {
-                    reg.insert(*dest, HashSet::new());
-                } else if set.len() > 1 {
-                    return Err(SolveError::QueryForMultiplePages);
-                } else {
-                    let mut result_set: HashSet<Title> = HashSet::new();
-                    for t in set.iter() {
-                        let res_one = apisolver::get_backlinks_one(t, cs.ns.as_ref(), !cs.directlink.unwrap_or(false), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await?;
-                        result_set.extend(res_one);
+            }
+            let dest = insts[idx].get_dest();
+            reg.insert(dest, Arc::new(set));
+            if let Some(readers) = dependents.get(&dest) {
+                for reader in readers {
+                    waiting_for[*reader] -= 1;
+                    if waiting_for[*reader] == 0 {
+                        ready.push_back(*reader);
                     }
-                    reg.insert(*dest, result_set);
                 }
-            },
-            Instruction::EmbeddedIn { dest, op, cs } => {
-                let set = get_set_1(&reg, op)?;
-                if set.is_empty() {
-                    reg.insert(*dest, HashSet::new());
-                } else if set.len() > 1 {
-                    return Err(SolveError::QueryForMultiplePages);
-                } else {
-                    let mut result_set: HashSet<Title> = HashSet::new();
-                    for t in set.iter() {
-                        let res_one = apisolver::get_embed_one(t, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await?;
-                        result_set.extend(res_one);
-                    }
-                    reg.insert(*dest, result_set);
+            }
+        } else {
+            break;
+        }
+    }
+
+    let result = reg.remove(&query.1).ok_or(SolveError::UnknownIntermediateValue)?;
+    Ok(Arc::try_unwrap(result).unwrap_or_else(|shared| (*shared).clone()))
+}
+
+/// `solve` behind a pointer, so that `Site` instructions can run a query within a query
+fn solve_boxed<'a>(backend: &'a dyn WikiBackend, query: &'a Query, config: &'a SolverConfig) -> Pin<Box<dyn Future<Output = Result<HashSet<Title>, SolveError>> + Send + 'a>> {
+    Box::pin(solve(backend, None, query, config))
+}
+
+/// Evaluates one instruction, then keeps the pages satisfying its title patterns, metadata conditions, page properties and content patterns. `inputs` holds the registers it reads.
+async fn solve_one(backend: &dyn WikiBackend, db: Option<&DBService>, inst: &Instruction, inputs: Register, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+    let mut set = solve_inst(backend, db, inst, inputs, config).await?;
+    if let Some(cs) = inst.get_constraint() {
+        if !cs.titlematch.is_empty() {
+            set = util::filter_titles(backend, set, &cs.titlematch).await?;
+        }
+        // the metadata are looked up with the API even when the replica database is used
+        if !cs.pagefilter.is_empty() && !set.is_empty() {
+            set = apisolver::filter_by_metadata(backend, &set, &cs.pagefilter, config.fanout).await?;
+        }
+        if !cs.pageprops.is_empty() && !set.is_empty() {
+            set = apisolver::filter_by_pageprops(backend, &set, &cs.pageprops).await?;
+        }
+        if !cs.contains.is_empty() && !set.is_empty() {
+            set = apisolver::filter_by_content(backend, &set, &cs.contains, config.querylimit).await?;
+        }
+    }
+    Ok(set)
+}
+
+async fn solve_inst(backend: &dyn WikiBackend, db: Option<&DBService>, inst: &Instruction, inputs: Register, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+    let default_limit = config.querylimit;
+    match inst {
+        Instruction::And { op1, op2, .. } => {
+            let (set1, set2) = get_set_2(&inputs, op1, op2)?;
+            Ok(set1.intersection(set2).cloned().collect())
+        },
+        Instruction::Or { op1, op2, .. } => {
+            let (set1, set2) = get_set_2(&inputs, op1, op2)?;
+            Ok(set1.union(set2).cloned().collect())
+        },
+        Instruction::Exclude { op1, op2, .. } => {
+            let (set1, set2) = get_set_2(&inputs, op1, op2)?;
+            Ok(set1.difference(set2).cloned().collect())
+        },
+        Instruction::Xor { op1, op2, .. } => {
+            let (set1, set2) = get_set_2(&inputs, op1, op2)?;
+            Ok(set1.symmetric_difference(set2).cloned().collect())
+        },
+        Instruction::Link { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                if let Some(db) = db {
+                    return dbsolver::get_links(db, set, cs.ns.as_ref(), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await;
                 }
-            },
-            Instruction::InCat { dest, op, cs } => {
-                let set = get_set_1(&reg, op)?;
-                if set.is_empty() {
-                    reg.insert(*dest, HashSet::new());
-                } else if set.len() > 1 {
-                    return Err(SolveError::QueryForMultiplePages);
-                } else {
-                    let sub_limit = cs.depth.unwrap_or(0);
-                    let mut result_set: HashSet<Title> = HashSet::new();
-                    for t in set.iter() {
-                        let res_one = apisolver::get_category_members_one(t, cs.ns.as_ref(), sub_limit, cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await?;
-                        result_set.extend(res_one);
-                    }
-                    reg.insert(*dest, result_set);
+                apisolver::get_links(backend, set, cs.ns.as_ref(), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await
+            }
+        },
+        Instruction::LinkTo { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                if let Some(db) = db {
+                    return dbsolver::get_backlinks(db, set, cs.ns.as_ref(), !cs.directlink.unwrap_or(false), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await;
                 }
-            },
-            Instruction::Toggle { dest, op } => {
-                let set = get_set_1(&reg, op)?;
-                let title_set: HashSet<Title> = set.iter().cloned().map(|title| title.into_toggle_talk()).collect();
-                reg.insert(*dest, title_set);
-            },
-            Instruction::Prefix { dest, op, cs } => {
-                let set = get_set_1(&reg, op)?;
-                if set.is_empty() {
-                    reg.insert(*dest, HashSet::new());
-                } else if set.len() > 1 {
-                    return Err(SolveError::QueryForMultiplePages);
+                let mut result_set: HashSet<Title> = HashSet::new();
+                for t in set.iter() {
+                    let limit = match util::remaining_limit(cs.limit.unwrap_or(default_limit), result_set.len()) {
+                        Some(limit) => limit,
+                        None => break,
+                    };
+                    let res_one = apisolver::get_backlinks_one(backend, t, cs.ns.as_ref(), !cs.directlink.unwrap_or(false), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), limit).await?;
+                    result_set.extend(res_one);
+                }
+                Ok(result_set)
+            }
+        },
+        Instruction::EmbeddedIn { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                let result_set = if let Some(db) = db {
+                    dbsolver::get_embed(db, set, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await?
                 } else {
                     let mut result_set: HashSet<Title> = HashSet::new();
                     for t in set.iter() {
-                        let res_one = apisolver::get_prefix_index_one(t, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.limit.unwrap_or(default_limit)).await?;
+                        let limit = match util::remaining_limit(cs.limit.unwrap_or(default_limit), result_set.len()) {
+                            Some(limit) => limit,
+                            None => break,
+                        };
+                        let res_one = apisolver::get_embed_one(backend, t, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), limit).await?;
                         result_set.extend(res_one);
                     }
-                    reg.insert(*dest, result_set);
+                    result_set
+                };
+                if cs.params.is_empty() || result_set.is_empty() {
+                    Ok(result_set)
+                } else {
+                    // the template calls are read from the content, which only the API provides
+                    apisolver::filter_by_params(backend, &result_set, set, &cs.params, cs.limit.unwrap_or(default_limit)).await
+                }
+            }
+        },
+        Instruction::InCat { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                if let Some(db) = db {
+                    return dbsolver::get_category_members(db, set, cs.ns.as_ref(), cs.depth.unwrap_or(0), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await;
+                }
+                let sub_limit = cs.depth.unwrap_or(0);
+                let mut result_set: HashSet<Title> = HashSet::new();
+                for t in set.iter() {
+                    let limit = match util::remaining_limit(cs.limit.unwrap_or(default_limit), result_set.len()) {
+                        Some(limit) => limit,
+                        None => break,
+                    };
+                    let res_one = apisolver::get_category_members_one(backend, t, cs.ns.as_ref(), sub_limit, cs.resolveredir.unwrap_or(false), limit).await?;
+                    result_set.extend(res_one);
+                }
+                Ok(result_set)
+            }
+        },
+        Instruction::Toggle { op, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            Ok(set.iter().cloned().map(|title| title.into_toggle_talk()).collect())
+        },
+        Instruction::Prefix { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                if let Some(db) = db {
+                    return dbsolver::get_prefix_index(db, set, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.limit.unwrap_or(default_limit)).await;
+                }
+                let mut result_set: HashSet<Title> = HashSet::new();
+                for t in set.iter() {
+                    let limit = match util::remaining_limit(cs.limit.unwrap_or(default_limit), result_set.len()) {
+                        Some(limit) => limit,
+                        None => break,
+                    };
+                    let res_one = apisolver::get_prefix_index_one(backend, t, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), limit).await?;
+                    result_set.extend(res_one);
+                }
+                Ok(result_set)
+            }
+        },
+        Instruction::Categories { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                // these go to the API even when the replica database is used
+                util::check_fanout(set, config.fanout)?;
+                apisolver::get_categories(backend, set, cs.ns.as_ref(), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await
+            }
+        },
+        Instruction::Templates { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                apisolver::get_templates(backend, set, cs.ns.as_ref(), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await
+            }
+        },
+        Instruction::Images { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                apisolver::get_images(backend, set, cs.ns.as_ref(), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await
+            }
+        },
+        Instruction::FileUsage { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                apisolver::get_file_usage(backend, set, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.resolveredir.unwrap_or(false), cs.limit.unwrap_or(default_limit)).await
+            }
+        },
+        Instruction::Redirects { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                apisolver::get_redirects(backend, set, cs.ns.as_ref(), cs.limit.unwrap_or(default_limit)).await
+            }
+        },
+        Instruction::Subpages { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if set.is_empty() {
+                Ok(HashSet::new())
+            } else {
+                util::check_fanout(set, config.fanout)?;
+                // the subpages of a page are the pages prefixed with its title and a slash
+                let prefixes: HashSet<Title> = set.iter().map(|t| Title::new(&format!("{}/", t.pretty()), t.namespace_id())).collect();
+                if let Some(db) = db {
+                    return dbsolver::get_prefix_index(db, &prefixes, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), cs.limit.unwrap_or(default_limit)).await;
+                }
+                let mut result_set: HashSet<Title> = HashSet::new();
+                for t in prefixes.iter() {
+                    let limit = match util::remaining_limit(cs.limit.unwrap_or(default_limit), result_set.len()) {
+                        Some(limit) => limit,
+                        None => break,
+                    };
+                    let res_one = apisolver::get_prefix_index_one(backend, t, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), limit).await?;
+                    result_set.extend(res_one);
                 }
-            },
-            Instruction::Set { dest, titles, cs } => {
-                let mut title_set: HashSet<Title> = HashSet::new();
-                for t in titles {
-                    let title: Title = API_SERVICE.title_new_from_full(t).await?;
-                    if let Some(nss) = &cs.ns {
-                        if !nss.contains(&title.namespace_id()) {
-                            continue;
-                        }
+                Ok(result_set)
+            }
+        },
+        Instruction::Filter { op, cs, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            if let Some(nss) = &cs.ns {
+                Ok(set.iter().filter(|title| nss.contains(&title.namespace_id())).cloned().collect())
+            } else {
+                Ok(set.to_owned())
+            }
+        },
+        Instruction::Set { titles, cs, .. } => {
+            let mut title_set: HashSet<Title> = HashSet::new();
+            for t in titles {
+                let title: Title = backend.title_new_from_full(t).await?;
+                if let Some(nss) = &cs.ns {
+                    if !nss.contains(&title.namespace_id()) {
+                        continue;
                     }
-                    title_set.insert(title);
                 }
-                reg.insert(*dest, title_set);
-            },
-            Instruction::Nop { dest, op } => {
-                let set = get_set_1(&reg, op)?;
-                let copiedset = set.clone();
-                reg.insert(*dest, copiedset);
-            },
+                title_set.insert(title);
+            }
+            Ok(title_set)
+        },
+        Instruction::Site { site, query, cs, .. } => {
+            // the replica database only covers this wiki
+            let remote = backend.site(site).await?;
+            let remote_set = solve_boxed(remote.as_ref(), query, config).await?;
+            let mut title_set = apisolver::get_langlinks(remote.as_ref(), backend, &remote_set).await?;
+            if let Some(nss) = &cs.ns {
+                title_set.retain(|title| nss.contains(&title.namespace_id()));
+            }
+            Ok(title_set)
+        },
+        Instruction::Search { query, cs, .. } => {
+            // the replica database has no search index
+            apisolver::get_search(backend, query, cs.ns.as_ref(), cs.limit.unwrap_or(default_limit)).await
+        },
+        Instruction::Special { page, cs, .. } => {
+            apisolver::get_query_page(backend, page, cs.ns.as_ref(), cs.limit.unwrap_or(default_limit)).await
+        },
+        Instruction::WithProp { prop, cs, .. } => {
+            apisolver::get_pages_with_prop(backend, prop, cs.ns.as_ref(), cs.limit.unwrap_or(default_limit)).await
+        },
+        Instruction::Activity { source, since, until, cs, .. } => {
+            // the recent changes, the logs and the contributions are read from the API
+            let now = chrono::Utc::now();
+            let since = since.map(|t| t.resolve(now).ok_or(SolveError::TimeOutOfRange)).transpose()?;
+            let until = until.map(|t| t.resolve(now).ok_or(SolveError::TimeOutOfRange)).transpose()?;
+            let limit = cs.limit.unwrap_or(default_limit);
+            match source {
+                ActivitySource::NewPages => apisolver::get_recent_changes(backend, true, since, until, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), limit).await,
+                ActivitySource::RecentChanges => apisolver::get_recent_changes(backend, false, since, until, cs.ns.as_ref(), cs.redir.unwrap_or(RedirectFilterStrategy::All), limit).await,
+                ActivitySource::Log(log) => apisolver::get_log_events(backend, log, since, until, cs.ns.as_ref(), limit).await,
+                ActivitySource::Contribs(user) => apisolver::get_user_contribs(backend, user, None, since, until, cs.ns.as_ref(), limit).await,
+                ActivitySource::CreatedBy(user) => apisolver::get_user_contribs(backend, user, Some("new"), since, until, cs.ns.as_ref(), limit).await,
+                ActivitySource::LastEditor(user) => apisolver::get_user_contribs(backend, user, Some("top"), since, until, cs.ns.as_ref(), limit).await,
+            }
+        },
+        Instruction::Nop { op, .. } => {
+            let set = get_set_1(&inputs, op)?;
+            Ok(set.to_owned())
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::parser::{parse_with_namespaces, NamespaceTable};
+
+    fn config(querylimit: i64) -> SolverConfig {
+        SolverConfig { querylimit, fanout: -1, parallelism: 1 }
+    }
+
+    async fn solve_on(wiki: &MemoryBackend, src: &str, config: &SolverConfig) -> Result<HashSet<Title>, SolveError> {
+        let query = parse_with_namespaces(src, &NamespaceTable::canonical()).unwrap();
+        solve_api(wiki, &query, config).await
+    }
+
+    fn categorized_wiki() -> MemoryBackend {
+        let mut wiki = MemoryBackend::new();
+        for cat in ["A", "B", "C"] {
+            for idx in 0..3 {
+                wiki.add_category(&format!("{} {}", cat, idx), &format!("Category:{}", cat));
+            }
+        }
+        wiki
+    }
+
+    async fn titles(wiki: &MemoryBackend, names: &[&str]) -> HashSet<Title> {
+        let mut set = HashSet::new();
+        for name in names {
+            set.insert(wiki.title_new_from_full(name).await.unwrap());
+        }
+        set
+    }
+
+    #[tokio::test]
+    async fn content_is_searched_within_the_query_limit() {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_page("A", "foo bar").add_page("B", "bar").add_page("C", "foo");
+        let src = "page(\"A\", \"B\", \"C\").contains(\"foo\")";
+        assert_eq!(solve_on(&wiki, src, &config(3)).await.unwrap(), titles(&wiki, &["A", "C"]).await);
+        // searching an arbitrary subset would drop matches at random
+        assert!(matches!(solve_on(&wiki, src, &config(2)).await, Err(SolveError::TooManyPages(3, 2))));
+    }
+
+    #[tokio::test]
+    async fn first_edits_are_looked_up_within_fanout() {
+        let wiki = sample_wiki();
+        let src = "page(\"A\", \"B\", \"C\").contentmodel(\"wikitext\").created(after=\"2020-01-01\")";
+        let strict = SolverConfig { querylimit: -1, fanout: 2, parallelism: 1 };
+        assert!(matches!(solve_on(&wiki, src, &strict).await, Err(SolveError::TooManyPages(3, 2))));
+        // without `.created(...)`, the metadata are looked up in batches
+        let batched = "page(\"A\", \"B\", \"C\").contentmodel(\"wikitext\")";
+        assert_eq!(solve_on(&wiki, batched, &strict).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn namespaces_of_the_wiki_are_resolved() {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_namespace(100, "Portal").add_namespace_alias("P", 100);
+        wiki.add_link("Portal:X", "B").add_link("User:Y", "B").add_link("A", "B");
+        let namespaces = NamespaceTable::new(wiki.namespace_names().await.unwrap());
+        for (src, expected) in [
+            ("linkto(\"B\").ns(P)", &["Portal:X"][..]),
+            ("linkto(\"B\").ns(!User)", &["Portal:X", "A"]),
+            ("linkto(\"B\").ns(0..100)", &["Portal:X", "User:Y", "A"]),
+        ] {
+            let query = parse_with_namespaces(src, &namespaces).unwrap();
+            assert_eq!(solve_api(&wiki, &query, &config(-1)).await.unwrap(), titles(&wiki, expected).await, "{}", src);
+        }
+        // with the canonical names only, the exclusion would leave out Portal:X
+        assert!(parse_with_namespaces("linkto(\"B\").ns(!User)", &NamespaceTable::canonical()).is_err());
+    }
+
+    /// A small wiki: A links to B and C, D redirects to B and E links to D,
+    /// F and G transclude Template:T, and Category:X holds A, Category:Y and File:P.png, Category:Y holds H.
+    fn sample_wiki() -> MemoryBackend {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_page("B", "")
+            .add_page("C", "")
+            .add_link("A", "B")
+            .add_link("A", "C")
+            .add_redirect("D", "B")
+            .add_link("E", "D")
+            .add_page("Template:T", "")
+            .add_template("F", "Template:T")
+            .add_template("G", "Template:T")
+            .add_category("A", "Category:X")
+            .add_category("Category:Y", "Category:X")
+            .add_category("File:P.png", "Category:X")
+            .add_category("H", "Category:Y")
+            .add_page("Foo/1", "")
+            .add_page("Foo/2", "")
+            .add_page("Foobar", "");
+        wiki
+    }
+
+    #[tokio::test]
+    async fn incat_lists_members() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "incat(\"Category:X\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "Category:Y", "File:P.png"]).await);
+        let result = solve_on(&wiki, "incat(\"Category:X\").depth(1).ns(0)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "H"]).await);
+        let result = solve_on(&wiki, "incat(\"B\")", &config(-1)).await;
+        assert!(matches!(result, Err(SolveError::NotCategory)));
+    }
+
+    #[tokio::test]
+    async fn linkto_lists_backlinks() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "linkto(\"B\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "D", "E"]).await);
+        let result = solve_on(&wiki, "linkto(\"B\").direct()", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "D"]).await);
+        let result = solve_on(&wiki, "linkto(\"B\").noredir()", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "E"]).await);
+    }
+
+    #[tokio::test]
+    async fn link_lists_links() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "link(\"A\", \"E\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["B", "C", "D"]).await);
+    }
+
+    #[tokio::test]
+    async fn embed_lists_transclusions() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "embed(\"Template:T\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["F", "G"]).await);
+    }
+
+    #[tokio::test]
+    async fn template_parameters_are_filtered() {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_page("Template:Infobox person", "")
+            .add_page("A", "{{infobox person|name=A}}")
+            .add_page("B", "{{Infobox_person|name=B|image=b.png}}")
+            .add_page("C", "{{:Template:Infobox person|image=}}")
+            .add_page("D", "{{Other|image=}} {{Infobox person|image=d.png}}");
+        for page in ["A", "B", "C", "D"] {
+            wiki.add_template(page, "Template:Infobox person");
+        }
+        let result = solve_on(&wiki, "embed(\"Template:Infobox person\").noparam(\"image\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "C"]).await);
+        let result = solve_on(&wiki, "embed(\"Template:Infobox person\").param(\"name\", \"^B$\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["B"]).await);
+        // the limit of the instruction also bounds the pages whose calls are read
+        let result = solve_on(&wiki, "embed(\"Template:Infobox person\").noparam(\"image\").limit(4)", &config(2)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "C"]).await);
+    }
+
+    #[tokio::test]
+    async fn page_generators_list_related_pages() {
+        let mut wiki = sample_wiki();
+        wiki.add_image("A", "File:P.png").add_image("E", "File:Q.png").add_image("Template:T", "File:P.png").add_category("B", "Category:Z");
+        let cases: &[(&str, &[&str])] = &[
+            ("categories(\"A\", \"H\")", &["Category:X", "Category:Y"]),
+            ("categories(\"Category:Y\").ns(0)", &[]),
+            ("categories(\"D\")", &[]),
+            ("categories(\"D\").resolve()", &["Category:Z"]),
+            ("templates(\"F\", \"G\")", &["Template:T"]),
+            ("images(\"A\", \"E\")", &["File:P.png", "File:Q.png"]),
+            ("fileusage(\"File:P.png\")", &["A", "Template:T"]),
+            ("fileusage(\"File:P.png\").ns(0)", &["A"]),
+            ("redirects(\"B\", \"C\")", &["D"]),
+            ("subpages(\"Foo\")", &["Foo/1", "Foo/2"]),
+            ("subpages(\"Foo/1\")", &[]),
+            ("templates(images(\"A\"))", &[]),
+        ];
+        for (src, expected) in cases {
+            let result = solve_on(&wiki, src, &config(-1)).await.unwrap();
+            assert_eq!(result, titles(&wiki, expected).await, "{}", src);
+        }
+    }
+
+    #[tokio::test]
+    async fn page_generators_apply_limit_and_fanout() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "categories(\"A\", \"H\").limit(1)", &config(-1)).await.unwrap();
+        assert_eq!(result.len(), 1);
+        let strict = SolverConfig { querylimit: -1, fanout: 1, parallelism: 1 };
+        for src in ["categories(\"A\", \"H\")", "templates(\"F\", \"G\")", "redirects(\"B\", \"C\")", "subpages(\"Foo\", \"Foobar\")"] {
+            assert!(matches!(solve_on(&wiki, src, &strict).await, Err(SolveError::TooManyPages(2, 1))), "{}", src);
+        }
+    }
+
+    #[tokio::test]
+    async fn prefix_lists_pages() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "prefix(\"Foo\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["Foo/1", "Foo/2", "Foobar"]).await);
+        let result = solve_on(&wiki, "prefix(\"Foo/\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["Foo/1", "Foo/2"]).await);
+    }
+
+    #[tokio::test]
+    async fn toggle_maps_to_talk_pages() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "toggle(\"A\", \"Talk:B\", \"Category:X\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["Talk:A", "B", "Category talk:X"]).await);
+    }
+
+    #[tokio::test]
+    async fn set_operations() {
+        let wiki = sample_wiki();
+        let result = solve_on(&wiki, "link(\"A\") & linkto(\"D\")", &config(-1)).await.unwrap();
+        assert!(result.is_empty());
+        let result = solve_on(&wiki, "link(\"A\") + embed(\"Template:T\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["B", "C", "F", "G"]).await);
+        let result = solve_on(&wiki, "linkto(\"B\") - link(\"E\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "E"]).await);
+        let result = solve_on(&wiki, "linkto(\"B\") ^ (\"A\", \"C\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["C", "D", "E"]).await);
+        let result = solve_on(&wiki, "linkto(\"B\") & (\"A\", \"C\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A"]).await);
+    }
+
+    #[tokio::test]
+    async fn search_finds_title_or_content() {
+        let mut wiki = sample_wiki();
+        wiki.add_page("Bar", "some foo text").add_page("Talk:Foo", "");
+        let result = solve_on(&wiki, "search(\"foo\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["Foo/1", "Foo/2", "Foobar", "Bar"]).await);
+    }
+
+    #[tokio::test]
+    async fn missing_report_is_an_error() {
+        let wiki = sample_wiki();
+        assert!(solve_on(&wiki, "special(\"Lonelypages\")", &config(-1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn special_page_reports_are_listed() {
+        let mut wiki = MemoryBackend::new();
+        // more pages in other namespaces than the API lists at once
+        let mut report: Vec<String> = (0..600).map(|idx| format!("User:U{}", idx)).collect();
+        report.extend(["A", "Talk:A", "B", "C"].map(String::from));
+        wiki.add_report("Lonelypages", &report.iter().map(|p| p.as_str()).collect::<Vec<&str>>());
+        let result = solve_on(&wiki, "special(\"Lonelypages\").ns(0, 1)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "Talk:A", "B", "C"]).await);
+        // the limit counts the pages left after the namespace filter
+        let result = solve_on(&wiki, "special(\"Lonelypages\").ns(0).limit(2)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "B"]).await);
+        assert_eq!(solve_on(&wiki, "special(\"Lonelypages\")", &config(10)).await.unwrap().len(), 10);
+    }
+
+    fn wiki_with_props() -> MemoryBackend {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_page("B", "")
+            .add_page_prop("A", "disambiguation", "")
+            .add_page_prop("Talk:A", "disambiguation", "")
+            .add_page_prop("C", "wikibase_item", "Q1")
+            .add_page_prop("D", "wikibase_item", "Q2")
+            .add_page_prop("D", "disambiguation", "");
+        wiki
+    }
+
+    #[tokio::test]
+    async fn pages_with_prop_are_listed() {
+        let wiki = wiki_with_props();
+        let result = solve_on(&wiki, "pageswithprop(\"disambiguation\")", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["A", "D", "Talk:A"]).await);
+        let result = solve_on(&wiki, "pageswithprop(\"disambiguation\").ns(1)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["Talk:A"]).await);
+        // the limit counts the pages left after the namespace filter
+        let result = solve_on(&wiki, "pageswithprop(\"disambiguation\").ns(1).limit(1)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["Talk:A"]).await);
+        assert!(solve_on(&wiki, "pageswithprop(\"nonexistent\")", &config(-1)).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn pages_are_filtered_by_props() {
+        let wiki = wiki_with_props();
+        let pages = "page(\"A\", \"B\", \"C\", \"D\", \"Missing\")";
+        let cases: [(&str, &[&str]); 4] = [
+            (".wikidata(has=true)", &["C", "D"]),
+            (".wikidata(has=false)", &["A", "B"]),
+            (".withoutprop(\"disambiguation\")", &["B", "C"]),
+            (".withoutprop(\"disambiguation\").wikidata(has=true)", &["C"]),
+        ];
+        for (constraints, expected) in cases {
+            let src = format!("{}{}", pages, constraints);
+            assert_eq!(solve_on(&wiki, &src, &config(-1)).await.unwrap(), titles(&wiki, expected).await, "{}", src);
         }
     }
 
-    let result = get_set_1(&reg, &query.1)?;
-    Ok(result.clone())
+    fn edited_wiki() -> MemoryBackend {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_edit("D", "Bob", "2019-12-01T00:00:00Z")
+            .add_edit("A", "Alice", "2020-01-01T00:00:00Z")
+            .add_edit("D", "Alice", "2020-01-01T12:00:00Z")
+            .add_edit("B", "Alice", "2020-01-02T00:00:00Z")
+            .add_edit("B", "Bob", "2020-01-03T00:00:00Z")
+            .add_edit("Talk:A", "Alice", "2020-01-04T00:00:00Z");
+        // more edits of one page than the API lists at once
+        for _ in 0..600 {
+            wiki.add_edit("C", "Alice", "2020-02-01T00:00:00Z");
+        }
+        wiki
+    }
+
+    #[tokio::test]
+    async fn user_contributions_are_listed() {
+        let wiki = edited_wiki();
+        let cases: [(&str, &[&str]); 6] = [
+            ("contribs(\"Alice\")", &["A", "B", "C", "D", "Talk:A"]),
+            ("created_by(\"User:Alice\")", &["A", "B", "C", "Talk:A"]),
+            ("lasteditor(\"Alice\")", &["A", "C", "D", "Talk:A"]),
+            ("contribs(\"Alice\", ns=Talk)", &["Talk:A"]),
+            ("contribs(\"Alice\", since=\"2020-01-01T06:00:00Z\", until=\"2020-01-03\")", &["B", "D"]),
+            ("contribs(\"Bob\")", &["B", "D"]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(solve_on(&wiki, src, &config(-1)).await.unwrap(), titles(&wiki, expected).await, "{}", src);
+        }
+    }
+
+    #[tokio::test]
+    async fn user_contributions_limit_counts_pages() {
+        let wiki = edited_wiki();
+        // the 600 edits of C count as one page, the next page is on the next batch of edits
+        let result = solve_on(&wiki, "contribs(\"Alice\").limit(2)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["C", "Talk:A"]).await);
+        let result = solve_on(&wiki, "contribs(\"Alice\")", &config(3)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["B", "C", "Talk:A"]).await);
+    }
+
+    #[tokio::test]
+    async fn recent_changes_are_listed() {
+        let wiki = edited_wiki();
+        let cases: [(&str, &[&str]); 4] = [
+            ("recentlychanged()", &["A", "B", "C", "D", "Talk:A"]),
+            ("newpages(since=\"2020-01-01\")", &["A", "B", "C", "Talk:A"]),
+            ("recentlychanged(ns=0, since=\"2020-01-01T06:00:00Z\", until=\"2020-01-03\")", &["B", "D"]),
+            ("newpages(ns=Talk)", &["Talk:A"]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(solve_on(&wiki, src, &config(-1)).await.unwrap(), titles(&wiki, expected).await, "{}", src);
+        }
+        // the 600 edits of C count as one page, another page comes from the next batch of changes, which a generator lists in no set order
+        let result = solve_on(&wiki, "recentlychanged().limit(2)", &config(-1)).await.unwrap();
+        assert_eq!(result.len(), 2);
+        assert!(result.contains(&Title::new("C", 0)));
+    }
+
+    #[tokio::test]
+    async fn log_events_are_listed() {
+        let mut wiki = MemoryBackend::new();
+        wiki.add_log_entry("delete/delete", "D", "2020-01-01T00:00:00Z")
+            .add_log_entry("move/move", "A", "2020-01-02T00:00:00Z")
+            .add_log_entry("move/move_redir", "Talk:A", "2020-01-03T00:00:00Z")
+            .add_log_entry("move/move", "User:U", "2020-01-04T00:00:00Z");
+        // more entries of one page than the API lists at once
+        for _ in 0..600 {
+            wiki.add_log_entry("move/move", "B", "2020-02-01T00:00:00Z");
+        }
+        let cases: [(&str, &[&str]); 4] = [
+            ("log(\"move\")", &["A", "B", "Talk:A", "User:U"]),
+            ("log(\"move/move\", until=\"2020-01-05\")", &["A", "User:U"]),
+            ("log(\"delete\")", &["D"]),
+            ("log(\"move\", ns=0..1, since=\"2020-01-02T12:00:00Z\")", &["B", "Talk:A"]),
+        ];
+        for (src, expected) in cases {
+            assert_eq!(solve_on(&wiki, src, &config(-1)).await.unwrap(), titles(&wiki, expected).await, "{}", src);
+        }
+        // the 600 entries of B count as one page, and the limit counts the pages left after the namespace filter
+        let result = solve_on(&wiki, "log(\"move\", ns=0..1).limit(2)", &config(-1)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["B", "Talk:A"]).await);
+        let result = solve_on(&wiki, "log(\"move\")", &config(3)).await.unwrap();
+        assert_eq!(result, titles(&wiki, &["B", "Talk:A", "User:U"]).await);
+    }
+
+    #[tokio::test]
+    async fn fanout_is_checked() {
+        let wiki = sample_wiki();
+        let query = parse_with_namespaces("linkto(\"A\", \"B\", \"C\")", &NamespaceTable::canonical()).unwrap();
+        let config = SolverConfig { querylimit: -1, fanout: 2, parallelism: 1 };
+        assert!(matches!(solve_api(&wiki, &query, &config).await, Err(SolveError::TooManyPages(3, 2))));
+    }
+
+    /// A SQLite database loaded with `testdata/replica.sql`, in a file of its own
+    async fn sample_replica(name: &str) -> DBService {
+        let path = std::env::temp_dir().join(format!("plbot-replica-{}-{}.sqlite", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        sqlx::any::install_default_drivers();
+        let pool = sqlx::AnyPool::connect(&url).await.unwrap();
+        sqlx::raw_sql(include_str!("testdata/replica.sql")).execute(&pool).await.unwrap();
+        pool.close().await;
+        DBService::connect(&url).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn db_solver_agrees_with_api_solver() {
+        let wiki = sample_wiki();
+        let db = sample_replica("agree").await;
+        for src in [
+            "incat(\"Category:X\")",
+            "incat(\"Category:X\").ns(0)",
+            "incat(\"Category:X\").depth(1).ns(0)",
+            "incat(\"Category:X\").depth(-1)",
+            "linkto(\"B\")",
+            "linkto(\"B\").direct()",
+            "linkto(\"B\").noredir()",
+            "linkto(\"B\").onlyredir()",
+            "linkto(\"B\", \"C\").ns(0)",
+            "link(\"A\", \"E\")",
+            "link(\"E\").resolve()",
+            "embed(\"Template:T\")",
+            "embed(\"Template:T\").ns(1)",
+            "prefix(\"Foo\")",
+            "prefix(\"Foo/\").noredir()",
+            "subpages(\"Foo\")",
+            "link(\"A\") + embed(\"Template:T\") - linkto(\"C\")",
+            "toggle(linkto(\"B\")) ^ (\"Talk:A\", \"C\")",
+        ] {
+            let query = parse_with_namespaces(src, &NamespaceTable::canonical()).unwrap();
+            let from_api = solve_api(&wiki, &query, &config(-1)).await.unwrap();
+            let from_db = solve_db(&wiki, &db, &query, &config(-1)).await.unwrap();
+            assert!(!from_api.is_empty() || src.contains("ns(1)"), "{} should find pages", src);
+            assert_eq!(from_api, from_db, "{}", src);
+        }
+    }
+
+    #[tokio::test]
+    async fn db_solver_applies_the_limit() {
+        let wiki = sample_wiki();
+        let db = sample_replica("limit").await;
+        let query = parse_with_namespaces("linkto(\"B\", \"C\", \"D\").limit(2)", &NamespaceTable::canonical()).unwrap();
+        assert_eq!(solve_db(&wiki, &db, &query, &config(-1)).await.unwrap().len(), 2);
+        let query = parse_with_namespaces("incat(\"B\")", &NamespaceTable::canonical()).unwrap();
+        assert!(matches!(solve_db(&wiki, &db, &query, &config(-1)).await, Err(SolveError::NotCategory)));
+    }
+
+    #[tokio::test]
+    async fn limit_applies_to_the_whole_result() {
+        let wiki = categorized_wiki();
+        let result = solve_on(&wiki, "incat(\"Category:A\", \"Category:B\", \"Category:C\").limit(4)", &config(-1)).await.unwrap();
+        assert_eq!(result.len(), 4);
+        let result = solve_on(&wiki, "incat(\"Category:A\", \"Category:B\", \"Category:C\")", &config(5)).await.unwrap();
+        assert_eq!(result.len(), 5);
+        let result = solve_on(&wiki, "incat(\"Category:A\", \"Category:B\", \"Category:C\")", &config(-1)).await.unwrap();
+        assert_eq!(result.len(), 9);
+    }
 }
@@ -0,0 +1,207 @@
+//! A small wikitext parser that finds template calls and their parameters.
+//!
+//! It understands nested templates, links (whose pipes do not separate parameters), template parameters
+//! such as `{{{1}}}`, comments and `<nowiki>`. It does not expand anything, so a parameter value is its raw wikitext.
+
+/// One template call, such as `{{X|a|b=c}}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TemplateCall {
+    /// The name as written, trimmed
+    pub name: String,
+    /// Named parameters and numbered positional ones, in the order they appear.
+    /// Named values are trimmed, positional values are kept as they are, like MediaWiki does.
+    pub params: Vec<(String, String)>,
+}
+
+impl TemplateCall {
+    /// Returns the value of a parameter. If it is given several times, the last one wins
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.iter().rev().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+enum Frame {
+    // `start`: where the content starts, after `{{`
+    // `seps`: top-level pipes, `eqs`: the first top-level `=` of each part
+    Template { start: usize, seps: Vec<usize>, eqs: Vec<Option<usize>> },
+    Link,
+    Param,
+}
+
+/// Finds every template call in `text`, including the calls nested in parameters of other calls.
+/// Calls that are never closed are left out.
+pub(crate) fn parse_template_calls(text: &str) -> Vec<TemplateCall> {
+    let text = strip_unparsed(text);
+    let bytes = text.as_bytes();
+    let mut calls: Vec<TemplateCall> = Vec::new();
+    let mut stack: Vec<Frame> = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let rest = &bytes[pos..];
+        if rest.starts_with(b"{{{") {
+            stack.push(Frame::Param);
+            pos += 3;
+        } else if rest.starts_with(b"{{") {
+            stack.push(Frame::Template { start: pos + 2, seps: Vec::new(), eqs: vec![None] });
+            pos += 2;
+        } else if rest.starts_with(b"[[") {
+            stack.push(Frame::Link);
+            pos += 2;
+        } else if rest.starts_with(b"}}}") && matches!(stack.last(), Some(Frame::Param)) {
+            stack.pop();
+            pos += 3;
+        } else if rest.starts_with(b"}}") && matches!(stack.last(), Some(Frame::Template { .. })) {
+            if let Some(Frame::Template { start, seps, eqs }) = stack.pop() {
+                calls.push(build_call(&text, start, &seps, &eqs, pos));
+            }
+            pos += 2;
+        } else if rest.starts_with(b"]]") && matches!(stack.last(), Some(Frame::Link)) {
+            stack.pop();
+            pos += 2;
+        } else {
+            if let Some(Frame::Template { seps, eqs, .. }) = stack.last_mut() {
+                match bytes[pos] {
+                    b'|' => {
+                        seps.push(pos);
+                        eqs.push(None);
+                    },
+                    b'=' => {
+                        let eq = eqs.last_mut().unwrap();
+                        if eq.is_none() {
+                            *eq = Some(pos);
+                        }
+                    },
+                    _ => {},
+                }
+            }
+            pos += 1;
+        }
+    }
+    calls
+}
+
+/// Splits the content of a call, from `start` to `end`, at its top-level pipes
+fn build_call(text: &str, start: usize, seps: &[usize], eqs: &[Option<usize>], end: usize) -> TemplateCall {
+    let mut bounds: Vec<(usize, usize)> = Vec::with_capacity(seps.len() + 1);
+    let mut from = start;
+    for sep in seps {
+        bounds.push((from, *sep));
+        from = sep + 1;
+    }
+    bounds.push((from, end));
+
+    let name = text[bounds[0].0..bounds[0].1].trim().to_owned();
+    let mut params: Vec<(String, String)> = Vec::new();
+    let mut positional = 0;
+    for ((from, to), eq) in bounds.iter().zip(eqs).skip(1) {
+        if let Some(eq) = eq {
+            params.push((text[*from..*eq].trim().to_owned(), text[eq + 1..*to].trim().to_owned()));
+        } else {
+            positional += 1;
+            params.push((positional.to_string(), text[*from..*to].to_owned()));
+        }
+    }
+    TemplateCall { name, params }
+}
+
+/// Removes comments and the content of `<nowiki>` tags, which are never parsed
+fn strip_unparsed(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        let comment = rest.find("<!--");
+        let nowiki = rest.find("<nowiki>");
+        let (idx, close) = match (comment, nowiki) {
+            (Some(c), Some(n)) if n < c => (n, "</nowiki>"),
+            (Some(c), _) => (c, "-->"),
+            (None, Some(n)) => (n, "</nowiki>"),
+            (None, None) => break,
+        };
+        result.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+        match rest.find(close) {
+            Some(end) => rest = &rest[end + close.len()..],
+            // an unclosed comment runs to the end of the page
+            None => {
+                rest = "";
+                break;
+            },
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(name: &str, params: &[(&str, &str)]) -> TemplateCall {
+        TemplateCall { name: name.to_owned(), params: params.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect() }
+    }
+
+    #[test]
+    fn named_and_positional_parameters() {
+        let calls = parse_template_calls("{{X| a | b = c |d|1=e}}");
+        assert_eq!(calls, vec![call("X", &[("1", " a "), ("b", "c"), ("2", "d"), ("1", "e")])]);
+        // a named parameter overrides a positional one given before it
+        assert_eq!(calls[0].get("1"), Some("e"));
+        assert_eq!(calls[0].get("2"), Some("d"));
+        assert_eq!(calls[0].get("3"), None);
+    }
+
+    #[test]
+    fn only_the_first_equal_sign_names_a_parameter() {
+        let calls = parse_template_calls("{{X|a=b=c|=d|e=}}");
+        assert_eq!(calls, vec![call("X", &[("a", "b=c"), ("", "d"), ("e", "")])]);
+    }
+
+    #[test]
+    fn nested_templates_are_found() {
+        let calls = parse_template_calls("{{Outer|a={{Inner|1|b=2}}|c}}");
+        assert_eq!(calls, vec![
+            call("Inner", &[("1", "1"), ("b", "2")]),
+            call("Outer", &[("a", "{{Inner|1|b=2}}"), ("1", "c")]),
+        ]);
+    }
+
+    #[test]
+    fn links_do_not_split_parameters() {
+        let calls = parse_template_calls("{{X|[[A|B]]|c=[[File:F.png|thumb|alt=D]]}}");
+        assert_eq!(calls, vec![call("X", &[("1", "[[A|B]]"), ("c", "[[File:F.png|thumb|alt=D]]")])]);
+    }
+
+    #[test]
+    fn template_parameters_are_not_calls() {
+        let calls = parse_template_calls("{{X|a={{{1|default}}}|{{{b}}}}}");
+        assert_eq!(calls, vec![call("X", &[("a", "{{{1|default}}}"), ("1", "{{{b}}}")])]);
+    }
+
+    #[test]
+    fn unparsed_text_is_skipped() {
+        let calls = parse_template_calls("<nowiki>{{A}}</nowiki>{{B|<!-- a=1 | -->b=2<nowiki>|</nowiki>}}");
+        assert_eq!(calls, vec![call("B", &[("b", "2")])]);
+    }
+
+    #[test]
+    fn unclosed_calls_are_left_out() {
+        assert_eq!(parse_template_calls("{{A|{{B}}"), vec![call("B", &[])]);
+        assert!(parse_template_calls("}}{{").is_empty());
+    }
+
+    #[test]
+    fn build_call_splits_at_pipes() {
+        let text = "{{ Name |x| k = v }}";
+        let call = build_call(text, 2, &[8, 10], &[None, None, Some(14)], 18);
+        assert_eq!(call, TemplateCall { name: String::from("Name"), params: vec![(String::from("1"), String::from("x")), (String::from("k"), String::from("v"))] });
+    }
+
+    #[test]
+    fn strip_unparsed_removes_comments_and_nowiki() {
+        assert_eq!(strip_unparsed("a<!-- b -->c<nowiki>d</nowiki>e"), "ace");
+        assert_eq!(strip_unparsed("a<nowiki><!--</nowiki>b-->c"), "ab-->c");
+        // an unclosed comment runs to the end of the page
+        assert_eq!(strip_unparsed("a<!-- b"), "a");
+        assert_eq!(strip_unparsed("plain"), "plain");
+    }
+}
@@ -0,0 +1,80 @@
+-- The tables of the replica database that the solver reads, trimmed to the columns it uses.
+-- The pages mirror `sample_wiki` in the solver tests, so that both solvers can be compared.
+
+CREATE TABLE page (
+    page_id INTEGER PRIMARY KEY,
+    page_namespace INTEGER NOT NULL,
+    page_title TEXT NOT NULL,
+    page_is_redirect INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE linktarget (
+    lt_id INTEGER PRIMARY KEY,
+    lt_namespace INTEGER NOT NULL,
+    lt_title TEXT NOT NULL
+);
+
+CREATE TABLE pagelinks (
+    pl_from INTEGER NOT NULL,
+    pl_target_id INTEGER NOT NULL
+);
+
+CREATE TABLE templatelinks (
+    tl_from INTEGER NOT NULL,
+    tl_target_id INTEGER NOT NULL
+);
+
+CREATE TABLE categorylinks (
+    cl_from INTEGER NOT NULL,
+    cl_to TEXT NOT NULL,
+    cl_type TEXT NOT NULL
+);
+
+CREATE TABLE redirect (
+    rd_from INTEGER PRIMARY KEY,
+    rd_namespace INTEGER NOT NULL,
+    rd_title TEXT NOT NULL,
+    rd_interwiki TEXT
+);
+
+INSERT INTO page (page_id, page_namespace, page_title, page_is_redirect) VALUES
+    (1, 0, 'A', 0),
+    (2, 0, 'B', 0),
+    (3, 0, 'C', 0),
+    (4, 0, 'D', 1),
+    (5, 0, 'E', 0),
+    (6, 0, 'F', 0),
+    (7, 0, 'G', 0),
+    (8, 10, 'T', 0),
+    (9, 14, 'Y', 0),
+    (10, 6, 'P.png', 0),
+    (11, 0, 'H', 0),
+    (12, 0, 'Foo/1', 0),
+    (13, 0, 'Foo/2', 0),
+    (14, 0, 'Foobar', 0);
+
+INSERT INTO linktarget (lt_id, lt_namespace, lt_title) VALUES
+    (1, 0, 'B'),
+    (2, 0, 'C'),
+    (3, 0, 'D'),
+    (4, 10, 'T');
+
+-- A links to B and C, the redirect D links to its target B, E links to D
+INSERT INTO pagelinks (pl_from, pl_target_id) VALUES
+    (1, 1),
+    (1, 2),
+    (4, 1),
+    (5, 3);
+
+INSERT INTO templatelinks (tl_from, tl_target_id) VALUES
+    (6, 4),
+    (7, 4);
+
+INSERT INTO categorylinks (cl_from, cl_to, cl_type) VALUES
+    (1, 'X', 'page'),
+    (9, 'X', 'subcat'),
+    (10, 'X', 'file'),
+    (11, 'Y', 'page');
+
+INSERT INTO redirect (rd_from, rd_namespace, rd_title, rd_interwiki) VALUES
+    (4, 0, 'B', '');
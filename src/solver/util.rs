@@ -1,6 +1,7 @@
 use super::error::SolveError;
 
-use crate::parser::ir::RegID;
+use crate::backend::WikiBackend;
+use crate::parser::ir::{RegID, TitleMatch};
 
 use std::collections::HashSet;
 use mediawiki::title::Title;
@@ -26,9 +27,113 @@ pub(crate) fn get_set_2<'a>(reg: &'a Register, reg_id1: &'a RegID, reg_id2: &'a
     }
 }
 
+/// Copies out the registers an instruction reads, so that it can run without borrowing the whole register file
+pub(crate) fn get_inputs(reg: &Register, reg_ids: &[RegID]) -> Result<Register, SolveError> {
+    reg_ids.iter().map(|reg_id| {
+        reg.get(reg_id).map(|s| (*reg_id, s.clone())).ok_or(SolveError::UnknownIntermediateValue)
+    }).collect()
+}
+
 pub(crate) fn concat_params<T>(v: &HashSet<T>) -> String 
 where
     T: ToString,
 {
     v.iter().map(|f| T::to_string(f)).collect::<Vec<String>>().join("|")
 }
+
+/// Rejects a unary instruction that would run on more pages than `fanout` allows
+pub(crate) fn check_fanout(set: &HashSet<Title>, fanout: i64) -> Result<(), SolveError> {
+    check_fanout_count(set.len(), fanout)
+}
+
+/// Rejects sending one request per page for more pages than `fanout` allows
+pub(crate) fn check_fanout_count(count: usize, fanout: i64) -> Result<(), SolveError> {
+    if fanout >= 0 && count as i64 > fanout {
+        Err(SolveError::TooManyPages(count, fanout))
+    } else {
+        Ok(())
+    }
+}
+
+/// The limit left for the next request of an instruction, once `found` pages are collected.
+/// A negative `limit` means no limit. Returns `None` once the limit is reached.
+pub(crate) fn remaining_limit(limit: i64, found: usize) -> Option<i64> {
+    if limit < 0 {
+        Some(limit)
+    } else if (found as i64) < limit {
+        Some(limit - found as i64)
+    } else {
+        None
+    }
+}
+
+/// Keeps the titles satisfying every pattern of `patterns`
+pub(crate) async fn filter_titles(backend: &dyn WikiBackend, set: HashSet<Title>, patterns: &[TitleMatch]) -> Result<HashSet<Title>, SolveError> {
+    let mut result: HashSet<Title> = HashSet::new();
+    for title in set {
+        let full = if patterns.iter().any(|m| m.full) {
+            backend.full_pretty(&title).await?.unwrap_or_else(|| title.pretty().to_owned())
+        } else {
+            String::new()
+        };
+        if patterns.iter().all(|m| m.keeps(if m.full { &full } else { title.pretty() })) {
+            result.insert(title);
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use crate::backend::MemoryBackend;
+    use crate::parser::ir::Pattern;
+
+    fn title_match(regex: &str, full: bool, negated: bool) -> TitleMatch {
+        TitleMatch { regex: Pattern(Regex::new(regex).unwrap()), full, negated }
+    }
+
+    async fn filtered(patterns: &[TitleMatch]) -> Vec<String> {
+        let wiki = MemoryBackend::new();
+        let mut set = HashSet::new();
+        for name in ["Foo/Sandbox", "User:Foo/Sandbox", "User talk:Foo/Sandbox", "Sandbox", "User:Bar"] {
+            set.insert(wiki.title_new_from_full(name).await.unwrap());
+        }
+        let mut names = Vec::new();
+        for title in filter_titles(&wiki, set, patterns).await.unwrap() {
+            names.push(wiki.full_pretty(&title).await.unwrap().unwrap());
+        }
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn titles_are_matched_without_namespace() {
+        assert_eq!(filtered(&[title_match("^Foo/", false, false)]).await, ["Foo/Sandbox", "User talk:Foo/Sandbox", "User:Foo/Sandbox"]);
+        assert!(filtered(&[title_match("^User", false, false)]).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn full_titles_are_matched_with_namespace() {
+        assert_eq!(filtered(&[title_match("^User:.*/Sandbox$", true, false)]).await, ["User:Foo/Sandbox"]);
+        // the main namespace has no prefix
+        assert_eq!(filtered(&[title_match("^Sandbox$", true, false)]).await, ["Sandbox"]);
+    }
+
+    #[tokio::test]
+    async fn every_pattern_must_hold() {
+        let patterns = [title_match("Sandbox$", false, false), title_match("^User", true, true)];
+        assert_eq!(filtered(&patterns).await, ["Foo/Sandbox", "Sandbox"]);
+        assert_eq!(filtered(&[]).await.len(), 5);
+    }
+
+    #[test]
+    fn remaining_limit_counts_down() {
+        assert_eq!(remaining_limit(5, 0), Some(5));
+        assert_eq!(remaining_limit(5, 3), Some(2));
+        assert_eq!(remaining_limit(5, 5), None);
+        assert_eq!(remaining_limit(0, 0), None);
+        assert_eq!(remaining_limit(-1, 1000), Some(-1));
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug, serde::Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum APIAssertType {
@@ -6,12 +8,12 @@ pub enum APIAssertType {
     Bot,
 }
 
-impl ToString for APIAssertType {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for APIAssertType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match *self {
-            Self::Anon => String::from("anon"),
-            Self::User => String::from("user"),
-            Self::Bot => String::from("bot"),
+            Self::Anon => f.write_str("anon"),
+            Self::User => f.write_str("user"),
+            Self::Bot => f.write_str("bot"),
         }
     }
 }
@@ -22,7 +24,7 @@ pub struct LoginCredential {
     pub password: String,
 }
 
-#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+#[derive(PartialEq, Clone, Debug, serde::Deserialize)]
 pub struct SiteProfile {
     pub api: String,
     pub db: Option<String>,
@@ -30,4 +32,65 @@ pub struct SiteProfile {
     pub assert: Option<APIAssertType>,
     pub botflag: bool,
     pub config: String,
+    pub cache: Option<CacheProfile>,
+    #[serde(default)]
+    pub throttle: ThrottleProfile,
+}
+
+impl SiteProfile {
+    /// Reads every profile of a site information file. Profiles that cannot be parsed are left out
+    pub fn all_from_value(sites: &serde_json::Value) -> HashMap<String, SiteProfile> {
+        sites.as_object()
+            .map(|sites| sites.iter()
+                .filter_map(|(name, profile)| serde_json::from_value(profile.clone()).ok().map(|profile| (name.to_owned(), profile)))
+                .collect())
+            .unwrap_or_default()
+    }
+}
+
+/// `ttl`: seconds a response is kept, by module name (`categorymembers`, `links`, ...), or `default` for modules not listed.
+/// Responses of modules without a TTL are not cached.
+/// `path`: directory where responses are also written, so that they survive restarts
+/// `maxfiles`: most responses kept in `path`
+#[derive(PartialEq, Eq, Clone, Debug, serde::Deserialize)]
+pub struct CacheProfile {
+    #[serde(default)]
+    pub ttl: HashMap<String, u64>,
+    pub path: Option<String>,
+    pub maxfiles: Option<usize>,
+}
+
+/// Separate budgets for read and edit requests
+#[derive(PartialEq, Clone, Debug, serde::Deserialize)]
+pub struct ThrottleProfile {
+    #[serde(default = "default_read_budget")]
+    pub read: ThrottleBudget,
+    #[serde(default = "default_edit_budget")]
+    pub edit: ThrottleBudget,
+}
+
+impl Default for ThrottleProfile {
+    fn default() -> Self {
+        ThrottleProfile {
+            read: default_read_budget(),
+            edit: default_edit_budget(),
+        }
+    }
+}
+
+/// `rps`: the maximum number of requests started per second.
+/// `concurrency`: the maximum number of requests in flight.
+/// An absent limit is unlimited
+#[derive(PartialEq, Clone, Debug, serde::Deserialize)]
+pub struct ThrottleBudget {
+    pub rps: Option<f64>,
+    pub concurrency: Option<usize>,
+}
+
+fn default_read_budget() -> ThrottleBudget {
+    ThrottleBudget { rps: None, concurrency: Some(4) }
+}
+
+fn default_edit_budget() -> ThrottleBudget {
+    ThrottleBudget { rps: None, concurrency: Some(1) }
 }